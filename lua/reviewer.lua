@@ -0,0 +1,38 @@
+-- Lua entry points of vim-reviewer, for mappings that shouldn't have to go
+-- through Ex command parsing.
+local M = {}
+
+-- Start a review comment on the visual selection (or the last one, when
+-- called from normal mode), e.g.
+--
+--   vim.keymap.set('x', '<leader>rc', require('reviewer').comment_selection)
+--
+-- `opts` may prefill the comment's `severity` (e.g. 'nit') and `labels`.
+function M.comment_selection(opts)
+  opts = opts or {}
+  local mode = vim.fn.mode()
+  if mode == 'v' or mode == 'V' or mode == '\22' then
+    -- The '< and '> marks are only set once visual mode is left.
+    vim.api.nvim_feedkeys(vim.api.nvim_replace_termcodes('<Esc>', true, false, true), 'nx', false)
+  end
+  local start_line = vim.fn.line("'<")
+  local end_line = vim.fn.line("'>")
+  if start_line == 0 or end_line == 0 then
+    vim.notify('No visual selection to comment on.', vim.log.levels.ERROR)
+    return
+  end
+  vim.fn.ReviewerCommentRange(start_line, end_line, {
+    severity = opts.severity,
+    labels = opts.labels,
+  })
+end
+
+-- The review status of the current buffer's file (e.g. `● 3 comments ✓
+-- viewed`), or an empty string, for statusline plugins like lualine:
+--
+--   sections = { lualine_c = { 'filename', require('reviewer').status } }
+function M.status()
+  return vim.b.reviewer_status or ''
+end
+
+return M
@@ -1 +1,7 @@
 from .offline_pr_review import *
+from .github import *
+from .diff import *
+from .mbox import *
+from .jobs import *
+from .codeowners import *
+from .crypto import *
@@ -0,0 +1,75 @@
+from dataclasses import dataclass
+import re
+from typing import List, Optional, Pattern
+
+# Where GitHub (and GitLab) look for the CODEOWNERS file, in order.
+CODEOWNERS_LOCATIONS = [".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS", ".gitlab/CODEOWNERS"]
+
+
+@dataclass
+class OwnershipRule:
+    pattern: str
+    owners: List[str]
+    regex: Pattern
+
+
+def pattern_regex(pattern: str) -> Pattern:
+    """
+    Translate a CODEOWNERS pattern, which follows the `.gitignore` rules, to
+    a regular expression matching repository paths.
+    """
+    anchored = pattern.startswith("/") or "/" in pattern.rstrip("/")
+    pattern = pattern.lstrip("/")
+    directory = pattern.endswith("/")
+    pattern = pattern.rstrip("/")
+    regex = ""
+    index = 0
+    while index < len(pattern):
+        if pattern.startswith("**/", index):
+            regex += "(?:.*/)?"
+            index += 3
+        elif pattern.startswith("**", index):
+            regex += ".*"
+            index += 2
+        elif pattern[index] == "*":
+            regex += "[^/]*"
+            index += 1
+        elif pattern[index] == "?":
+            regex += "[^/]"
+            index += 1
+        else:
+            regex += re.escape(pattern[index])
+            index += 1
+    prefix = "^" if anchored else "^(?:.*/)?"
+    if directory:
+        suffix = "/.*$"
+    elif pattern.endswith("*") and not pattern.endswith("**"):
+        # `docs/*` only owns the files directly in `docs`.
+        suffix = "$"
+    else:
+        # A pattern naming a directory owns everything below it.
+        suffix = "(?:/.*)?$"
+    return re.compile(prefix + regex + suffix)
+
+
+def parse_codeowners(text: str) -> List[OwnershipRule]:
+    rules = []
+    for line in text.split("\n"):
+        line = line.split(" #", 1)[0].strip()
+        if not line or line.startswith("#") or line.startswith("["):
+            # Comments, and GitLab's `[Section]` headers.
+            continue
+        pattern, *owners = line.split()
+        rules.append(OwnershipRule(pattern, owners, pattern_regex(pattern)))
+    return rules
+
+
+def owners_of(rules: List[OwnershipRule], path: str) -> Optional[List[str]]:
+    """
+    Return the owners of `path`, or None if no rule matches it. As on GitHub,
+    the last matching rule wins; it may list no owners at all.
+    """
+    for rule in reversed(rules):
+        if rule.regex.match(path):
+            return rule.owners
+    return None
@@ -0,0 +1,132 @@
+import base64
+import os
+import subprocess
+import threading
+from typing import Optional
+
+# First line of encrypted review files, followed by the base64 of the nonce
+# and the XChaCha20-Poly1305 ciphertext.
+ENCRYPTED_HEADER = "vim-reviewer-encrypted-v1"
+NONCE_SIZE = 24
+KEY_SIZE = 32
+KEYRING_SERVICE = "vim-reviewer"
+KEYRING_USERNAME = "review-encryption-key"
+
+# The key, once looked up, so that the key command isn't run on every save.
+ENCRYPTION_KEY: Optional[bytes] = None
+ENCRYPTION_KEY_LOCK = threading.Lock()
+
+
+def aead():
+    """
+    Return PyNaCl's low-level bindings, which provide XChaCha20-Poly1305.
+
+    Raises `ValueError` if PyNaCl isn't installed.
+    """
+    try:
+        import nacl.bindings
+    except ImportError:
+        raise ValueError("Encrypting reviews needs PyNaCl: pip install 'offline-pr-review[encryption]'.")
+    return nacl.bindings
+
+
+def decode_key(encoded: str) -> bytes:
+    try:
+        key = base64.b64decode(encoded.strip(), validate=True)
+    except ValueError:
+        key = b""
+    if len(key) != KEY_SIZE:
+        raise ValueError(f"The review encryption key must be {KEY_SIZE} bytes, base64-encoded.")
+    return key
+
+
+def keyring_module():
+    try:
+        import keyring
+    except ImportError:
+        return None
+    return keyring
+
+
+def encryption_key() -> bytes:
+    """
+    Return the key review files are encrypted with: the output of the
+    `GH_REVIEW_ENCRYPTION_KEY_COMMAND` shell command (e.g. `pass show
+    reviewer`) if set, or the key stored in the system keyring.
+
+    Raises `ValueError` if there is no key.
+    """
+    global ENCRYPTION_KEY
+    with ENCRYPTION_KEY_LOCK:
+        if ENCRYPTION_KEY is not None:
+            return ENCRYPTION_KEY
+        command = os.getenv("GH_REVIEW_ENCRYPTION_KEY_COMMAND")
+        if command:
+            result = subprocess.run(command, shell=True, stdout=subprocess.PIPE, stderr=subprocess.PIPE)
+            if result.returncode != 0:
+                raise ValueError(
+                    f"The review encryption key command failed: {result.stderr.decode('utf-8', 'replace').strip()}"
+                )
+            encoded = result.stdout.decode("utf-8")
+        else:
+            keyring = keyring_module()
+            encoded = keyring.get_password(KEYRING_SERVICE, KEYRING_USERNAME) if keyring is not None else None
+            if encoded is None:
+                raise ValueError(
+                    "No review encryption key: set GH_REVIEW_ENCRYPTION_KEY_COMMAND, "
+                    "or store one in the system keyring with :ReviewerEncryption on."
+                )
+        ENCRYPTION_KEY = decode_key(encoded)
+        return ENCRYPTION_KEY
+
+
+def store_new_encryption_key() -> bytes:
+    """
+    Generate a key and store it in the system keyring.
+
+    Raises `ValueError` if the keyring package isn't installed.
+    """
+    global ENCRYPTION_KEY
+    keyring = keyring_module()
+    if keyring is None:
+        raise ValueError("Storing the key in the system keyring needs keyring: pip install keyring.")
+    key = os.urandom(KEY_SIZE)
+    keyring.set_password(KEYRING_SERVICE, KEYRING_USERNAME, base64.b64encode(key).decode("ascii"))
+    with ENCRYPTION_KEY_LOCK:
+        ENCRYPTION_KEY = key
+    return key
+
+
+def is_encrypted(text: str) -> bool:
+    return text.startswith(ENCRYPTED_HEADER + "\n")
+
+
+def encrypt_text(text: str) -> str:
+    bindings = aead()
+    nonce = os.urandom(NONCE_SIZE)
+    ciphertext = bindings.crypto_aead_xchacha20poly1305_ietf_encrypt(
+        text.encode("utf-8"), ENCRYPTED_HEADER.encode("ascii"), nonce, encryption_key()
+    )
+    return f"{ENCRYPTED_HEADER}\n{base64.b64encode(nonce + ciphertext).decode('ascii')}\n"
+
+
+def decrypt_text(text: str) -> str:
+    """
+    Decrypt the contents of an encrypted review file.
+
+    Raises `ValueError` if it can't be decrypted, e.g. with the wrong key.
+    """
+    bindings = aead()
+    try:
+        data = base64.b64decode(text[len(ENCRYPTED_HEADER) + 1:].strip(), validate=True)
+    except ValueError:
+        raise ValueError("The encrypted review file is corrupted.")
+    nonce, ciphertext = data[:NONCE_SIZE], data[NONCE_SIZE:]
+    try:
+        plaintext = bindings.crypto_aead_xchacha20poly1305_ietf_decrypt(
+            ciphertext, ENCRYPTED_HEADER.encode("ascii"), nonce, encryption_key()
+        )
+    except Exception:
+        # PyNaCl raises its own CryptoError.
+        raise ValueError("Could not decrypt the review file; the encryption key may be wrong.")
+    return plaintext.decode("utf-8")
@@ -0,0 +1,201 @@
+from dataclasses import dataclass, field
+import re
+from typing import List, Optional, Tuple
+
+HUNK_HEADER_PATTERN = re.compile(r"^@@ -(\d+)(?:,(\d+))? \+(\d+)(?:,(\d+))? @@(.*)$")
+
+
+@dataclass
+class DiffLine:
+    """
+    A single line of a hunk: context (` `), an addition (`+`) or a deletion (`-`).
+    """
+    kind: str
+    text: str
+    old_line: Optional[int]
+    new_line: Optional[int]
+    # 1-based line number of this line in the text the diff was parsed from.
+    source_line: int
+
+    def line_on_side(self, side: str) -> Optional[int]:
+        return self.new_line if side == "RIGHT" else self.old_line
+
+
+@dataclass
+class Hunk:
+    old_start: int
+    old_count: int
+    new_start: int
+    new_count: int
+    header: str
+    lines: List[DiffLine] = field(default_factory=list)
+
+
+@dataclass
+class FileDiff:
+    # None when the file is added (old path) or deleted (new path).
+    old_path: Optional[str]
+    new_path: Optional[str]
+    hunks: List[Hunk] = field(default_factory=list)
+
+    @property
+    def path(self) -> str:
+        return self.new_path or self.old_path
+
+    def find_line(self, line: int, side: str) -> Optional[DiffLine]:
+        """
+        Return the diff line showing `line` of the file on the given side, or
+        None if that line is not part of any hunk.
+        """
+        for hunk in self.hunks:
+            for diff_line in hunk.lines:
+                if diff_line.line_on_side(side) == line:
+                    return diff_line
+        return None
+
+
+    def infer_side(self, start: int, texts: List[str]) -> Optional[Tuple[str, int, int]]:
+        """
+        Return the side and the range of lines to comment on for the lines
+        of the new version of the file starting at `start`, whose contents
+        are `texts`.
+
+        When those aren't the lines the diff shows there (e.g. the buffer
+        holds the base version of the file), fall back to the closest run of
+        lines of the old version with the same contents that includes a
+        deleted line, which only exists on the LEFT side. The range is mapped
+        as a whole, so its start never ends up after its end. Returns None if
+        neither matches.
+        """
+        right = [self.find_line(start + offset, "RIGHT") for offset in range(len(texts))]
+        if all(diff_line is not None and diff_line.text == text for diff_line, text in zip(right, texts)):
+            return "RIGHT", start, start + len(texts) - 1
+        candidates = []
+        for hunk in self.hunks:
+            old_lines = [diff_line for diff_line in hunk.lines if diff_line.old_line is not None]
+            for first in range(len(old_lines) - len(texts) + 1):
+                run = old_lines[first:first + len(texts)]
+                if [diff_line.text for diff_line in run] == texts and any(line.kind == "-" for line in run):
+                    candidates.append(run[0].old_line)
+        if not candidates:
+            return None
+        closest = min(candidates, key=lambda old_line: abs(old_line - start))
+        return "LEFT", closest, closest + len(texts) - 1
+
+
+def strip_path_prefix(path: str) -> Optional[str]:
+    if path == "/dev/null":
+        return None
+    if path.startswith(("a/", "b/")):
+        return path[2:]
+    return path
+
+
+def hunk_ranges(patch: str) -> List[Tuple[int, int, int, int]]:
+    """
+    Return the `(old_start, old_count, new_start, new_count)` of each hunk of
+    `patch`, from the hunk headers alone, without parsing the diff lines.
+    """
+    ranges = []
+    for line in patch.split("\n"):
+        match = HUNK_HEADER_PATTERN.match(line) if line.startswith("@@") else None
+        if match is None:
+            continue
+        old_start, old_count, new_start, new_count, _ = match.groups()
+        ranges.append((
+            int(old_start),
+            int(old_count) if old_count is not None else 1,
+            int(new_start),
+            int(new_count) if new_count is not None else 1,
+        ))
+    return ranges
+
+
+def in_hunk_ranges(ranges: List[Tuple[int, int, int, int]], line: int, side: str) -> bool:
+    """
+    Whether `line` of the given side of the file is shown by one of the
+    hunks, as returned by `hunk_ranges`.
+    """
+    for old_start, old_count, new_start, new_count in ranges:
+        start, count = (new_start, new_count) if side == "RIGHT" else (old_start, old_count)
+        if start <= line < start + count:
+            return True
+    return False
+
+
+def parse_unified_diff(text: str, first_line: int = 1) -> List[FileDiff]:
+    """
+    Parse a (git-style) unified diff into its files and hunks.
+
+    `first_line` is the line number of the first line of `text` in the
+    document it was taken from, so that `DiffLine.source_line` refers to
+    that document.
+    """
+    file_diffs: List[FileDiff] = []
+    current_file: Optional[FileDiff] = None
+    current_hunk: Optional[Hunk] = None
+    old_line = new_line = 0
+
+    for index, line in enumerate(text.split("\n")):
+        source_line = first_line + index
+        if line.startswith("diff --git "):
+            current_file = FileDiff(None, None)
+            file_diffs.append(current_file)
+            current_hunk = None
+            # Fill in the paths from the header, in case the diff has no
+            # `---`/`+++` lines (e.g. pure renames or binary files).
+            paths = line[len("diff --git "):].split(" b/", 1)
+            if len(paths) == 2:
+                current_file.old_path = strip_path_prefix(paths[0])
+                current_file.new_path = paths[1]
+        elif line.startswith("--- ") and current_hunk is None:
+            if current_file is None:
+                current_file = FileDiff(None, None)
+                file_diffs.append(current_file)
+            current_file.old_path = strip_path_prefix(line[4:].split("\t")[0])
+        elif line.startswith("+++ ") and current_hunk is None and current_file is not None:
+            current_file.new_path = strip_path_prefix(line[4:].split("\t")[0])
+        elif line.startswith("new file mode") and current_file is not None:
+            current_file.old_path = None
+        elif line.startswith("deleted file mode") and current_file is not None:
+            current_file.new_path = None
+        elif line.startswith("@@") and current_file is not None:
+            match = HUNK_HEADER_PATTERN.match(line)
+            if match is None:
+                continue
+            old_start, old_count, new_start, new_count, header = match.groups()
+            current_hunk = Hunk(
+                int(old_start),
+                int(old_count) if old_count is not None else 1,
+                int(new_start),
+                int(new_count) if new_count is not None else 1,
+                header.strip(),
+            )
+            current_file.hunks.append(current_hunk)
+            old_line, new_line = current_hunk.old_start, current_hunk.new_start
+        elif current_hunk is not None and line[:1] in ("", " ", "+", "-"):
+            # Some tools strip the trailing space of empty context lines.
+            kind = line[:1] or " "
+            diff_line = DiffLine(
+                kind,
+                line[1:],
+                old_line if kind != "+" else None,
+                new_line if kind != "-" else None,
+                source_line,
+            )
+            current_hunk.lines.append(diff_line)
+            if kind != "+":
+                old_line += 1
+            if kind != "-":
+                new_line += 1
+            if (
+                old_line >= current_hunk.old_start + current_hunk.old_count
+                and new_line >= current_hunk.new_start + current_hunk.new_count
+            ):
+                current_hunk = None
+        elif line.startswith("\\"):
+            # "\ No newline at end of file"
+            continue
+        else:
+            current_hunk = None
+    return file_diffs
@@ -0,0 +1,869 @@
+from collections import OrderedDict
+from datetime import datetime, timezone
+import hashlib
+import logging
+import os
+import subprocess
+import threading
+import time
+from typing import Any, Callable, Dict, List, Optional, Tuple
+from urllib.parse import quote
+
+import requests
+
+from .crypto import KEYRING_SERVICE, keyring_module
+from .jobs import current_job
+
+logger = logging.getLogger(__name__)
+
+API_URL = "https://api.github.com"
+GRAPHQL_URL = f"{API_URL}/graphql"
+WEB_URL = "https://github.com"
+
+
+def set_host(host: str):
+    """
+    Talk to the GitHub instance at `host`: github.com, or a GitHub
+    Enterprise Server, whose APIs live under `/api`.
+    """
+    global API_URL, GRAPHQL_URL, WEB_URL, INSTALLATION_TOKEN
+    if WEB_URL != f"https://{host}":
+        INSTALLATION_TOKEN = None
+    if host == "github.com":
+        API_URL = "https://api.github.com"
+        GRAPHQL_URL = f"{API_URL}/graphql"
+    else:
+        API_URL = f"https://{host}/api/v3"
+        GRAPHQL_URL = f"https://{host}/api/graphql"
+    WEB_URL = f"https://{host}"
+
+
+# Where the personal access token comes from when `GH_REVIEW_API_TOKEN` isn't
+# set: "keyring" or "command" (see `set_token_source`), or None.
+TOKEN_SOURCE: Optional[str] = None
+TOKEN_COMMAND: Optional[str] = None
+# A plaintext token from the repository's configuration, only used when
+# `GH_REVIEW_ALLOW_REPO_TOKEN` allows it (see `set_repo_token`).
+REPO_TOKEN: Optional[str] = None
+# The tokens printed by token commands and when, so that they aren't run on
+# every request. They are run again after `COMMAND_TOKEN_TTL` seconds, or
+# as soon as GitHub rejects a token, since the token may have been rotated.
+COMMAND_TOKENS: Dict[str, Tuple[str, float]] = {}
+COMMAND_TOKENS_LOCK = threading.Lock()
+COMMAND_TOKEN_TTL = 600
+
+
+def set_token_source(source: Optional[str], command: Optional[str] = None):
+    """
+    Read the personal access token from the system keyring (`"keyring"`),
+    from the output of the shell command `command` (`"command"`, e.g.
+    `gh auth token`), or only from `GH_REVIEW_API_TOKEN` (None).
+    """
+    global TOKEN_SOURCE, TOKEN_COMMAND
+    TOKEN_SOURCE = source
+    TOKEN_COMMAND = command
+
+
+def set_repo_token(token: Optional[str]):
+    """
+    Fall back to `token`, read from a repository configuration file, when
+    there is no token from the environment or the token source.
+    """
+    global REPO_TOKEN
+    REPO_TOKEN = token
+
+
+def token_keyring():
+    """
+    Raises `ValueError` if the keyring package isn't installed.
+    """
+    keyring = keyring_module()
+    if keyring is None:
+        raise ValueError("Keeping the API token in the system keyring needs keyring: pip install keyring.")
+    return keyring
+
+
+def keyring_token_username() -> str:
+    # Tokens are per GitHub instance.
+    return f"api-token@{WEB_URL[len('https://'):]}"
+
+
+def store_keyring_token(token: str):
+    """
+    Store the personal access token for the current GitHub instance in the
+    system keyring.
+
+    Raises `ValueError` if the keyring package isn't installed.
+    """
+    token_keyring().set_password(KEYRING_SERVICE, keyring_token_username(), token)
+
+
+def configured_token() -> Optional[str]:
+    """
+    Return the personal access token from the configured token source, or
+    None if there is none.
+
+    Raises `ValueError` if the token command fails or the keyring package
+    isn't installed.
+    """
+    if TOKEN_SOURCE == "keyring":
+        return token_keyring().get_password(KEYRING_SERVICE, keyring_token_username())
+    if TOKEN_SOURCE != "command" or not TOKEN_COMMAND:
+        return None
+    with COMMAND_TOKENS_LOCK:
+        cached = COMMAND_TOKENS.get(TOKEN_COMMAND)
+        if cached is None or time.time() - cached[1] > COMMAND_TOKEN_TTL:
+            result = subprocess.run(TOKEN_COMMAND, shell=True, stdout=subprocess.PIPE, stderr=subprocess.PIPE)
+            if result.returncode != 0:
+                raise ValueError(f"The API token command failed: {result.stderr.decode('utf-8', 'replace').strip()}")
+            COMMAND_TOKENS[TOKEN_COMMAND] = (result.stdout.decode("utf-8").strip(), time.time())
+        return COMMAND_TOKENS[TOKEN_COMMAND][0] or None
+
+
+class TokenError(requests.RequestException, ValueError):
+    """
+    The API token can't be had, e.g. because the token command failed or
+    PyJWT isn't installed for the GitHub App. It is a `RequestException`, so
+    that callers that handle failed requests report it too.
+    """
+
+
+def get_api_token() -> Optional[str]:
+    """
+    Return the GitHub API token used to talk to GitHub on the user's behalf:
+    the personal access token in `GH_REVIEW_API_TOKEN`, from the configured
+    token source or from the repository's configuration (if allowed) or,
+    when a GitHub App is configured instead, a token of its installation.
+
+    Raises `TokenError` if the token source or the GitHub App is
+    misconfigured, and `requests.HTTPError` if the installation token can't
+    be fetched.
+    """
+    try:
+        token = os.getenv("GH_REVIEW_API_TOKEN") or configured_token() or REPO_TOKEN
+        if token or not os.getenv("GH_REVIEW_APP_ID"):
+            return token
+        return get_installation_token()
+    except ValueError as e:
+        raise TokenError(str(e)) from e
+
+
+def api_headers(token: Optional[str], scheme: str = "token") -> dict:
+    headers = {"Accept": "application/vnd.github+json"}
+    if token:
+        headers["Authorization"] = f"{scheme} {token}"
+    return headers
+
+
+# The installation token of the GitHub App and when it expires. Tokens last an
+# hour; they are renewed a few minutes early so that requests in flight don't
+# fail.
+INSTALLATION_TOKEN: Optional[Tuple[str, float]] = None
+INSTALLATION_TOKEN_MARGIN = 300
+INSTALLATION_TOKEN_LOCK = threading.Lock()
+
+
+def app_jwt() -> str:
+    """
+    Mint the short-lived JWT that authenticates as the GitHub App whose ID is
+    in `GH_REVIEW_APP_ID`, signed with the private key at
+    `GH_REVIEW_APP_PRIVATE_KEY_FILE`.
+
+    Raises `ValueError` if the key can't be read or PyJWT isn't installed.
+    """
+    try:
+        import jwt
+    except ImportError:
+        raise ValueError("Authenticating as a GitHub App needs PyJWT: pip install 'PyJWT[crypto]'.")
+    key_file = os.getenv("GH_REVIEW_APP_PRIVATE_KEY_FILE")
+    if not key_file:
+        raise ValueError("GH_REVIEW_APP_PRIVATE_KEY_FILE is not set.")
+    try:
+        with open(os.path.expanduser(key_file), encoding="utf-8") as f:
+            private_key = f.read()
+    except OSError as e:
+        raise ValueError(f"Can't read the GitHub App private key: {e}")
+    now = int(time.time())
+    # Backdated to allow for clock drift; GitHub accepts at most 10 minutes.
+    claims = {"iat": now - 60, "exp": now + 540, "iss": os.getenv("GH_REVIEW_APP_ID")}
+    return jwt.encode(claims, private_key, algorithm="RS256")
+
+
+def get_installation_id(app_token: str) -> str:
+    """
+    Return the installation of the GitHub App to use: the one in
+    `GH_REVIEW_APP_INSTALLATION_ID`, or the only one there is.
+    """
+    installation_id = os.getenv("GH_REVIEW_APP_INSTALLATION_ID")
+    if installation_id:
+        return installation_id
+    response = api_request("GET", f"{API_URL}/app/installations", headers=api_headers(app_token, "Bearer"))
+    raise_for_response(response)
+    installations = response.json()
+    if len(installations) != 1:
+        accounts = ", ".join(f"{i['account']['login']} ({i['id']})" for i in installations) or "none"
+        raise ValueError(
+            f"Set GH_REVIEW_APP_INSTALLATION_ID to choose an installation of the GitHub App: {accounts}."
+        )
+    return str(installations[0]["id"])
+
+
+def get_installation_token() -> str:
+    """
+    Return a token of the GitHub App installation, fetching a new one when the
+    cached one is about to expire.
+    """
+    global INSTALLATION_TOKEN
+    with INSTALLATION_TOKEN_LOCK:
+        if INSTALLATION_TOKEN is not None and INSTALLATION_TOKEN[1] - time.time() > INSTALLATION_TOKEN_MARGIN:
+            return INSTALLATION_TOKEN[0]
+        app_token = app_jwt()
+        installation_id = get_installation_id(app_token)
+        response = api_request(
+            "POST",
+            f"{API_URL}/app/installations/{installation_id}/access_tokens",
+            headers=api_headers(app_token, "Bearer"),
+        )
+        raise_for_response(response)
+        token = response.json()
+        expires_at = datetime.strptime(token["expires_at"], "%Y-%m-%dT%H:%M:%SZ").replace(tzinfo=timezone.utc)
+        INSTALLATION_TOKEN = (token["token"], expires_at.timestamp())
+        return token["token"]
+
+
+# The rate limits GitHub last reported by resource (`core`, `graphql`,
+# `search`, ...), as in the `resources` of `GET /rate_limit`: `limit`,
+# `remaining`, `used` and `reset` (a timestamp). Every response updates the
+# resource it counted against.
+RATE_LIMITS: Dict[str, dict] = {}
+# Rate-limited requests are retried, up to `RATE_LIMIT_RETRIES` times, when
+# the limit resets within `RATE_LIMIT_MAX_WAIT` seconds; otherwise they fail
+# right away rather than hang.
+RATE_LIMIT_MAX_WAIT = 60
+RATE_LIMIT_RETRIES = 3
+
+
+def record_rate_limit(response: requests.Response):
+    headers = response.headers
+    if "X-RateLimit-Remaining" not in headers:
+        return
+    RATE_LIMITS[headers.get("X-RateLimit-Resource", "core")] = {
+        "limit": int(headers.get("X-RateLimit-Limit", 0)),
+        "remaining": int(headers["X-RateLimit-Remaining"]),
+        "used": int(headers.get("X-RateLimit-Used", 0)),
+        "reset": int(headers.get("X-RateLimit-Reset", 0)),
+    }
+
+
+def rate_limit_wait(response: requests.Response) -> Optional[float]:
+    """
+    Return how many seconds to wait before retrying the request `response`
+    answers, if GitHub rate limited it (primary or secondary limit), or None
+    if it didn't.
+    """
+    if response.status_code not in (403, 429):
+        return None
+    if "Retry-After" in response.headers:
+        return float(response.headers["Retry-After"])
+    if response.headers.get("X-RateLimit-Remaining") == "0":
+        return max(int(response.headers.get("X-RateLimit-Reset", 0)) - time.time(), 0) + 1
+    # Secondary limits don't always say how long; GitHub asks to wait at
+    # least a minute.
+    return RATE_LIMIT_MAX_WAIT if response.status_code == 429 else None
+
+
+def rate_limit_resource(url: str) -> str:
+    if url == GRAPHQL_URL:
+        return "graphql"
+    return "search" if url.startswith(f"{API_URL}/search/") else "core"
+
+
+def api_request(method: str, url: str, **kwargs) -> requests.Response:
+    """
+    Send a request to the GitHub API. In a background job, wait out rate
+    limits that reset soon (see `RATE_LIMIT_MAX_WAIT`): before sending when
+    the last response said the limit is used up, and by retrying when GitHub
+    rejects it. Elsewhere, i.e. on the editor's thread, never wait.
+
+    Returns the final response, rate limited or not; use
+    `raise_for_response` to turn failures into exceptions.
+    """
+    may_wait = current_job() is not None
+    known = RATE_LIMITS.get(rate_limit_resource(url))
+    if (
+        may_wait
+        and known is not None
+        and known["remaining"] == 0
+        and 0 < known["reset"] - time.time() <= RATE_LIMIT_MAX_WAIT
+    ):
+        time.sleep(known["reset"] - time.time() + 1)
+    for attempt in range(RATE_LIMIT_RETRIES + 1):
+        response = requests.request(method, url, **kwargs)
+        logger.debug("%s %s: %d %s", method, response.url, response.status_code, response.reason)
+        record_rate_limit(response)
+        if response.status_code == 401:
+            # The token may have been rotated; print it again next time.
+            with COMMAND_TOKENS_LOCK:
+                COMMAND_TOKENS.clear()
+        wait = rate_limit_wait(response)
+        if wait is None or not may_wait or wait > RATE_LIMIT_MAX_WAIT or attempt == RATE_LIMIT_RETRIES:
+            return response
+        logger.info("Rate limited by GitHub; retrying in %.0f seconds.", wait)
+        time.sleep(wait)
+
+
+def raise_for_response(response: requests.Response):
+    """
+    Raise `requests.HTTPError` if `response` is an error, saying until when
+    if it's because of the rate limit.
+    """
+    if rate_limit_wait(response) is not None:
+        reset = response.headers.get("X-RateLimit-Reset")
+        until = f" until {datetime.fromtimestamp(int(reset)).strftime('%H:%M')}" if reset else ""
+        raise requests.HTTPError(
+            f"GitHub's API rate limit is used up{until}; see :ReviewerRateLimit.", response=response
+        )
+    response.raise_for_status()
+
+
+def get_rate_limits(token: Optional[str]) -> Dict[str, dict]:
+    """
+    Fetch the current rate limits of the token, by resource (see
+    `RATE_LIMITS`). This doesn't count against them.
+
+    Raises `requests.HTTPError` if the request fails, e.g. on a GitHub
+    Enterprise Server without rate limiting.
+    """
+    response = api_request("GET", f"{API_URL}/rate_limit", headers=api_headers(token))
+    raise_for_response(response)
+    resources = response.json()["resources"]
+    RATE_LIMITS.update(resources)
+    return resources
+
+
+# Responses of conditional requests by URL and token (responses depend on who
+# asks), with the validators (`ETag` and `Last-Modified`) to send the next time
+# and the link to the next page. GitHub answers `304 Not Modified` when nothing
+# changed, which doesn't count against the rate limit. Only the
+# `CONDITIONAL_RESPONSES_KEPT` most recently used responses are kept.
+CONDITIONAL_RESPONSES: "OrderedDict[Tuple[str, str], Tuple[dict, Any, Optional[str]]]" = OrderedDict()
+CONDITIONAL_RESPONSES_KEPT = 200
+CONDITIONAL_RESPONSES_LOCK = threading.Lock()
+
+
+def token_identity(token: Optional[str]) -> str:
+    """
+    Return a digest telling `token` apart from other tokens, to key cached
+    responses by without keeping the token itself around.
+    """
+    return hashlib.sha256((token or "").encode("utf-8")).hexdigest()
+
+
+def get_page(
+    url: str, token: Optional[str], params: Optional[dict] = None, conditional: bool = False
+) -> Tuple[Any, Optional[str]]:
+    """
+    GET the JSON at `url`, returning it along with the URL of the next page
+    of results, if any. With `conditional`, the previous response is reused
+    if GitHub reports that it hasn't changed; this is meant for endpoints
+    that are polled.
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    key = (requests.Request("GET", url, params=params).prepare().url, token_identity(token))
+    headers = api_headers(token)
+    cached = None
+    if conditional:
+        with CONDITIONAL_RESPONSES_LOCK:
+            cached = CONDITIONAL_RESPONSES.get(key)
+            if cached is not None:
+                CONDITIONAL_RESPONSES.move_to_end(key)
+    if cached is not None:
+        headers.update(cached[0])
+    response = api_request("GET", url, params=params, headers=headers)
+    if response.status_code == 304 and cached is not None:
+        return cached[1], cached[2]
+    raise_for_response(response)
+    result = response.json()
+    next_url = response.links.get("next", {}).get("url")
+    if conditional:
+        validators = {}
+        if "ETag" in response.headers:
+            validators["If-None-Match"] = response.headers["ETag"]
+        if "Last-Modified" in response.headers:
+            validators["If-Modified-Since"] = response.headers["Last-Modified"]
+        if validators:
+            with CONDITIONAL_RESPONSES_LOCK:
+                CONDITIONAL_RESPONSES[key] = (validators, result, next_url)
+                CONDITIONAL_RESPONSES.move_to_end(key)
+                while len(CONDITIONAL_RESPONSES) > CONDITIONAL_RESPONSES_KEPT:
+                    CONDITIONAL_RESPONSES.popitem(last=False)
+    return result, next_url
+
+
+def conditional_get(url: str, token: Optional[str], params: Optional[dict] = None) -> Any:
+    """
+    GET the JSON at `url`, reusing the previous response if it hasn't changed.
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    return get_page(url, token, params, conditional=True)[0]
+
+
+def get_all_pages(
+    url: str,
+    token: Optional[str],
+    params: Optional[dict] = None,
+    items_key: Optional[str] = None,
+    conditional: bool = False,
+    on_page: Optional[Callable[[List[Any]], None]] = None,
+) -> List[Any]:
+    """
+    GET a paginated collection, following the `Link` headers until the last
+    page. For endpoints that wrap the collection in an object (e.g. search
+    results), `items_key` is the key holding it.
+
+    `on_page` is called with the items of each page as it arrives, so that
+    big collections can be processed (and progress reported) incrementally.
+
+    Raises `requests.HTTPError` if any request fails.
+    """
+    items: List[Any] = []
+    params = {"per_page": 100, **(params or {})}
+    next_url: Optional[str] = url
+    while next_url is not None:
+        page, next_url = get_page(next_url, token, params, conditional)
+        page_items = page[items_key] if items_key else page
+        items += page_items
+        if on_page is not None:
+            on_page(page_items)
+        # The links to the next pages already include the query parameters.
+        params = None
+    return items
+
+
+def encode_path(path: str) -> str:
+    """
+    Percent-encode a path in the repository for use in a URL, e.g. for names
+    with spaces, `#` or non-ASCII characters, keeping the `/` separators.
+    """
+    return quote(path, safe="/")
+
+
+def pull_request_url(owner: str, repo: str, pr_number: int) -> str:
+    return f"{API_URL}/repos/{owner}/{repo}/pulls/{pr_number}"
+
+
+def pull_request_web_url(owner: str, repo: str, pr_number: int) -> str:
+    return f"{WEB_URL}/{owner}/{repo}/pull/{pr_number}"
+
+
+def blob_web_url(owner: str, repo: str, ref: str, path: str) -> str:
+    return f"{WEB_URL}/{owner}/{repo}/blob/{encode_path(ref)}/{encode_path(path)}"
+
+
+def get_pull_request(owner: str, repo: str, pr_number: int, token: Optional[str]) -> dict:
+    """
+    Fetch the pull request metadata (base and head refs, title, state, ...).
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    return conditional_get(pull_request_url(owner, repo, pr_number), token)
+
+
+def get_pull_request_if_changed(
+    owner: str, repo: str, pr_number: int, token: Optional[str], etag: Optional[str]
+) -> Tuple[Optional[dict], Optional[str]]:
+    """
+    Fetch the pull request metadata unless it is unchanged since the
+    response with the ETag `etag`, returning it (None if unchanged) along
+    with its ETag. Unchanged responses don't count against the rate limit.
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    headers = api_headers(token)
+    if etag:
+        headers["If-None-Match"] = etag
+    response = api_request("GET", pull_request_url(owner, repo, pr_number), headers=headers)
+    if response.status_code == 304:
+        return None, etag
+    raise_for_response(response)
+    return response.json(), response.headers.get("ETag")
+
+
+def update_pull_request(owner: str, repo: str, pr_number: int, token: Optional[str], **fields) -> dict:
+    """
+    Update the pull request metadata, e.g. `state="closed"`.
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    response = api_request(
+        "PATCH",
+        pull_request_url(owner, repo, pr_number),
+        json=fields,
+        headers=api_headers(token),
+    )
+    raise_for_response(response)
+    return response.json()
+
+
+def create_gist(files: Dict[str, str], description: str, public: bool, token: Optional[str]) -> str:
+    """
+    Create a gist holding `files` (contents by file name), returning its URL.
+    Creating gists needs a token with the `gist` scope.
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    response = api_request(
+        "POST",
+        f"{API_URL}/gists",
+        json={
+            "description": description,
+            "public": public,
+            "files": {name: {"content": content} for name, content in files.items()},
+        },
+        headers=api_headers(token),
+    )
+    raise_for_response(response)
+    return response.json()["html_url"]
+
+
+def list_pull_requests(owner: str, repo: str, token: Optional[str], **filters: str) -> List[dict]:
+    """
+    Fetch the open pull requests of the repository matching `filters` (e.g.
+    `head="owner:branch"` or `base="branch"`).
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    return get_all_pages(f"{API_URL}/repos/{owner}/{repo}/pulls", token, {"state": "open", **filters})
+
+
+def request_reviewers(
+    owner: str, repo: str, pr_number: int, reviewers: List[str], team_reviewers: List[str], token: Optional[str]
+) -> dict:
+    """
+    Ask users (by login) and teams (by slug) to review the pull request. Users
+    who already reviewed it are asked to review it again.
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    response = api_request(
+        "POST",
+        f"{pull_request_url(owner, repo, pr_number)}/requested_reviewers",
+        json={"reviewers": reviewers, "team_reviewers": team_reviewers},
+        headers=api_headers(token),
+    )
+    raise_for_response(response)
+    return response.json()
+
+
+def get_pull_request_files(
+    owner: str, repo: str, pr_number: int, token: Optional[str], on_page: Optional[Callable[[List[dict]], None]] = None
+) -> List[dict]:
+    """
+    Fetch the list of files changed by the pull request, a page at a time.
+    `on_page` is called with the files of each page as it arrives.
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    return get_all_pages(f"{pull_request_url(owner, repo, pr_number)}/files", token, on_page=on_page)
+
+
+def get_pull_request_comments(owner: str, repo: str, pr_number: int, token: Optional[str]) -> List[dict]:
+    """
+    Fetch the review comments already posted on the pull request.
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    return get_all_pages(f"{pull_request_url(owner, repo, pr_number)}/comments", token)
+
+
+def graphql(query: str, variables: dict, token: Optional[str]) -> dict:
+    """
+    Run a GraphQL query or mutation, returning its `data`.
+
+    Raises `requests.HTTPError` if the request fails, including when GitHub
+    reports errors in the response body.
+    """
+    response = api_request(
+        "POST",
+        GRAPHQL_URL,
+        json={"query": query, "variables": variables},
+        headers=api_headers(token),
+    )
+    raise_for_response(response)
+    result = response.json()
+    if result.get("errors"):
+        raise requests.HTTPError(
+            "; ".join(error["message"] for error in result["errors"]), response=response
+        )
+    return result["data"]
+
+
+PULL_REQUEST_OVERVIEW_QUERY = """
+query($owner: String!, $repo: String!, $number: Int!,
+      $withFiles: Boolean!, $filesAfter: String, $withThreads: Boolean!, $threadsAfter: String) {
+  repository(owner: $owner, name: $repo) {
+    pullRequest(number: $number) {
+      id
+      headRefOid
+      files(first: 100, after: $filesAfter) @include(if: $withFiles) {
+        pageInfo { hasNextPage endCursor }
+        nodes { path changeType additions deletions viewerViewedState }
+      }
+      reviewThreads(first: 100, after: $threadsAfter) @include(if: $withThreads) {
+        pageInfo { hasNextPage endCursor }
+        nodes { path comments { totalCount } }
+      }
+    }
+  }
+}
+"""
+
+
+def get_pull_request_overview(owner: str, repo: str, pr_number: int, token: Optional[str]) -> dict:
+    """
+    Fetch the pull request's node ID and head, its changed files with their
+    viewed state, and its review threads in as few GraphQL queries as
+    possible (one, unless there are more than 100 files or threads).
+
+    Returns the `pullRequest` object, with all the files and threads.
+
+    Raises `requests.HTTPError` if the request fails, including when the
+    token can't be used with GraphQL.
+    """
+    variables = {
+        "owner": owner, "repo": repo, "number": pr_number,
+        "withFiles": True, "filesAfter": None, "withThreads": True, "threadsAfter": None,
+    }
+    overview = None
+    while variables["withFiles"] or variables["withThreads"]:
+        pull_request = graphql(PULL_REQUEST_OVERVIEW_QUERY, variables, token)["repository"]["pullRequest"]
+        if overview is None:
+            overview = {**pull_request, "files": {"nodes": []}, "reviewThreads": {"nodes": []}}
+        connections = (("files", "withFiles", "filesAfter"), ("reviewThreads", "withThreads", "threadsAfter"))
+        for connection, include, cursor in connections:
+            if not variables[include]:
+                continue
+            overview[connection]["nodes"] += pull_request[connection]["nodes"]
+            page_info = pull_request[connection]["pageInfo"]
+            variables[include] = page_info["hasNextPage"]
+            variables[cursor] = page_info["endCursor"]
+    return overview
+
+
+def set_file_viewed(pull_request_node_id: str, path: str, viewed: bool, token: Optional[str]):
+    """
+    Mark (or unmark) a file as viewed in the GitHub UI for the token's user.
+    """
+    mutation = "markFileAsViewed" if viewed else "unmarkFileAsViewed"
+    graphql(
+        f"""
+        mutation($pullRequestId: ID!, $path: String!) {{
+          {mutation}(input: {{pullRequestId: $pullRequestId, path: $path}}) {{
+            clientMutationId
+          }}
+        }}
+        """,
+        {"pullRequestId": pull_request_node_id, "path": path},
+        token,
+    )
+
+
+def get_file_contents(owner: str, repo: str, path: str, ref: str, token: Optional[str]) -> Optional[str]:
+    """
+    Fetch the contents of the file at `path` as of `ref`, or None if the file
+    does not exist at that ref.
+
+    Raises `requests.HTTPError` if the request fails for any other reason.
+    """
+    response = api_request(
+        "GET",
+        f"{API_URL}/repos/{owner}/{repo}/contents/{encode_path(path)}",
+        params={"ref": ref},
+        headers={**api_headers(token), "Accept": "application/vnd.github.raw"},
+    )
+    if response.status_code == 404:
+        return None
+    raise_for_response(response)
+    return response.text
+
+
+def get_comparison(owner: str, repo: str, base: str, head: str, token: Optional[str]) -> dict:
+    """
+    Fetch the comparison between two commits, including the changed files.
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    response = api_request(
+        "GET",
+        f"{API_URL}/repos/{owner}/{repo}/compare/{encode_path(base)}...{encode_path(head)}",
+        headers=api_headers(token),
+    )
+    raise_for_response(response)
+    return response.json()
+
+
+def get_commit(owner: str, repo: str, sha: str, token: Optional[str]) -> dict:
+    """
+    Fetch a commit, including the GitHub users it is attributed to.
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    response = api_request(
+        "GET",
+        f"{API_URL}/repos/{owner}/{repo}/commits/{sha}",
+        headers=api_headers(token),
+    )
+    raise_for_response(response)
+    return response.json()
+
+
+def get_pull_request_reviews(owner: str, repo: str, pr_number: int, token: Optional[str]) -> List[dict]:
+    """
+    Fetch the reviews submitted on the pull request.
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    return get_all_pages(f"{pull_request_url(owner, repo, pr_number)}/reviews", token, conditional=True)
+
+
+def search_review_requests(token: Optional[str]) -> List[dict]:
+    """
+    Fetch the open pull requests, across all repositories, where the token's
+    user is asked for a review.
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    return get_all_pages(
+        f"{API_URL}/search/issues",
+        token,
+        {"q": "is:open is:pr review-requested:@me archived:false", "sort": "created", "order": "asc"},
+        items_key="items",
+    )
+
+
+def list_labels(owner: str, repo: str, token: Optional[str]) -> List[str]:
+    """
+    Fetch the names of the labels defined in the repository.
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    return [label["name"] for label in get_all_pages(f"{API_URL}/repos/{owner}/{repo}/labels", token)]
+
+
+def add_labels(owner: str, repo: str, pr_number: int, labels: List[str], token: Optional[str]):
+    """
+    Add labels to the pull request.
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    response = api_request(
+        "POST",
+        f"{API_URL}/repos/{owner}/{repo}/issues/{pr_number}/labels",
+        json={"labels": labels},
+        headers=api_headers(token),
+    )
+    raise_for_response(response)
+
+
+def remove_label(owner: str, repo: str, pr_number: int, label: str, token: Optional[str]):
+    """
+    Remove a label from the pull request.
+
+    Raises `requests.HTTPError` if the request fails, e.g. if the pull request
+    doesn't have that label.
+    """
+    response = api_request(
+        "DELETE",
+        f"{API_URL}/repos/{owner}/{repo}/issues/{pr_number}/labels/{quote(label, safe='')}",
+        headers=api_headers(token),
+    )
+    raise_for_response(response)
+
+
+def get_combined_status(owner: str, repo: str, ref: str, token: Optional[str]) -> dict:
+    """
+    Fetch the combined commit status (from the statuses API) of `ref`.
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    response = api_request(
+        "GET",
+        f"{API_URL}/repos/{owner}/{repo}/commits/{quote(ref, safe='')}/status",
+        headers=api_headers(token),
+    )
+    raise_for_response(response)
+    return response.json()
+
+
+def get_check_runs(owner: str, repo: str, ref: str, token: Optional[str]) -> List[dict]:
+    """
+    Fetch the check runs (e.g. from GitHub Actions) of `ref`.
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    return get_all_pages(
+        f"{API_URL}/repos/{owner}/{repo}/commits/{quote(ref, safe='')}/check-runs", token, items_key="check_runs"
+    )
+
+
+def merge_pull_request(owner: str, repo: str, pr_number: int, method: str, sha: str, token: Optional[str]) -> dict:
+    """
+    Merge the pull request with the given method (`merge`, `squash` or
+    `rebase`), provided its head is still `sha`.
+
+    Raises `requests.HTTPError` if the request fails, e.g. if the pull request
+    is not mergeable or its head moved.
+    """
+    response = api_request(
+        "PUT",
+        f"{pull_request_url(owner, repo, pr_number)}/merge",
+        json={"merge_method": method, "sha": sha},
+        headers=api_headers(token),
+    )
+    raise_for_response(response)
+    return response.json()
+
+
+def list_collaborators(owner: str, repo: str, token: Optional[str]) -> List[str]:
+    """
+    Fetch the logins of the users who can be mentioned or asked for reviews
+    in the repository.
+
+    Listing collaborators requires push access, so fall back to the users
+    that issues can be assigned to otherwise.
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    try:
+        users = get_all_pages(f"{API_URL}/repos/{owner}/{repo}/collaborators", token)
+    except requests.HTTPError as e:
+        if e.response is None or e.response.status_code not in (403, 404):
+            raise
+        users = get_all_pages(f"{API_URL}/repos/{owner}/{repo}/assignees", token)
+    return [user["login"] for user in users]
+
+
+def get_authenticated_user(token: Optional[str]) -> str:
+    """
+    Fetch the login of the token's user.
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    response = api_request("GET", f"{API_URL}/user", headers=api_headers(token))
+    raise_for_response(response)
+    return response.json()["login"]
+
+
+def list_user_teams(token: Optional[str]) -> List[str]:
+    """
+    Fetch the teams of the token's user, as `org/team-slug`. This needs the
+    `read:org` scope.
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    return [
+        f"{team['organization']['login']}/{team['slug']}"
+        for team in get_all_pages(f"{API_URL}/user/teams", token)
+    ]
@@ -0,0 +1,161 @@
+from concurrent.futures import ThreadPoolExecutor
+from dataclasses import dataclass, field
+import threading
+import time
+from typing import Any, Callable, Dict, List, Optional
+
+JobWork = Callable[["Job"], Any]
+Dispatch = Callable[[Callable[[], None]], None]
+
+JOB_CONTEXT = threading.local()
+
+# How many finished (done, failed or cancelled) jobs `JobRunner` remembers for
+# `:ReviewerJobs`; older ones are forgotten as new jobs are submitted.
+FINISHED_JOBS_KEPT = 50
+FINISHED_STATUSES = ("done", "failed", "cancelled")
+
+
+@dataclass
+class Job:
+    """
+    A unit of background work, e.g. publishing a review or polling a PR.
+    """
+    id: int
+    name: str
+    # One of "pending", "running", "waiting" (between runs of a periodic
+    # job), "done", "failed" or "cancelled".
+    status: str = "pending"
+    # Seconds between runs for periodic jobs, None for one-off jobs.
+    interval: Optional[float] = None
+    runs: int = 0
+    error: Optional[str] = None
+    started_at: Optional[float] = None
+    finished_at: Optional[float] = None
+    cancel_event: threading.Event = field(default_factory=threading.Event)
+
+    @property
+    def cancelled(self) -> bool:
+        """
+        Whether the job was cancelled. Long-running work should check this
+        and stop early.
+        """
+        return self.cancel_event.is_set()
+
+    def describe(self) -> str:
+        details = [self.status]
+        if self.interval is not None:
+            details.append(f"every {self.interval:g}s, {self.runs} runs")
+        if self.started_at is not None and self.status == "running":
+            details.append(f"for {time.time() - self.started_at:.0f}s")
+        if self.error:
+            details.append(self.error)
+        return f"{self.id}: {self.name} ({', '.join(details)})"
+
+
+def current_job() -> Optional[Job]:
+    """
+    Return the job running on the current thread, or None outside of jobs
+    (e.g. on the editor's main thread).
+    """
+    return getattr(JOB_CONTEXT, "job", None)
+
+
+class JobRunner:
+    """
+    Runs jobs on a pool of background threads.
+
+    Jobs must not touch the editor; their results are handed to `on_done`
+    (or their exception to `on_error`) through `dispatch`, which runs the
+    callbacks on the main thread.
+    """
+
+    def __init__(self, dispatch: Dispatch, max_workers: int = 4):
+        self.dispatch = dispatch
+        self.executor = ThreadPoolExecutor(max_workers=max_workers, thread_name_prefix="vim-reviewer")
+        self.jobs: Dict[int, Job] = {}
+        self.next_id = 1
+        self.lock = threading.Lock()
+
+    def submit(
+        self,
+        name: str,
+        work: JobWork,
+        on_done: Optional[Callable[[Any], None]] = None,
+        on_error: Optional[Callable[[Exception], None]] = None,
+        interval: Optional[float] = None,
+    ) -> Job:
+        """
+        Run `work` in the background. With an `interval`, the job runs
+        periodically, starting `interval` seconds from now, until cancelled.
+        """
+        with self.lock:
+            job = Job(self.next_id, name, interval=interval)
+            self.next_id += 1
+            self.jobs[job.id] = job
+            self.prune()
+        if interval is None:
+            self.executor.submit(self.run, job, work, on_done, on_error)
+        else:
+            job.status = "waiting"
+            self.schedule(job, work, on_done, on_error)
+        return job
+
+    def schedule(self, job: Job, work: JobWork, on_done, on_error):
+        timer = threading.Timer(job.interval, lambda: self.executor.submit(self.run, job, work, on_done, on_error))
+        timer.daemon = True
+        timer.start()
+
+    def run(self, job: Job, work: JobWork, on_done, on_error):
+        if job.cancelled:
+            return
+        job.status = "running"
+        job.started_at = time.time()
+        job.runs += 1
+        JOB_CONTEXT.job = job
+        try:
+            result = work(job)
+        except Exception as e:
+            job.status = "failed" if job.interval is None else "waiting"
+            job.error = str(e)
+            if on_error is not None and not job.cancelled:
+                # `e` is unbound when the except block ends, before
+                # `dispatch` gets to run the callback.
+                self.dispatch(lambda error=e: on_error(error))
+        else:
+            job.status = "done" if job.interval is None else "waiting"
+            job.error = None
+            if on_done is not None and not job.cancelled:
+                self.dispatch(lambda: on_done(result))
+        finally:
+            JOB_CONTEXT.job = None
+        job.finished_at = time.time()
+        if job.cancelled:
+            job.status = "cancelled"
+        elif job.interval is not None:
+            self.schedule(job, work, on_done, on_error)
+
+    def cancel(self, job_id: int) -> bool:
+        """
+        Cancel a job, returning False if there is no such unfinished job.
+        Running work finishes, but its result is discarded.
+        """
+        job = self.jobs.get(job_id)
+        if job is None or job.status in FINISHED_STATUSES:
+            return False
+        job.cancel_event.set()
+        if job.status != "running":
+            job.status = "cancelled"
+        return True
+
+    def prune(self):
+        """
+        Forget all but the last `FINISHED_JOBS_KEPT` finished jobs. Must be
+        called with `lock` held.
+        """
+        finished = [job_id for job_id, job in self.jobs.items() if job.status in FINISHED_STATUSES]
+        for job_id in sorted(finished)[:-FINISHED_JOBS_KEPT]:
+            del self.jobs[job_id]
+
+    def list(self) -> List[Job]:
+        with self.lock:
+            return sorted(self.jobs.values(), key=lambda job: job.id)
@@ -0,0 +1,149 @@
+from dataclasses import dataclass
+import re
+from typing import Dict, List, Optional, Tuple
+
+from .diff import DiffLine, FileDiff, parse_unified_diff
+
+# Each message of an mbox starts with a `From <sender> <date>` line. Patches
+# produced by `git format-patch` use the commit SHA as the sender.
+MBOX_SEPARATOR_PATTERN = re.compile(r"^From \S+ .*\d{4}$")
+
+
+@dataclass
+class Patch:
+    """
+    A single patch email, e.g. one message of a `git format-patch` series.
+    """
+    headers: Dict[str, str]
+    # The raw lines of the message, headers included.
+    lines: List[str]
+    # 1-based line number of the first line of this message in the mbox.
+    first_line: int
+    # Index into `lines` of the first line after the headers.
+    body_start: int
+    files: List[FileDiff]
+
+    @property
+    def subject(self) -> str:
+        return self.headers.get("Subject", "")
+
+    @property
+    def author(self) -> str:
+        return self.headers.get("From", "")
+
+    @property
+    def message_id(self) -> Optional[str]:
+        return self.headers.get("Message-Id")
+
+    def find_file(self, path: str) -> Optional[FileDiff]:
+        for file_diff in self.files:
+            if file_diff.path == path:
+                return file_diff
+        return None
+
+
+def parse_headers(lines: List[str]) -> Tuple[Dict[str, str], int]:
+    """
+    Parse the email headers at the start of `lines`, returning them along
+    with the index of the first line after the headers.
+    """
+    headers: Dict[str, str] = {}
+    last_header = None
+    for index, line in enumerate(lines):
+        if not line:
+            return headers, index + 1
+        if line[0] in " \t" and last_header is not None:
+            # Folded continuation of the previous header.
+            headers[last_header] += " " + line.strip()
+        elif ":" in line:
+            name, value = line.split(":", 1)
+            last_header = "-".join(part.capitalize() for part in name.strip().split("-"))
+            headers[last_header] = value.strip()
+        else:
+            # Not an email at all, e.g. a bare diff.
+            return {}, 0
+    return headers, len(lines)
+
+
+def parse_mbox(text: str) -> List[Patch]:
+    """
+    Parse an mbox (or a single `.patch` file, or a bare diff) into its patches.
+    """
+    all_lines = text.split("\n")
+    message_starts = [
+        index for index, line in enumerate(all_lines)
+        if MBOX_SEPARATOR_PATTERN.match(line)
+    ]
+    if not message_starts or message_starts[0] != 0:
+        message_starts.insert(0, 0)
+
+    patches = []
+    for start, end in zip(message_starts, message_starts[1:] + [len(all_lines)]):
+        lines = all_lines[start:end]
+        header_start = 1 if lines and MBOX_SEPARATOR_PATTERN.match(lines[0]) else 0
+        headers, body_start = parse_headers(lines[header_start:])
+        body_start += header_start
+        files = parse_unified_diff("\n".join(lines[body_start:]), start + body_start + 1)
+        if files or headers:
+            patches.append(Patch(headers, lines, start + 1, body_start, files))
+    return patches
+
+
+def locate_source_line(patches: List[Patch], source_line: int) -> Optional[Tuple[int, FileDiff, DiffLine]]:
+    """
+    Return the index of the patch, the file and the diff line found at line
+    `source_line` of the mbox, or None if that line is not part of a hunk.
+    """
+    for patch_index, patch in enumerate(patches):
+        for file_diff in patch.files:
+            for hunk in file_diff.hunks:
+                for diff_line in hunk.lines:
+                    if diff_line.source_line == source_line:
+                        return patch_index, file_diff, diff_line
+    return None
+
+
+def quote_reply(patch: Patch, replies: Dict[int, List[str]]) -> str:
+    """
+    Return the body of an inline reply to `patch`: the patch quoted with
+    `> `, with each reply inserted right after the mbox line it refers to.
+
+    `replies` maps 1-based mbox line numbers to the reply texts for them.
+    """
+    reply_lines = [f"On {patch.headers.get('Date', 'an unknown date')}, {patch.author or 'the author'} wrote:"]
+    for index, line in enumerate(patch.lines[patch.body_start:], start=patch.body_start):
+        reply_lines.append(f"> {line}".rstrip())
+        for reply in replies.get(patch.first_line + index, []):
+            reply_lines += ["", reply.rstrip("\n"), ""]
+    return "\n".join(reply_lines).rstrip("\n") + "\n"
+
+
+def reply_email(patch: Patch, replies: Dict[int, List[str]], sender: str, reviewed_by: bool = False) -> str:
+    """
+    Return a complete reply email to `patch`, ready to be sent with
+    `git send-email`: threaded under the patch, addressed to its author with
+    the original recipients in copy, and with the comments interleaved in
+    the quoted patch.
+
+    With `reviewed_by`, a `Reviewed-by:` trailer for `sender` is appended.
+    """
+    subject = patch.subject
+    if not subject.lower().startswith("re:"):
+        subject = f"Re: {subject}"
+    cc = ", ".join(
+        address for address in (patch.headers.get("To"), patch.headers.get("Cc")) if address
+    )
+    headers = [f"From: {sender}", f"To: {patch.author}"]
+    if cc:
+        headers.append(f"Cc: {cc}")
+    headers.append(f"Subject: {subject}")
+    if patch.message_id:
+        references = " ".join(
+            reference for reference in (patch.headers.get("References"), patch.message_id) if reference
+        )
+        headers += [f"In-Reply-To: {patch.message_id}", f"References: {references}"]
+
+    body = quote_reply(patch, replies) if replies else ""
+    if reviewed_by:
+        body += f"\nReviewed-by: {sender}\n"
+    return "\n".join(headers) + "\n\n" + body.lstrip("\n")
@@ -1,14 +1,345 @@
 from argparse import ArgumentParser
-from dataclasses import dataclass
+import csv
+from dataclasses import dataclass, field, fields, is_dataclass, replace
+from datetime import datetime, timedelta, timezone
+import difflib
+import hashlib
 import os
 import json
-from typing import List, Optional, Literal, Tuple
+import logging
+import re
+import shlex
+import sys
+import tempfile
+import time
+from typing import Callable, Dict, Iterable, List, Optional, Literal, Tuple
 import subprocess
+import uuid
 
 import requests
 
+from .diff import FileDiff, hunk_ranges, in_hunk_ranges, parse_unified_diff
+from .mbox import Patch, parse_mbox, quote_reply, reply_email
+from .crypto import decrypt_text, encrypt_text, is_encrypted
+from .codeowners import CODEOWNERS_LOCATIONS, OwnershipRule, parse_codeowners, pattern_regex
+from .github import (
+    add_labels,
+    api_request,
+    blob_web_url,
+    create_gist,
+    get_api_token,
+    get_authenticated_user,
+    get_check_runs,
+    get_combined_status,
+    get_commit,
+    get_comparison,
+    get_file_contents,
+    get_pull_request_if_changed,
+    get_pull_request_comments,
+    get_pull_request_files,
+    get_pull_request_overview,
+    get_pull_request_reviews,
+    get_rate_limits,
+    list_collaborators,
+    list_labels,
+    list_pull_requests,
+    list_user_teams,
+    merge_pull_request,
+    pull_request_url,
+    pull_request_web_url,
+    remove_label,
+    request_reviewers,
+    search_review_requests,
+    set_file_viewed,
+    set_host,
+    set_repo_token,
+    set_token_source,
+    update_pull_request,
+)
+
 Side = Literal["RIGHT", "LEFT"]
 
+# Details for debugging, such as the payloads published; the editor plugin
+# echoes them in verbose mode.
+logger = logging.getLogger(__name__)
+
+# Orders comments can be listed and exported in.
+COMMENT_ORDERS = ("file", "severity", "created", "published")
+
+# Severities from most to least pressing; others sort after these, and
+# comments without a severity last.
+SEVERITY_ORDER = ["blocker", "issue", "suggestion", "question", "nit", "praise"]
+
+# The verdicts a review can be published with, and GitHub's names for them.
+# Without one, the review stays pending on GitHub until submitted there.
+REVIEW_EVENTS = {"approve": "APPROVE", "request-changes": "REQUEST_CHANGES", "comment": "COMMENT"}
+
+# How long the cached PR metadata is used as is; after that, it is checked
+# with a conditional request the next time it is needed.
+PULL_REQUEST_CACHE_TTL = 300
+
+# Labels that mark comments worth learning from, compiled across reviews by
+# `:ExportReview digest` (`g:reviewer_teaching_labels` replaces them).
+DEFAULT_TEACHING_LABELS = ("pattern", "idiom", "tooling")
+
+# The requirements compliance mode (the `compliance` setting of
+# `vim-reviewer.json`) can enforce before a review is published, see
+# `Review.compliance_problems`.
+COMPLIANCE_RULES = ("checklist", "body", "viewed", "severity")
+
+# GitHub rejects review bodies longer than this, and submissions with many
+# inline comments tend to time out, so bigger reviews are published in parts.
+MAX_REVIEW_BODY_LENGTH = 65536
+MAX_REVIEW_COMMENTS = 50
+
+# Headings of the severities in review summaries.
+SEVERITY_HEADINGS = {
+    "blocker": "Blocking",
+    "issue": "Issues",
+    "suggestion": "Suggestions",
+    "question": "Questions",
+    "nit": "Nits",
+    "praise": "Praise",
+}
+
+# Lists patterns (`vendor/**`, `*.lock`, ...) of files to leave out of signs
+# and the changed files panel, e.g. generated files, at the repository root.
+IGNORE_FILE = ".reviewerignore"
+
+# Separates the header of a comment buffer from the comment's body.
+COMMENT_HEADER_SEPARATOR = "---"
+
+# Text autolinks leave alone: code, Markdown links and bare URLs.
+AUTOLINK_SKIP_PATTERN = re.compile(r"(?s:```.*?```)|`[^`\n]*`|\[[^\]\n]*\]\([^)\n]*\)|https?://\S+")
+
+# Placeholders in comment bodies and the review body, expanded when the review
+# is published, e.g. "In {{file}}:{{line}}, consider...".
+PLACEHOLDER_PATTERN = re.compile(r"\{\{\s*(file|line|pr|selection)\s*\}\}")
+
+
+def comment_location(comment: "Comment") -> str:
+    """
+    Return where `comment` is, e.g. `` `src/main.py` lines 10-12 ``.
+    """
+    if comment.start_line is not None:
+        location = f"`{comment.path}` lines {comment.start_line}-{comment.line}"
+    else:
+        location = f"`{comment.path}` line {comment.line}"
+    if comment.side == "LEFT":
+        location += " (base)"
+    return location
+
+
+def comments_at_position(
+    comments: List["Comment"], path: str, line: int, side: Side, patch_index: Optional[int] = None
+) -> List["Comment"]:
+    """
+    Return the comments of `comments` whose span contains the given line.
+    """
+    return [
+        c for c in comments
+        if c.path == path and c.side == side and c.patch_index == patch_index and (
+            line == c.line or (c.start_line is not None and (line >= c.start_line) and (line <= c.line))
+        )
+    ]
+
+
+def is_ignored(path: str, patterns: List[str]) -> bool:
+    """
+    Whether `path` matches one of the `.gitignore`-style `patterns`.
+    """
+    return any(pattern_regex(pattern).match(path) for pattern in patterns)
+
+
+def split_text(text: str, max_length: int) -> List[str]:
+    """
+    Split `text` into parts of at most `max_length` characters, between
+    paragraphs when possible.
+    """
+    parts: List[str] = []
+    current = ""
+    for paragraph in text.split("\n\n"):
+        while len(paragraph) > max_length:
+            if current:
+                parts.append(current)
+                current = ""
+            parts.append(paragraph[:max_length])
+            paragraph = paragraph[max_length:]
+        if current and len(current) + 2 + len(paragraph) > max_length:
+            parts.append(current)
+            current = paragraph
+        else:
+            current = f"{current}\n\n{paragraph}" if current else paragraph
+    if current:
+        parts.append(current)
+    return parts
+
+
+def normalize_text(text: str) -> str:
+    """
+    Return `text`, as written in an editor buffer, in the form comments and
+    review bodies are stored in: with `\n` line endings, even if the buffer
+    had Windows (`\r\n`) or old Mac (`\r`) ones, and without trailing blank
+    lines.
+    """
+    lines = text.replace("\r\n", "\n").replace("\r", "\n").split("\n")
+    while lines and not lines[-1].strip():
+        lines.pop()
+    return "\n".join(lines)
+
+
+def apply_autolinks(text: str, autolinks: Dict[str, str]) -> str:
+    """
+    Turn references to issue trackers in `text` into Markdown links.
+
+    `autolinks` maps regular expressions, e.g. `JIRA-(\\d+)`, to the URLs
+    their matches link to, which can refer to the groups of the match
+    (`https://jira.example.com/browse/JIRA-\\1`). Code spans and existing
+    links are left alone.
+    """
+    for pattern, url in autolinks.items():
+        regex = re.compile(pattern)
+
+        def link(match) -> str:
+            return f"[{match.group(0)}]({match.expand(url)})"
+
+        parts = []
+        position = 0
+        for skipped in AUTOLINK_SKIP_PATTERN.finditer(text):
+            parts.append(regex.sub(link, text[position:skipped.start()]))
+            parts.append(skipped.group(0))
+            position = skipped.end()
+        parts.append(regex.sub(link, text[position:]))
+        text = "".join(parts)
+    return text
+
+
+def now_timestamp() -> str:
+    return datetime.now(timezone.utc).isoformat(timespec="seconds")
+
+
+def format_timestamp(timestamp: str) -> str:
+    """
+    Format an ISO 8601 timestamp (ours or GitHub's) in local time.
+    """
+    moment = datetime.fromisoformat(timestamp.replace("Z", "+00:00"))
+    return moment.astimezone().strftime("%Y-%m-%d %H:%M")
+
+
+def normalize_path(path: str) -> str:
+    """
+    Return `path` in the form git and GitHub use for paths in a repository:
+    forward slashes, with no leading `./`.
+    """
+    path = path.replace("\\", "/")
+    while path.startswith("./"):
+        path = path[2:]
+    return path
+
+
+def repository_relative_path(path: str, repository_root: str) -> Optional[str]:
+    """
+    Return the path of the file at absolute `path` relative to
+    `repository_root`, normalized with `normalize_path`, or None if it is
+    outside the repository.
+
+    Either may use backslashes, and drive letters are compared
+    case-insensitively, so Windows paths match however they were spelled.
+    """
+    path = normalize_path(path)
+    root = normalize_path(repository_root).rstrip("/")
+    if not root:
+        return None
+    if re.match(r"^[A-Za-z]:", root) or os.name == "nt":
+        matches = path.lower().startswith(root.lower() + "/")
+    else:
+        matches = path.startswith(root + "/")
+    return path[len(root) + 1:] if matches else None
+
+
+def native_path(repository_root: str, path: str) -> str:
+    """
+    Return the absolute file name, in the platform's own form, of the file
+    at repository path `path`.
+    """
+    return os.path.join(repository_root, *normalize_path(path).split("/"))
+
+
+def legacy_comment_id(json_repr: dict, occurrence: int = 0) -> str:
+    """
+    Return an ID for a comment saved before comments had one, derived from
+    its contents so that every copy of the review agrees on it. Identical
+    comments are told apart by their `occurrence` among them.
+    """
+    key = json.dumps(json_repr, sort_keys=True)
+    if occurrence:
+        key += f"#{occurrence}"
+    return uuid.uuid5(uuid.NAMESPACE_OID, key).hex
+
+
+def comments_from_json(json_reprs: List[dict]) -> List["Comment"]:
+    """
+    Build the comments of a review file, giving identical comments saved
+    before comments had an ID distinct IDs.
+    """
+    occurrences: Dict[str, int] = {}
+    comments = []
+    for json_repr in json_reprs:
+        key = json.dumps(json_repr, sort_keys=True)
+        occurrence = occurrences.get(key, 0)
+        occurrences[key] = occurrence + 1
+        comments.append(Comment.from_json(json_repr, occurrence))
+    return comments
+
+
+def merge_fields(base, ours, theirs, prefer_ours: bool):
+    """
+    Merge two edited copies `ours` and `theirs` of the dataclass instance
+    `base`: every field only one of them changed keeps that change, and
+    fields both changed take `ours` if `prefer_ours`, `theirs` otherwise.
+    Fields that aren't compared (like bookkeeping) come from `theirs`.
+    """
+    merged = replace(theirs)
+    for f in fields(ours):
+        if not f.compare or getattr(ours, f.name) == getattr(base, f.name):
+            continue
+        if prefer_ours or getattr(theirs, f.name) == getattr(base, f.name):
+            setattr(merged, f.name, getattr(ours, f.name))
+    return merged
+
+
+def merge_lists(base: list, ours: list, theirs: list, key: Callable, prefer_ours: Callable) -> list:
+    """
+    Three-way merge of two edited copies `ours` and `theirs` of the list
+    `base`, whose items are identified by `key`: additions on either side
+    are kept, items deleted on one side are dropped unless the other side
+    edited them, and items both kept are merged with `merge_fields`, with
+    `prefer_ours(ours, theirs)` deciding conflicts. The order of `theirs`
+    is kept, followed by the items only `ours` added.
+    """
+    base_items = {key(item): item for item in base}
+    our_items = {key(item): item for item in ours}
+    their_keys = {key(item) for item in theirs}
+    merged = []
+    for item in theirs:
+        base_item = base_items.get(key(item))
+        our_item = our_items.get(key(item))
+        if our_item is not None:
+            prefer = prefer_ours(our_item, item)
+            if base_item is not None and is_dataclass(item):
+                merged.append(merge_fields(base_item, our_item, item, prefer))
+            else:
+                merged.append(our_item if prefer else item)
+        elif base_item is None or item != base_item:
+            # Added by them, or deleted by us after they edited it.
+            merged.append(item)
+    for item in ours:
+        base_item = base_items.get(key(item))
+        if key(item) not in their_keys and (base_item is None or item != base_item):
+            # Added by us, or deleted by them after we edited it.
+            merged.append(item)
+    return merged
+
 
 @dataclass
 class Comment:
@@ -18,29 +349,225 @@ class Comment:
     side: Side
     start_line: Optional[int]
     start_side: Optional[Side]
+    # For reviews of patch series, the index of the patch commented on.
+    patch_index: Optional[int] = None
+    # Set when the commented file changed in commits pushed after the
+    # comment was written, so its line numbers may need re-anchoring.
+    outdated: bool = False
+    # Optional classification, e.g. `nit` or `blocker`, and free-form labels.
+    severity: Optional[str] = None
+    labels: List[str] = field(default_factory=list)
+    # ISO 8601 timestamps, unset for comments written before they were recorded.
+    created_at: Optional[str] = None
+    updated_at: Optional[str] = None
+    # The GitHub login of the author, for comments fetched from GitHub. Draft
+    # comments are the user's own and leave it unset.
+    author: Optional[str] = None
+    # For character-wise selections, the 1-based columns (inclusive) the
+    # selection starts at on the first line and ends at on the last line.
+    # GitHub only anchors comments to whole lines, so these are local only.
+    start_column: Optional[int] = None
+    end_column: Optional[int] = None
+    # Identifies the comment for as long as it exists, unlike its position
+    # in the review, which changes as other comments are added or deleted.
+    id: str = field(default_factory=lambda: uuid.uuid4().hex)
+    # The body (with severity and labels) as of the last time the comment
+    # was published, or None if it never was.
+    published_body: Optional[str] = None
+    # Held comments (e.g. speculative ones still to be verified) are left out
+    # when publishing, unless asked to include them.
+    ready: bool = True
 
     def to_json(self) -> dict:
         return {
+            "id": self.id,
             "body": self.body,
             "path": self.path,
             "line": self.line,
             "side": self.side,
             "start_line": self.start_line,
             "start_side": self.start_side,
+            "patch_index": self.patch_index,
+            "outdated": self.outdated,
+            "severity": self.severity,
+            "labels": self.labels,
+            "created_at": self.created_at,
+            "updated_at": self.updated_at,
+            "author": self.author,
+            "start_column": self.start_column,
+            "end_column": self.end_column,
+            "published_body": self.published_body,
+            "ready": self.ready,
+        }
+
+    @property
+    def publish_state(self) -> str:
+        """
+        Return whether the comment is `new`, `edited` since it was published,
+        or `published` and unchanged since.
+        """
+        if self.published_body is None:
+            return "new"
+        if self.published_body != self.decorated_body:
+            return "edited"
+        return "published"
+
+    @property
+    def decorated_body(self) -> str:
+        """
+        Return the body prefixed with the severity and labels, if any, in the
+        style of Conventional Comments (`**nit** (style): ...`).
+        """
+        if not self.severity and not self.labels:
+            return self.body
+        prefix = f"**{self.severity or 'note'}**"
+        if self.labels:
+            prefix += f" ({', '.join(self.labels)})"
+        return f"{prefix}: {self.body}"
+
+    def quote(self, file_lines: List[str]) -> str:
+        """
+        Return the commented code from `file_lines`, the lines of the
+        commented file: the selected text for character-wise selections, or
+        the first commented line otherwise.
+        """
+        start_line = self.start_line or self.line
+        if start_line > len(file_lines):
+            return ""
+        if self.start_column is None:
+            return file_lines[start_line - 1].strip()
+        selected = file_lines[start_line - 1:self.line]
+        selected[-1] = selected[-1][:self.end_column]
+        selected[0] = selected[0][self.start_column - 1:]
+        return " ".join(line.strip() for line in selected)
+
+    def touch(self):
+        """
+        Record that the comment was just written or edited.
+        """
+        self.updated_at = now_timestamp()
+        if self.created_at is None:
+            self.created_at = self.updated_at
+
+    def describe_origin(self) -> str:
+        """
+        Return who wrote the comment and when, e.g. `@octocat, 2024-05-01 10:12`.
+        """
+        origin = f"@{self.author}" if self.author else "you"
+        if self.created_at:
+            origin += f", {format_timestamp(self.created_at)}"
+        if self.updated_at and self.updated_at != self.created_at:
+            origin += f" (edited {format_timestamp(self.updated_at)})"
+        return origin
+
+    def editor_text(self) -> str:
+        """
+        Return the contents of the buffer this comment is edited in: a header
+        with its location, severity and labels, then a `---` line and the body.
+        """
+        if self.start_line is not None:
+            lines = f"{self.start_line}-{self.line}"
+        else:
+            lines = str(self.line)
+        if self.start_column is not None:
+            lines += f" ({self.side}, columns {self.start_column}-{self.end_column})"
+        else:
+            lines += f" ({self.side})"
+        header = [
+            f"Path: {self.path}",
+            f"Lines: {lines}",
+            f"Severity: {self.severity or ''}",
+            f"Labels: {', '.join(self.labels)}",
+            f"Ready: {'yes' if self.ready else 'no'}",
+        ]
+        if self.created_at:
+            header.append(f"Written: {self.describe_origin()}")
+        header.append(COMMENT_HEADER_SEPARATOR)
+        return "\n".join(header) + "\n" + self.body
+
+    def update_from_editor_text(self, text: str):
+        """
+        Update the body, severity, labels and ready flag from the contents of
+        the buffer this comment was edited in. The location in the header is
+        read-only.
+        """
+        text = normalize_text(text)
+        lines = text.split("\n")
+        if not lines[0].startswith("Path:") or COMMENT_HEADER_SEPARATOR not in lines:
+            # The header was deleted; treat everything as the body.
+            self.body = text
+            return
+        separator = lines.index(COMMENT_HEADER_SEPARATOR)
+        for line in lines[:separator]:
+            name, _, value = line.partition(":")
+            value = value.strip()
+            if name.strip() == "Severity":
+                self.severity = value.lower() or None
+            elif name.strip() == "Labels":
+                self.labels = [label.strip() for label in value.split(",") if label.strip()]
+            elif name.strip() == "Ready":
+                self.ready = value.lower() not in ("no", "n", "false", "held")
+        self.body = "\n".join(lines[separator + 1:])
+
+    def publish_payload(self) -> dict:
+        """
+        Return the representation of this comment in a GitHub review submission.
+        """
+        # Drafts saved before line endings were normalized may still carry
+        # stray `\r`s.
+        return {
+            "body": normalize_text(self.decorated_body),
+            "path": self.path,
+            "line": self.line,
+            "side": self.side,
+            "start_line": self.start_line,
+            "start_side": self.start_side,
         }
 
     def serialize(self) -> str:
-        return json.dumps(self.to_json(), indent=2)
+        return json.dumps(self.to_json(), indent=2, ensure_ascii=False)
 
     @staticmethod
-    def from_json(json_repr: dict) -> "Comment":
+    def from_json(json_repr: dict, occurrence: int = 0) -> "Comment":
         return Comment(
             json_repr["body"],
             json_repr["line"],
-            json_repr["path"],
+            normalize_path(json_repr["path"]),
             json_repr["side"],
             json_repr["start_line"],
             json_repr["start_side"],
+            json_repr.get("patch_index"),
+            json_repr.get("outdated", False),
+            json_repr.get("severity"),
+            json_repr.get("labels", []),
+            json_repr.get("created_at"),
+            json_repr.get("updated_at"),
+            json_repr.get("author"),
+            json_repr.get("start_column"),
+            json_repr.get("end_column"),
+            json_repr.get("id") or legacy_comment_id(json_repr, occurrence),
+            json_repr.get("published_body"),
+            json_repr.get("ready", True),
+        )
+
+    @staticmethod
+    def from_remote_json(json_repr: dict) -> "Comment":
+        """
+        Build a comment from a review comment fetched from GitHub.
+        """
+        # Comments on lines that are no longer part of the diff only have
+        # their original position.
+        line = json_repr.get("line") or json_repr.get("original_line") or 1
+        return Comment(
+            json_repr["body"],
+            line,
+            normalize_path(json_repr["path"]),
+            json_repr.get("side") or "RIGHT",
+            json_repr.get("start_line"),
+            json_repr.get("start_side"),
+            created_at=json_repr.get("created_at"),
+            updated_at=json_repr.get("updated_at"),
+            author=json_repr["user"]["login"],
         )
 
     @staticmethod
@@ -49,154 +576,3372 @@ class Comment:
 
 
 @dataclass
-class Review:
-    owner: str
-    repo: str
-    pr_number: int
-    body: str
-    comments: List[Comment]
+class ChecklistItem:
+    """
+    A check the reviewer performs (e.g. "Migrations are reversible"), ticked
+    off as part of the review.
+    """
+    text: str
+    checked: bool = False
+    # Required items must be checked before the review can be published,
+    # when `g:reviewer_enforce_checklist` is set.
+    required: bool = False
+
+    def to_json(self) -> dict:
+        return {"text": self.text, "checked": self.checked, "required": self.required}
+
+    @staticmethod
+    def from_json(json_repr: dict) -> "ChecklistItem":
+        return ChecklistItem(json_repr["text"], json_repr.get("checked", False), json_repr.get("required", False))
+
+
+@dataclass
+class Publication:
+    """
+    A submission of the review to GitHub, kept as evidence of what was
+    published when, whether or not GitHub accepted it.
+    """
+    published_at: str
+    event: Optional[str]
+    head_sha: Optional[str]
+    # The number of parts the review was split into (see `publish_payloads`),
+    # the HTTP status of the last part submitted and whether all were accepted.
+    parts: int
+    status: Optional[int]
+    ok: bool
+    comment_count: int
+    # The review on GitHub, when the submission was accepted.
+    url: Optional[str] = None
+    author: Optional[str] = None
 
     def to_json(self) -> dict:
         return {
-            "owner": self.owner,
-            "repo": self.repo,
-            "pr_number": self.pr_number,
-            "body": self.body,
-            "comments": [comment.to_json() for comment in self.comments],
+            "published_at": self.published_at,
+            "event": self.event,
+            "head_sha": self.head_sha,
+            "parts": self.parts,
+            "status": self.status,
+            "ok": self.ok,
+            "comment_count": self.comment_count,
+            "url": self.url,
+            "author": self.author,
         }
 
-    def serialize(self) -> str:
-        return json.dumps(self.to_json(), indent=2)
-
-    @property
-    def post_url(self):
-        return f"https://api.github.com/repos/{self.owner}/{self.repo}/pulls/{self.pr_number}/reviews"
-
-    def publish(self, token):
-        return requests.post(
-            self.post_url,
-            data=self.serialize(),
-            headers={
-                "Accept": "application/vnd.github+json",
-                "Authorization": f"token {token}",
-            },
+    @staticmethod
+    def from_json(json_repr: dict) -> "Publication":
+        return Publication(
+            json_repr["published_at"],
+            json_repr.get("event"),
+            json_repr.get("head_sha"),
+            json_repr.get("parts", 1),
+            json_repr.get("status"),
+            json_repr.get("ok", False),
+            json_repr.get("comment_count", 0),
+            json_repr.get("url"),
+            json_repr.get("author"),
         )
 
-    def add_comment(self, comment: Comment):
-        self.comments.append(comment)
 
-    def set_body(self, body: str):
-        self.body = body
+@dataclass
+class HistoryEntry:
+    """
+    An entry of a review's action log: something done to the review (e.g.
+    `add`, `edit`, `delete` or `publish`), when, and to which comment or
+    note, with its body at that point.
+    """
+    at: str
+    action: str
+    comment_id: Optional[str] = None
+    path: Optional[str] = None
+    line: Optional[int] = None
+    body: Optional[str] = None
+    details: Optional[str] = None
 
-    def save(self):
-        review_file = get_review_file(self.pr_number)
-        with open(review_file, "w") as f:
-            f.write(self.serialize())
+    def to_json(self) -> dict:
+        return {
+            "at": self.at,
+            "action": self.action,
+            "comment_id": self.comment_id,
+            "path": self.path,
+            "line": self.line,
+            "body": self.body,
+            "details": self.details,
+        }
 
     @staticmethod
-    def from_json(json_repr: dict) -> "Review":
-        return Review(
-            json_repr["owner"],
-            json_repr["repo"],
-            json_repr["pr_number"],
-            json_repr["body"],
-            [Comment.from_json(c) for c in json_repr["comments"]],
+    def from_json(json_repr: dict) -> "HistoryEntry":
+        return HistoryEntry(
+            json_repr["at"],
+            json_repr["action"],
+            json_repr.get("comment_id"),
+            json_repr.get("path"),
+            json_repr.get("line"),
+            json_repr.get("body"),
+            json_repr.get("details"),
         )
 
-    @staticmethod
-    def deserialize(serialized: str) -> "Review":
-        return Review.from_json(json.loads(serialized))
+    def describe(self) -> str:
+        parts = [format_timestamp(self.at), f"{self.action:<11}"]
+        if self.path is not None:
+            parts.append(f"{self.path}:{self.line}")
+        if self.details:
+            parts.append(self.details)
+        if self.body:
+            parts.append(self.body.strip().split("\n")[0][:60])
+        return "  ".join(parts)
 
-    def get_comment_at_position(self, path: str, line: int) -> Optional[Comment]:
-        """
-        Return the first comment in this review whose span contains the
-        requested file path and line.
-        """
-        eligible_comments = [
-            c for c in self.comments
-            if c.path == path and (
-                line == c.line or (c.start_line is not None and (line >= c.start_line) and (line <= c.line))
-            )
-        ]
-        if eligible_comments:
-            return eligible_comments[0]
-        return None
 
-    def delete_comment(self, comment: Comment):
-        self.comments = list(filter(lambda c: c != comment, self.comments))
-        self.save()
+# The columns of `:ExportReview audit` CSV files. Each row is one record:
+# the review itself, a comment, a checklist item or a publication, with the
+# columns that don't apply to it left empty.
+AUDIT_COLUMNS = [
+    "record", "reviewer", "repository", "target", "exported_at",
+    "id", "path", "start_line", "line", "side", "severity", "labels", "ready", "publish_state",
+    "created_at", "updated_at", "body", "checked", "required",
+    "published_at", "event", "head_sha", "parts", "status", "ok", "comment_count", "url", "author",
+]
 
 
-def get_review_directory() -> str:
+@dataclass
+class ChangedFile:
     """
-    Returns the directory storing in-progress reviews. Creates this directory if it does not exist.
+    A file changed by the PR, as reported by GitHub.
+    """
+    path: str
+    status: str
+    additions: int
+    deletions: int
+    previous_path: Optional[str]
+    # The hunks of the change, as a unified diff without the file header.
+    # GitHub leaves this out for binary and very large diffs.
+    patch: Optional[str] = None
 
-    This directory is within the `.git` directory of the local repository.
+    @property
+    def status_letter(self) -> str:
+        return {
+            "added": "A",
+            "removed": "D",
+            "modified": "M",
+            "renamed": "R",
+            "copied": "C",
+            "changed": "M",
+        }.get(self.status, " ")
+
+    @staticmethod
+    def from_json(json_repr: dict) -> "ChangedFile":
+        return ChangedFile(
+            json_repr["filename"],
+            json_repr["status"],
+            json_repr["additions"],
+            json_repr["deletions"],
+            json_repr.get("previous_filename"),
+            json_repr.get("patch"),
+        )
+
+
+@dataclass
+class PullRequestOverview:
     """
-    git_dir = (
-        subprocess.check_output(["git", "rev-parse", "--git-dir"])
-        .decode("utf-8")
-        .strip()
-    )
-    reviews_path = os.path.join(git_dir, "reviews")
-    os.makedirs(reviews_path, exist_ok=True)
-    print(f"Review directory at {reviews_path}")
-    return reviews_path
+    Everything needed to show the changed-files panel of a PR.
+    """
+    changed_files: List[ChangedFile]
+    remote_comment_counts: Dict[str, int]
+    # The paths GitHub shows as viewed for the user, or None when unknown.
+    viewed_paths: Optional[List[str]]
 
 
-def get_review_file(pr_number: int) -> str:
+# GraphQL `PatchStatus` values, as the status names of the REST API.
+GRAPHQL_CHANGE_TYPES = {
+    "ADDED": "added",
+    "DELETED": "removed",
+    "MODIFIED": "modified",
+    "RENAMED": "renamed",
+    "COPIED": "copied",
+    "CHANGED": "changed",
+}
+
+
+@dataclass
+class PullRequestState:
     """
-    Return the path to the review file for the PR specified by `pr_number`.
+    A snapshot of the parts of a PR that are watched while polling for updates.
     """
-    review_directory = get_review_directory()
-    return os.path.join(review_directory, f"{pr_number}-review.json")
+    head_sha: str
+    comment_count: int
+    # Latest review state (APPROVED, CHANGES_REQUESTED, ...) of each reviewer.
+    review_states: Dict[str, str]
 
-def get_or_create_review(pr_number: int) -> Review:
-    review_file = get_review_file(pr_number)
-    if os.path.exists(review_file):
-        with open(review_file) as f:
-            return Review.deserialize(f.read())
-    else:
-        return new_blank_review(pr_number)
+
+@dataclass
+class ReviewDecisions:
+    """
+    Where the reviews of a PR stand, by reviewer login.
+    """
+    approved: List[str]
+    # Change requests that are still outstanding: the reviewer hasn't
+    # approved since, and the request wasn't dismissed.
+    changes_requested: List[str]
+    # Reviewers who only left comments.
+    commented: List[str]
+    # Users and teams (`org/team`) whose review was requested and is awaited.
+    requested: List[str]
 
 
-def get_repo_from_config() -> Tuple[str, str]:
-    config_path = get_config_file_path()
-    with open(config_path) as f:
-        config = json.load(f)
-        return config["owner"], config["repo"]
+def latest_review_states(submitted_reviews: List[dict]) -> Dict[str, str]:
+    """
+    Return the latest state (APPROVED, CHANGES_REQUESTED, ...) of the reviews
+    of each reviewer, from the reviews of a PR in the order they were
+    submitted.
+    """
+    review_states: Dict[str, str] = {}
+    for submitted_review in submitted_reviews:
+        reviewer = submitted_review["user"]["login"]
+        # Plain comments don't override an earlier approval or change request.
+        if submitted_review["state"] != "COMMENTED" or reviewer not in review_states:
+            review_states[reviewer] = submitted_review["state"]
+    return review_states
 
 
-def new_blank_review(pr_number: int) -> Review:
-    owner, repo = get_repo_from_config()
-    return Review(owner, repo, pr_number, "", [])
+@dataclass
+class BlameEntry:
+    """
+    The commit that last changed some lines of a file, as reported by `git blame`.
+    """
+    commit_sha: str
+    author: str
+    summary: str
+    # Unix timestamp of the commit's author date.
+    author_time: int
+    line_count: int
 
 
-def get_review(pr_number: int) -> Review:
+@dataclass
+class ReviewerSuggestion:
     """
-    Return the `Review` object representing the current review for `pr_number`.
-    Creates a new `Review` object if no review already exists.
+    Someone who recently worked on the code a PR changes, and may be a good
+    reviewer for it.
     """
-    review_file_path = get_review_file(pr_number)
-    if os.path.exists(review_file_path):
-        with open(review_file_path) as f:
-            return Review.deserialize(f.read())
-    else:
-        return new_blank_review(pr_number)
+    # The author name from git, and their GitHub login if it could be found.
+    name: str
+    login: Optional[str]
+    # Number of changed lines they last touched, weighted by how recently.
+    score: float
+    lines: int
+    last_commit_sha: str
+    last_commit_time: int
 
 
-def get_config_file_path():
-    review_dir = get_review_directory()
-    return os.path.join(review_dir, "config.json")
+@dataclass
+class MergeStatus:
+    """
+    Whether a PR can be merged, as checked before merging it.
+    """
+    head_sha: str
+    # None while GitHub is still computing it.
+    mergeable: Optional[bool]
+    # GitHub's `mergeable_state`: clean, blocked, behind, dirty, unstable, ...
+    mergeable_state: str
+    # Combined state of the commit statuses and check runs of the head:
+    # success, pending, failure, or none if there are no checks.
+    ci_state: str
+    failing_checks: List[str]
 
 
-def update_configuration(repository: str):
-    config_file_path = get_config_file_path()
-    if os.path.exists(config_file_path):
-        print("Warning: overwriting existing configuration.")
-    owner, repo = repository.split("/")
-    with open(config_file_path, "w") as f:
-        json.dump({"owner": owner, "repo": repo}, f, indent=2)
+@dataclass
+class ReviewRequest:
+    """
+    An open PR, in any repository, waiting for the user's review.
+    """
+    owner: str
+    repo: str
+    pr_number: int
+    title: str
+    author: str
+    created_at: datetime
+
+    @property
+    def key(self) -> str:
+        return f"{self.owner}/{self.repo}#{self.pr_number}"
+
+    @property
+    def age(self) -> str:
+        days = (datetime.now(timezone.utc) - self.created_at).days
+        if days == 0:
+            return "today"
+        return f"{days}d"
+
+    @staticmethod
+    def from_json(json_repr: dict) -> "ReviewRequest":
+        # Search results only link to their repository, e.g.
+        # `https://api.github.com/repos/<owner>/<repo>`.
+        owner, repo = json_repr["repository_url"].split("/")[-2:]
+        return ReviewRequest(
+            owner,
+            repo,
+            json_repr["number"],
+            json_repr["title"],
+            json_repr["user"]["login"],
+            datetime.fromisoformat(json_repr["created_at"].replace("Z", "+00:00")),
+        )
+
+
+@dataclass
+class InboxTriage:
+    """
+    The review requests the user set aside in the review inbox, by
+    `ReviewRequest.key`: snoozed until a given time, or dismissed for good.
+    """
+    # ISO 8601 timestamps the requests are snoozed until.
+    snoozed: Dict[str, str] = field(default_factory=dict)
+    dismissed: List[str] = field(default_factory=list)
+
+    def to_json(self) -> dict:
+        return {"snoozed": self.snoozed, "dismissed": self.dismissed}
+
+    @staticmethod
+    def from_json(json_repr: dict) -> "InboxTriage":
+        return InboxTriage(json_repr.get("snoozed", {}), json_repr.get("dismissed", []))
+
+    @staticmethod
+    def load() -> "InboxTriage":
+        try:
+            with open(get_inbox_triage_file(), encoding="utf-8") as f:
+                return InboxTriage.from_json(json.load(f))
+        except FileNotFoundError:
+            return InboxTriage()
+
+    def save(self):
+        triage_file = get_inbox_triage_file()
+        os.makedirs(os.path.dirname(triage_file), exist_ok=True)
+        with open(triage_file, "w", encoding="utf-8") as f:
+            json.dump(self.to_json(), f, indent=2)
+
+    def status(self, request: ReviewRequest) -> Optional[str]:
+        """
+        Return why `request` is set aside, e.g. `snoozed until 2024-05-03 09:00`,
+        or None if it is actionable.
+        """
+        if request.key in self.dismissed:
+            return "dismissed"
+        snoozed_until = self.snoozed.get(request.key)
+        if snoozed_until is not None and snoozed_until > now_timestamp():
+            return f"snoozed until {format_timestamp(snoozed_until)}"
+        return None
+
+    def snooze(self, request: ReviewRequest, duration: timedelta):
+        until = datetime.now(timezone.utc) + duration
+        self.snoozed[request.key] = until.isoformat(timespec="seconds")
+        self.save()
+
+    def dismiss(self, request: ReviewRequest):
+        if request.key not in self.dismissed:
+            self.dismissed.append(request.key)
+        self.save()
+
+    def restore(self, request: ReviewRequest):
+        self.snoozed.pop(request.key, None)
+        if request.key in self.dismissed:
+            self.dismissed.remove(request.key)
+        self.save()
+
+    def prune(self, requests: List[ReviewRequest]):
+        """
+        Forget the requests that are no longer pending, e.g. because the PR
+        was merged, so the triage file doesn't grow forever.
+        """
+        keys = {request.key for request in requests}
+        pruned = InboxTriage(
+            {key: until for key, until in self.snoozed.items() if key in keys},
+            [key for key in self.dismissed if key in keys],
+        )
+        if pruned != self:
+            self.snoozed, self.dismissed = pruned.snoozed, pruned.dismissed
+            self.save()
+
+
+def parse_duration(text: str) -> Optional[timedelta]:
+    """
+    Parse a duration like `30m`, `4h`, `2d` or `1w`, returning None if it
+    isn't one.
+    """
+    match = re.fullmatch(r"\s*(\d+)\s*([mhdw])\s*", text)
+    if match is None:
+        return None
+    unit = {"m": "minutes", "h": "hours", "d": "days", "w": "weeks"}[match.group(2)]
+    return timedelta(**{unit: int(match.group(1))})
+
+
+@dataclass
+class Review:
+    owner: str
+    repo: str
+    # None for local reviews, which aren't tied to a PR.
+    pr_number: Optional[int]
+    body: str
+    comments: List[Comment]
+    viewed_files: List[str] = field(default_factory=list)
+    # Reviews of repositories without a local checkout are stored in the
+    # user's data directory rather than in a `.git` directory.
+    remote_only: bool = False
+    # When set (e.g. `abc123..def456`), the review only covers the commits in
+    # this range rather than the whole PR.
+    commit_range: Optional[str] = None
+    # The PR head at the time this review was last published, so re-reviews
+    # can focus on what changed since then.
+    last_reviewed_head_sha: Optional[str] = None
+    # Local reviews cover `commit_range` without any PR on a forge, and can
+    # only be exported rather than published.
+    local_only: bool = False
+    # Reviews of a `.patch` file or mbox series (which are also local) record
+    # the path to it.
+    patch_file: Optional[str] = None
+    checklist: List[ChecklistItem] = field(default_factory=list)
+    # Private notes, e.g. reminders to check something later. They are
+    # anchored like comments but kept apart from them, so that nothing that
+    # publishes or exports the review's comments can ever include them.
+    notes: List[Comment] = field(default_factory=list)
+    # Every time the review was submitted to GitHub, oldest first.
+    publications: List[Publication] = field(default_factory=list)
+    # The login of the PR's author, recorded when the PR is fetched, so that
+    # reviews can be told apart by author without fetching their PRs.
+    pr_author: Optional[str] = None
+    # Modification time of the review file when this review was last loaded
+    # or saved, to notice when something else changed it.
+    file_mtime: Optional[float] = field(default=None, compare=False, repr=False)
+    # Set for reviews opened for browsing only, e.g. someone else's review
+    # file, which must never be written.
+    read_only: bool = field(default=False, compare=False, repr=False)
+    # Action log entries not written yet; they are appended to the log when
+    # the review is saved, so that a failing save leaves both untouched.
+    unsaved_history: List[HistoryEntry] = field(default_factory=list, compare=False, repr=False)
+    # The review as last loaded or saved (see `to_json`), the common ancestor
+    # when merging with changes saved by someone else (see `merged_with`).
+    baseline: Optional[dict] = field(default=None, compare=False, repr=False)
+    # The PR head and the base `base_sha` resolved for it, kept until the PR
+    # head moves so that the base is only looked up once.
+    resolved_base: Optional[Tuple[str, str]] = field(default=None, compare=False, repr=False)
+
+    def to_json(self) -> dict:
+        return {
+            "owner": self.owner,
+            "repo": self.repo,
+            "pr_number": self.pr_number,
+            "body": self.body,
+            "comments": [comment.to_json() for comment in self.comments],
+            "viewed_files": self.viewed_files,
+            "remote_only": self.remote_only,
+            "commit_range": self.commit_range,
+            "last_reviewed_head_sha": self.last_reviewed_head_sha,
+            "local_only": self.local_only,
+            "patch_file": self.patch_file,
+            "checklist": [item.to_json() for item in self.checklist],
+            "notes": [note.to_json() for note in self.notes],
+            "publications": [publication.to_json() for publication in self.publications],
+            "pr_author": self.pr_author,
+        }
+
+    def serialize(self) -> str:
+        return json.dumps(self.to_json(), indent=2, ensure_ascii=False)
+
+    def publish_payload(
+        self,
+        general_comments: Optional[Dict[str, str]] = None,
+        event: Optional[str] = None,
+        autolinks: Optional[Dict[str, str]] = None,
+        include_held: bool = False,
+    ) -> dict:
+        """
+        Return the request body for submitting this review to GitHub.
+
+        Only the fields GitHub knows about are included; local bookkeeping
+        like the viewed files stays in the review file.
+
+        `general_comments` maps the IDs of comments that can't be anchored to
+        the diff to their text as general comments, which are appended to the
+        review body instead of being published inline.
+
+        `event` is one of the keys of `REVIEW_EVENTS`.
+
+        `autolinks` are applied to all bodies (see `apply_autolinks`).
+
+        Comments published before and unchanged since are left out, so that
+        publishing again doesn't post them twice. Edited ones are published
+        as new comments, since a review can't edit its earlier comments.
+        Held comments are left out too, unless `include_held` is set.
+        """
+        general_comments = general_comments or {}
+        autolinks = autolinks or {}
+        body = "\n\n".join([self.expand_placeholders(self.body), *general_comments.values()])
+        payload = {
+            "body": apply_autolinks(normalize_text(body).strip("\n"), autolinks),
+            "comments": [
+                self.comment_payload(comment, autolinks)
+                for comment in self.publishable_comments(include_held)
+                if comment.id not in general_comments
+            ],
+        }
+        if event is not None:
+            payload["event"] = REVIEW_EVENTS[event]
+        return payload
+
+    def publishable_comments(self, include_held: bool = False) -> List[Comment]:
+        """
+        Return the comments publishing would post: those that are ready (or
+        all of them with `include_held`) and not already published as is.
+        """
+        return [
+            comment for comment in self.comments
+            if (comment.ready or include_held) and comment.publish_state != "published"
+        ]
+
+    def held_comments(self) -> List[Comment]:
+        return [comment for comment in self.comments if not comment.ready]
+
+    def compliance_problems(
+        self, rules: Iterable[str], changed_paths: List[str], include_held: bool = False
+    ) -> List[str]:
+        """
+        Return what keeps this review from meeting the compliance `rules`
+        (some of `COMPLIANCE_RULES`), or an empty list if it meets them:
+        every checklist item checked, a review body, every file of
+        `changed_paths` viewed, and, if publishing would post comments, a
+        severity on at least one of them.
+        """
+        problems = []
+        if "checklist" in rules:
+            unchecked = [item.text for item in self.checklist if not item.checked]
+            if unchecked:
+                problems.append(f"unchecked checklist items: {', '.join(unchecked)}")
+        if "body" in rules and not self.body.strip():
+            problems.append("the review body is empty")
+        if "viewed" in rules:
+            unviewed = [path for path in changed_paths if not self.is_file_viewed(path)]
+            if unviewed:
+                problems.append(f"{len(unviewed)} files not viewed: {', '.join(unviewed)}")
+        if "severity" in rules:
+            publishable = self.publishable_comments(include_held)
+            if publishable and not any(comment.severity for comment in publishable):
+                problems.append("no comment has a severity")
+        return problems
+
+    def comment_payload(self, comment: Comment, autolinks: Optional[Dict[str, str]] = None) -> dict:
+        """
+        Return the representation of `comment` in the submission of this
+        review, with its placeholders expanded and `autolinks` applied.
+        """
+        payload = comment.publish_payload()
+        payload["body"] = apply_autolinks(self.expand_placeholders(payload["body"], comment), autolinks or {})
+        return payload
+
+    @property
+    def title(self) -> str:
+        if self.patch_file:
+            return f"Review of {os.path.basename(self.patch_file)}"
+        if self.local_only:
+            return f"Local review of {self.commit_range}"
+        return f"PR #{self.pr_number}"
+
+    @property
+    def post_url(self):
+        return f"{pull_request_url(self.owner, self.repo, self.pr_number)}/reviews"
+
+    @property
+    def web_url(self) -> str:
+        return pull_request_web_url(self.owner, self.repo, self.pr_number)
+
+    def permalink(self, commit_sha: str, path: str, start_line: int, end_line: int) -> str:
+        """
+        Return the GitHub URL of lines `start_line` to `end_line` of `path` as
+        of `commit_sha`.
+        """
+        anchor = f"L{start_line}" if start_line == end_line else f"L{start_line}-L{end_line}"
+        return f"{blob_web_url(self.owner, self.repo, commit_sha, path)}#{anchor}"
+
+    def publish_payloads(
+        self,
+        general_comments: Optional[Dict[str, str]] = None,
+        event: Optional[str] = None,
+        autolinks: Optional[Dict[str, str]] = None,
+        include_held: bool = False,
+        max_comments: int = MAX_REVIEW_COMMENTS,
+        max_body_length: int = MAX_REVIEW_BODY_LENGTH,
+    ) -> List[dict]:
+        """
+        Return the request bodies for submitting this review to GitHub: just
+        the one of `publish_payload`, unless the review has more than
+        `max_comments` inline comments or a longer body than
+        `max_body_length`, in which case it is split into several reviews.
+
+        Each part says which part it is. All but the last are published as
+        plain comments, so that the verdict is given once the whole review is
+        in.
+        """
+        payload = self.publish_payload(general_comments, event, autolinks, include_held)
+        if len(payload["comments"]) <= max_comments and len(payload["body"]) <= max_body_length:
+            return [payload]
+        # Leave room for the part marker.
+        bodies = split_text(payload["body"], max_body_length - 100)
+        comments = payload["comments"]
+        comment_chunks = [comments[start:start + max_comments] for start in range(0, len(comments), max_comments)]
+        count = max(len(bodies), len(comment_chunks))
+        payloads = []
+        for index in range(count):
+            marker = f"_(Part {index + 1} of {count} of this review.)_"
+            body = bodies[index] if index < len(bodies) else ""
+            part = {
+                "body": f"{body}\n\n{marker}" if body else marker,
+                "comments": comment_chunks[index] if index < len(comment_chunks) else [],
+            }
+            if index < count - 1:
+                part["event"] = REVIEW_EVENTS["comment"]
+            elif event is not None:
+                part["event"] = REVIEW_EVENTS[event]
+            payloads.append(part)
+        return payloads
+
+    def publish(
+        self,
+        token,
+        general_comments: Optional[Dict[str, str]] = None,
+        event: Optional[str] = None,
+        autolinks: Optional[Dict[str, str]] = None,
+        include_held: bool = False,
+    ) -> List[Tuple[dict, Optional[requests.Response]]]:
+        """
+        Submit this review to GitHub, in several parts if it is too big for
+        one (see `publish_payloads`). Stops at the first part GitHub rejects.
+
+        Returns each part with GitHub's response to it, or None for the parts
+        that weren't submitted.
+        """
+        results: List[Tuple[dict, Optional[requests.Response]]] = []
+        rejected = False
+        payloads = self.publish_payloads(general_comments, event, autolinks, include_held)
+        for index, payload in enumerate(payloads):
+            response = None
+            if not rejected:
+                logger.debug(
+                    "Publishing part %d of %d of %s:\n%s", index + 1, len(payloads), self.title,
+                    json.dumps(payload, indent=2),
+                )
+                response = api_request(
+                    "POST",
+                    self.post_url,
+                    data=json.dumps(payload, indent=2).encode("utf-8"),
+                    headers={
+                        "Accept": "application/vnd.github+json",
+                        "Authorization": f"token {token}",
+                    },
+                )
+                rejected = not response.ok
+            results.append((payload, response))
+        return results
+
+    def add_comment(self, comment: Comment):
+        comment.touch()
+        self.comments.append(comment)
+        self.log_action("add", comment)
+
+    def add_note(self, note: Comment):
+        note.touch()
+        self.notes.append(note)
+        self.log_action("add-note", note)
+
+    def set_body(self, body: str):
+        self.body = body
+
+    def file_path(self) -> str:
+        """
+        Return the path to the file this review is saved in.
+        """
+        if self.remote_only:
+            return get_remote_review_file(self.owner, self.repo, self.pr_number)
+        if self.patch_file:
+            return get_patch_review_file(self.patch_file)
+        if self.local_only:
+            return get_local_review_file(self.commit_range)
+        return get_review_file(self.pr_number)
+
+    def save(self):
+        if self.read_only:
+            return
+        review_file = self.file_path()
+        plaintext = serialized = self.serialize()
+        # Encrypt everything first, so that a missing key fails before either
+        # file is written.
+        if encryption_enabled(self.remote_only):
+            serialized = encrypt_text(serialized)
+        history = "".join(self.history_line(entry) for entry in self.unsaved_history)
+        with open(review_file, "w", encoding="utf-8") as f:
+            f.write(serialized)
+        self.file_mtime = os.path.getmtime(review_file)
+        self.baseline = json.loads(plaintext)
+        if history:
+            with open(self.history_file(), "a", encoding="utf-8") as f:
+                f.write(history)
+        self.unsaved_history = []
+
+    def history_file(self) -> str:
+        """
+        Return the path to the action log of this review, next to the review
+        file.
+        """
+        return re.sub(r"-review\.json$", "-history.jsonl", self.file_path())
+
+    def log_action(self, action: str, comment: Optional[Comment] = None, details: Optional[str] = None):
+        """
+        Add an entry to the action log of this review, which is only ever
+        appended to, so that what happened to a comment can be traced even
+        after it was deleted. Entries are written (and encrypted like the
+        review file) the next time the review is saved.
+        """
+        if self.read_only:
+            return
+        entry = HistoryEntry(now_timestamp(), action, details=details)
+        if comment is not None:
+            entry.comment_id, entry.path, entry.line, entry.body = comment.id, comment.path, comment.line, comment.body
+        self.unsaved_history.append(entry)
+
+    def history_line(self, entry: HistoryEntry) -> str:
+        line = json.dumps(entry.to_json(), ensure_ascii=False)
+        if encryption_enabled(self.remote_only):
+            line = json.dumps({"encrypted": encrypt_text(line)})
+        return line + "\n"
+
+    def rewrite_history(self, history: List[HistoryEntry]):
+        """
+        Write the action log again with the same entries, e.g. to encrypt or
+        decrypt it. This is the only time the log is not appended to.
+        """
+        if not history:
+            return
+        with open(self.history_file(), "w", encoding="utf-8") as f:
+            f.write("".join(self.history_line(entry) for entry in history))
+
+    def history(self) -> List[HistoryEntry]:
+        """
+        Return the entries of the action log of this review, oldest first.
+
+        Raises `ValueError` if encrypted entries can't be decrypted.
+        """
+        try:
+            with open(self.history_file(), encoding="utf-8") as f:
+                lines = [line for line in f.read().split("\n") if line]
+        except FileNotFoundError:
+            return []
+        entries = []
+        for line in lines:
+            json_repr = json.loads(line)
+            if "encrypted" in json_repr:
+                json_repr = json.loads(decrypt_text(json_repr["encrypted"]))
+            entries.append(HistoryEntry.from_json(json_repr))
+        return entries
+
+    def layout_file(self) -> str:
+        """
+        Return the path to the file the window layout of this review is saved
+        in, next to the review file.
+        """
+        return re.sub(r"-review\.json$", "-layout.json", self.file_path())
+
+    def save_layout(self, layout: list):
+        """
+        Save the window layout of this review, a `winlayout()` tree whose
+        leaves describe the file shown in each window.
+        """
+        with open(self.layout_file(), "w", encoding="utf-8") as f:
+            json.dump(layout, f, indent=2)
+
+    def load_layout(self) -> Optional[list]:
+        try:
+            with open(self.layout_file(), encoding="utf-8") as f:
+                return json.load(f)
+        except FileNotFoundError:
+            return None
+
+    def changed_on_disk(self) -> bool:
+        """
+        Whether the review file was modified since this review was loaded or
+        last saved.
+        """
+        try:
+            return os.path.getmtime(self.file_path()) != self.file_mtime
+        except OSError:
+            return False
+
+    def reload(self) -> "Review":
+        """
+        Return this review as currently saved in its file.
+        """
+        return Review.load(self.file_path())
+
+    def merged_with(self, other: "Review") -> "Review":
+        """
+        Return the three-way merge of this review and `other` (typically this
+        review as saved by someone else since it was loaded), against the
+        review as it was loaded or last saved: the body, the comments and
+        notes (field by field), the viewed files and the checklist keep the
+        additions, deletions and edits of both. Where both edited the same
+        field of a comment, the most recently edited version wins; for
+        anything else, this review's. Publications are only ever added.
+        """
+        if self.baseline is not None:
+            base = Review.from_json(self.baseline)
+        else:
+            base = Review(self.owner, self.repo, self.pr_number, "", [])
+        merged = Review.from_json(other.to_json())
+        merged.file_mtime = other.file_mtime
+        merged.baseline = other.baseline
+        merged.unsaved_history = self.unsaved_history
+        merged.body = self.body if self.body != base.body else other.body
+        merged.last_reviewed_head_sha = (
+            self.last_reviewed_head_sha
+            if self.last_reviewed_head_sha != base.last_reviewed_head_sha
+            else other.last_reviewed_head_sha
+        )
+
+        def newer(ours: Comment, theirs: Comment) -> bool:
+            return (ours.updated_at or "") >= (theirs.updated_at or "")
+
+        merged.comments = merge_lists(base.comments, self.comments, merged.comments, lambda c: c.id, newer)
+        merged.notes = merge_lists(base.notes, self.notes, merged.notes, lambda c: c.id, newer)
+        merged.viewed_files = merge_lists(
+            base.viewed_files, self.viewed_files, merged.viewed_files, lambda path: path, lambda ours, theirs: True
+        )
+        merged.checklist = merge_lists(
+            base.checklist, self.checklist, merged.checklist, lambda item: item.text, lambda ours, theirs: True
+        )
+        merged.publications = sorted(
+            merged.publications + [p for p in self.publications if p not in merged.publications],
+            key=lambda publication: publication.published_at,
+        )
+        return merged
+
+    @staticmethod
+    def published(owner: str, repo: str, pr_number: int) -> "Review":
+        """
+        Return the reviews published on a PR on GitHub as a single read-only
+        review: the inline comments of all reviewers, and their review
+        bodies one after the other.
+
+        Raises `requests.HTTPError` if the request fails.
+        """
+        review = Review(owner, repo, pr_number, "", [], read_only=True)
+        review.comments = [Comment.from_remote_json(comment) for comment in review.fetch_remote_comments()]
+        review.body = "\n\n".join(
+            f"**@{submitted['user']['login']}** ({submitted['state'].lower().replace('_', ' ')}):\n\n{submitted['body']}"
+            for submitted in get_pull_request_reviews(owner, repo, pr_number, get_api_token())
+            if submitted.get("body")
+        )
+        return review
+
+    @staticmethod
+    def load(review_file: str) -> "Review":
+        """
+        Load a review file, decrypting it if it is encrypted.
+
+        Raises `ValueError` if an encrypted file can't be decrypted.
+        """
+        with open(review_file, encoding="utf-8") as f:
+            serialized = f.read()
+        if is_encrypted(serialized):
+            serialized = decrypt_text(serialized)
+        review = Review.deserialize(serialized)
+        review.file_mtime = os.path.getmtime(review_file)
+        review.baseline = json.loads(review.serialize())
+        return review
+
+    @staticmethod
+    def from_json(json_repr: dict) -> "Review":
+        return Review(
+            json_repr["owner"],
+            json_repr["repo"],
+            json_repr["pr_number"],
+            json_repr["body"],
+            comments_from_json(json_repr["comments"]),
+            json_repr.get("viewed_files", []),
+            json_repr.get("remote_only", False),
+            json_repr.get("commit_range"),
+            json_repr.get("last_reviewed_head_sha"),
+            json_repr.get("local_only", False),
+            json_repr.get("patch_file"),
+            [ChecklistItem.from_json(item) for item in json_repr.get("checklist", [])],
+            comments_from_json(json_repr.get("notes", [])),
+            [Publication.from_json(publication) for publication in json_repr.get("publications", [])],
+            json_repr.get("pr_author"),
+        )
+
+    @staticmethod
+    def deserialize(serialized: str) -> "Review":
+        return Review.from_json(json.loads(serialized))
+
+    def get_comment_at_position(
+        self, path: str, line: int, side: Side = "RIGHT", patch_index: Optional[int] = None
+    ) -> Optional[Comment]:
+        """
+        Return the first comment in this review whose span contains the
+        requested file path and line on the given side of the diff (and, for
+        reviews of patch series, in the given patch).
+        """
+        eligible_comments = self.get_comments_at_position(path, line, side, patch_index)
+        if eligible_comments:
+            return eligible_comments[0]
+        return None
+
+    def get_comments_at_position(
+        self, path: str, line: int, side: Side = "RIGHT", patch_index: Optional[int] = None
+    ) -> List[Comment]:
+        """
+        Return all the comments whose span contains the given line, in the
+        order they were written.
+        """
+        return comments_at_position(self.comments, path, line, side, patch_index)
+
+    def get_notes_at_position(
+        self, path: str, line: int, side: Side = "RIGHT", patch_index: Optional[int] = None
+    ) -> List[Comment]:
+        return comments_at_position(self.notes, path, line, side, patch_index)
+
+    def commit_range_bounds(self) -> Optional[Tuple[str, str]]:
+        """
+        Return the start and end revisions of the commit range this review is
+        scoped to, or None if it covers the whole PR.
+        """
+        if self.commit_range is None:
+            return None
+        start, end = self.commit_range.split("..")
+        return start, end
+
+    def base_sha(self) -> Optional[str]:
+        """
+        Return the SHA of the commit this PR is diffed against, or the start
+        of the commit range if the review is scoped to one.
+
+        The base is looked up on GitHub when possible, once for each PR head.
+        If that fails (e.g. no network access), fall back to the merge base
+        with the remote's default branch.
+        """
+        bounds = self.commit_range_bounds()
+        if bounds is not None:
+            return resolve_revision(bounds[0]) or bounds[0]
+        if self.resolved_base is not None and self.resolved_base[0] == self.known_head_sha():
+            return self.resolved_base[1]
+        try:
+            pull_request = self.pull_request()
+        except requests.RequestException:
+            return get_merge_base()
+        # The base branch may have moved on since the PR branched off it (or,
+        # for stacked PRs, the parent PR may have been updated), so diff
+        # against the merge base of the two, like GitHub does.
+        base, head = pull_request["base"]["sha"], pull_request["head"]["sha"]
+        metadata = self.read_metadata_cache()
+        merge_bases = metadata.get("merge_bases", {})
+        if head in merge_bases:
+            merge_base = merge_bases[head]
+        else:
+            merge_base = get_merge_base(base, head)
+            if merge_base is None:
+                try:
+                    comparison = get_comparison(self.owner, self.repo, base, head, get_api_token())
+                    merge_base = comparison["merge_base_commit"]["sha"]
+                except requests.RequestException:
+                    self.resolved_base = (head, base)
+                    return base
+            self.write_metadata_cache({**metadata, "merge_bases": {head: merge_base}})
+        self.resolved_base = (head, merge_base)
+        return merge_base
+
+    def stack_parent(self) -> Optional[int]:
+        """
+        Return the number of the open PR this PR is stacked on, i.e. whose
+        head branch is this PR's base branch, or None if there is none.
+
+        Raises `requests.HTTPError` if the request fails.
+        """
+        token = get_api_token()
+        pull_request = self.pull_request()
+        base = pull_request["base"]
+        if base["ref"] == base["repo"]["default_branch"]:
+            return None
+        parents = list_pull_requests(
+            self.owner, self.repo, token, head=f"{base['repo']['owner']['login']}:{base['ref']}"
+        )
+        return parents[0]["number"] if parents else None
+
+    def stack_children(self) -> List[int]:
+        """
+        Return the numbers of the open PRs stacked on this PR, i.e. whose base
+        branch is this PR's head branch.
+
+        Raises `requests.HTTPError` if the request fails.
+        """
+        token = get_api_token()
+        pull_request = self.pull_request()
+        children = list_pull_requests(self.owner, self.repo, token, base=pull_request["head"]["ref"])
+        return [child["number"] for child in children]
+
+    def head_sha(self) -> str:
+        """
+        Return the SHA of the latest commit of this PR on GitHub, or the end
+        of the commit range if the review is scoped to one.
+
+        Raises `requests.HTTPError` if the request fails.
+        """
+        bounds = self.commit_range_bounds()
+        if bounds is not None:
+            return resolve_revision(bounds[1]) or bounds[1]
+        return self.pull_request()["head"]["sha"]
+
+    def mark_published(self, include_held: bool = False, comment_ids: Optional[Iterable[str]] = None):
+        """
+        Record the current body of every published comment as published:
+        all of them with `include_held`, only the ready ones otherwise, and
+        only those with `comment_ids` if given.
+        """
+        for comment in self.comments:
+            if (comment.ready or include_held) and (comment_ids is None or comment.id in comment_ids):
+                comment.published_body = comment.decorated_body
+        self.save()
+
+    def record_publication(self, results: List[Tuple[dict, Optional[requests.Response]]], head_sha: Optional[str]):
+        """
+        Record a submission of this review, given the parts `publish`
+        submitted and GitHub's responses.
+        """
+        responses = [response for _, response in results if response is not None]
+        last = responses[-1] if responses else None
+        ok = bool(responses) and len(responses) == len(results) and all(response.ok for response in responses)
+        url = author = None
+        if ok:
+            try:
+                submitted = last.json()
+                url = submitted.get("html_url")
+                author = (submitted.get("user") or {}).get("login")
+            except ValueError:
+                pass
+        self.publications.append(Publication(
+            now_timestamp(),
+            results[-1][0].get("event") if results else None,
+            head_sha,
+            len(results),
+            last.status_code if last is not None else None,
+            ok,
+            sum(len(payload.get("comments", [])) for payload, _ in results),
+            url,
+            author,
+        ))
+        publication = self.publications[-1]
+        outcome = f"{publication.comment_count} comments, HTTP {publication.status}"
+        self.log_action("publish" if publication.ok else "publish-failed", details=outcome)
+        self.save()
+
+    def audit_records(self, reviewer: str) -> List[dict]:
+        """
+        Return the records of `:ExportReview audit`, keyed by `AUDIT_COLUMNS`:
+        one for the review (its body and who wrote it), then one per comment,
+        checklist item and publication. Private notes are left out.
+        """
+        common = {
+            "reviewer": reviewer,
+            "repository": f"{self.owner}/{self.repo}" if self.owner else "",
+            "target": self.title if self.pr_number is None else self.web_url,
+            "exported_at": now_timestamp(),
+        }
+        records = [{**common, "record": "review", "body": self.body}]
+        for comment in self.comments:
+            records.append({
+                **common,
+                "record": "comment",
+                "id": comment.id,
+                "path": comment.path,
+                "start_line": comment.start_line,
+                "line": comment.line,
+                "side": comment.side,
+                "severity": comment.severity,
+                "labels": ", ".join(comment.labels),
+                "ready": comment.ready,
+                "publish_state": comment.publish_state,
+                "created_at": comment.created_at,
+                "updated_at": comment.updated_at,
+                "body": comment.body,
+            })
+        for item in self.checklist:
+            records.append({
+                **common, "record": "checklist", "body": item.text, "checked": item.checked, "required": item.required
+            })
+        for publication in self.publications:
+            records.append({**common, "record": "publication", **publication.to_json()})
+        return records
+
+    def mark_reviewed(self, head_sha: str):
+        """
+        Record `head_sha` as the PR head this review was last published against.
+        """
+        self.last_reviewed_head_sha = head_sha
+        self.save()
+
+    def fetch_file_contents(self, path: str, side: Side) -> Optional[str]:
+        """
+        Fetch the contents of the file at `path` from GitHub, as of the PR's
+        base commit for the LEFT side or its head commit for the RIGHT side.
+        Returns None if the file does not exist on that side.
+        """
+        ref = self.base_sha() if side == "LEFT" else self.head_sha()
+        return get_file_contents(self.owner, self.repo, path, ref, get_api_token())
+
+    def fetch_codeowners(self) -> List[OwnershipRule]:
+        """
+        Return the rules of the CODEOWNERS file as of the PR's base commit,
+        or an empty list if the repository has none.
+        """
+        for location in CODEOWNERS_LOCATIONS:
+            if self.remote_only:
+                text = self.fetch_file_contents(location, "LEFT")
+            else:
+                text = get_file_at_revision(self.base_sha() or "HEAD", location)
+            if text is not None:
+                return parse_codeowners(text)
+        return []
+
+    def fetch_ignore_patterns(self) -> List[str]:
+        """
+        Return the patterns of the `.reviewerignore` file as of the PR's base
+        commit, or an empty list if the repository has none.
+        """
+        if self.remote_only:
+            text = self.fetch_file_contents(IGNORE_FILE, "LEFT")
+        else:
+            text = get_file_at_revision(self.base_sha() or "HEAD", IGNORE_FILE)
+        if text is None:
+            return []
+        return [line.strip() for line in text.split("\n") if line.strip() and not line.startswith("#")]
+
+    def changed_files_cache_key(self) -> str:
+        """
+        The key the diff of this PR is cached under: it only changes with the
+        commits it covers, i.e. the commit range or the PR head.
+        """
+        head = self.head_sha()
+        return f"{self.base_sha()}..{head}" if self.commit_range_bounds() is not None else head
+
+    def fetch_changed_files(self, progress: Optional[Callable[[int], None]] = None) -> List[ChangedFile]:
+        """
+        Fetch the files changed by this PR (or by its commit range), along
+        with their patches, from GitHub or from the cache.
+
+        Big PRs are downloaded a page at a time: each page's patches are
+        indexed as it arrives, and `progress` is called with the number of
+        files fetched so far. The hunk index is cached along with the files,
+        see `fetch_hunk_index`.
+        """
+        bounds = self.commit_range_bounds()
+        if self.patch_file:
+            return self.patch_changed_files()
+        if self.local_only:
+            return get_local_changed_files(*bounds)
+        key = self.changed_files_cache_key()
+        metadata = self.read_metadata_cache()
+        files = metadata.get("changed_files", {}).get(key)
+        if files is None:
+            files = []
+            hunk_index: Dict[str, List[Tuple[int, int, int, int]]] = {}
+
+            def on_page(page: List[dict]):
+                for f in page:
+                    if f.get("patch") is not None:
+                        hunk_index[f["filename"]] = hunk_ranges(f["patch"])
+                files.extend(page)
+                if progress is not None:
+                    progress(len(files))
+
+            if bounds is not None:
+                comparison = get_comparison(self.owner, self.repo, self.base_sha(), self.head_sha(), get_api_token())
+                on_page(comparison["files"])
+            else:
+                get_pull_request_files(self.owner, self.repo, self.pr_number, get_api_token(), on_page)
+            self.write_metadata_cache({**metadata, "changed_files": {key: files}, "hunk_index": {key: hunk_index}})
+        return [ChangedFile.from_json(f) for f in files]
+
+    def fetch_hunk_index(self) -> Dict[str, List[Tuple[int, int, int, int]]]:
+        """
+        Return the ranges of lines shown by the hunks of each changed file
+        (see `hunk_ranges`), from the cache when possible so that the patches
+        of big PRs don't have to be parsed again. Files without a patch (e.g.
+        binary files, or local reviews) are left out.
+        """
+        if not self.patch_file and not self.local_only:
+            key = self.changed_files_cache_key()
+            index = self.read_metadata_cache().get("hunk_index", {}).get(key)
+            if index is not None:
+                return {path: [tuple(r) for r in ranges] for path, ranges in index.items()}
+        return {
+            changed_file.path: hunk_ranges(changed_file.patch)
+            for changed_file in self.fetch_changed_files()
+            if changed_file.patch is not None
+        }
+
+    def fetch_file_diff(self, changed_file: ChangedFile) -> Optional[FileDiff]:
+        """
+        Return the hunks of the change to `changed_file`, from the patch
+        GitHub sent along with it or, for local reviews, from git.
+        """
+        if changed_file.patch is not None:
+            old_path = changed_file.previous_path or changed_file.path
+            text = f"--- a/{old_path}\n+++ b/{changed_file.path}\n{changed_file.patch}"
+        elif self.local_only and not self.patch_file:
+            base, head = self.commit_range_bounds()
+            text = subprocess.run(
+                ["git", "diff", "-M", base, head, "--", changed_file.path],
+                stdout=subprocess.PIPE,
+                stderr=subprocess.DEVNULL,
+            ).stdout.decode("utf-8", errors="replace")
+        else:
+            return None
+        file_diffs = parse_unified_diff(text)
+        return file_diffs[0] if file_diffs else None
+
+    def find_comments_outside_diff(self) -> List[Comment]:
+        """
+        Return the comments on lines that aren't part of the
+        PR diff, which GitHub refuses to anchor inline comments to.
+
+        Comments on files whose diff isn't available (e.g. binary files) are
+        assumed to be fine.
+        """
+        changed_files = {changed_file.path: changed_file for changed_file in self.fetch_changed_files()}
+        hunk_index = self.fetch_hunk_index()
+        file_diffs: Dict[str, Optional[FileDiff]] = {}
+        outside = []
+        for comment in self.comments:
+            if comment.path not in changed_files:
+                outside.append(comment)
+                continue
+            lines = [comment.line] if comment.start_line is None else [comment.start_line, comment.line]
+            if comment.path in hunk_index:
+                if not all(in_hunk_ranges(hunk_index[comment.path], line, comment.side) for line in lines):
+                    outside.append(comment)
+                continue
+            if comment.path not in file_diffs:
+                file_diffs[comment.path] = self.fetch_file_diff(changed_files[comment.path])
+            file_diff = file_diffs[comment.path]
+            if file_diff is None:
+                continue
+            if any(file_diff.find_line(line, comment.side) is None for line in lines):
+                outside.append(comment)
+        return outside
+
+    def general_comment(self, comment: Comment) -> str:
+        """
+        Return the text of `comment` as a general comment of the review body,
+        quoting the commented code since it can't be shown inline.
+        """
+        try:
+            contents = self.fetch_file_contents(comment.path, comment.side)
+        except requests.RequestException:
+            contents = None
+        text = f"**{comment_location(comment)}**"
+        if contents is not None:
+            file_lines = contents.split("\n")
+            quoted = file_lines[(comment.start_line or comment.line) - 1:comment.line]
+            text += "\n\n```\n" + "\n".join(quoted) + "\n```"
+        return f"{text}\n\n{self.expand_placeholders(comment.decorated_body, comment)}"
+
+    def expand_placeholders(self, text: str, comment: Optional[Comment] = None) -> str:
+        """
+        Replace the placeholders in `text`: `{{pr}}` with the PR number and,
+        in the body of `comment`, `{{file}}` with its path, `{{line}}` with its
+        line (or range of lines, e.g. `10-12`) and `{{selection}}` with the
+        commented code. Placeholders that don't apply are left as they are.
+        """
+        def expand(match) -> str:
+            name = match.group(1)
+            if name == "pr" and self.pr_number is not None:
+                return str(self.pr_number)
+            if comment is None:
+                return match.group(0)
+            if name == "file":
+                return comment.path
+            if name == "line":
+                return str(comment.line) if comment.start_line is None else f"{comment.start_line}-{comment.line}"
+            if name == "selection":
+                try:
+                    contents = self.fetch_file_contents(comment.path, comment.side)
+                except requests.RequestException:
+                    contents = None
+                if contents is not None:
+                    return comment.quote(contents.split("\n"))
+            return match.group(0)
+
+        return PLACEHOLDER_PATTERN.sub(expand, text)
+
+    def fetch_remote_comments(self) -> List[dict]:
+        """
+        Fetch the review comments already posted on this PR on GitHub.
+        """
+        if self.local_only:
+            return []
+        return get_pull_request_comments(self.owner, self.repo, self.pr_number, get_api_token())
+
+    def fetch_remote_comment_at(self, path: str, line: int, side: Side) -> Optional[dict]:
+        """
+        Fetch the comment posted on GitHub that covers `line` of `path` on
+        the given side, if any. With several, the latest one is returned.
+        """
+        found = None
+        for remote_comment in self.fetch_remote_comments():
+            # Comments on lines that are no longer part of the diff have no line.
+            if remote_comment["path"] != path or remote_comment.get("line") is None:
+                continue
+            start_line = remote_comment.get("start_line") or remote_comment["line"]
+            if remote_comment.get("side", "RIGHT") == side and start_line <= line <= remote_comment["line"]:
+                found = remote_comment
+        return found
+
+    def fetch_state(self) -> PullRequestState:
+        """
+        Fetch the current head, number of review comments and review states
+        of this PR.
+
+        Raises `requests.HTTPError` if the request fails.
+        """
+        token = get_api_token()
+        pull_request = self.pull_request(refresh=True)
+        return PullRequestState(
+            pull_request["head"]["sha"],
+            pull_request["review_comments"],
+            latest_review_states(get_pull_request_reviews(self.owner, self.repo, self.pr_number, token)),
+        )
+
+    def fetch_review_decisions(self) -> ReviewDecisions:
+        """
+        Fetch who approved this PR, who requested changes, and whose review
+        is still awaited.
+
+        Raises `requests.HTTPError` if the request fails.
+        """
+        pull_request = self.pull_request(refresh=True)
+        review_states = latest_review_states(
+            get_pull_request_reviews(self.owner, self.repo, self.pr_number, get_api_token())
+        )
+        organization = pull_request["base"]["repo"]["owner"]["login"]
+        return ReviewDecisions(
+            [reviewer for reviewer, state in review_states.items() if state == "APPROVED"],
+            [reviewer for reviewer, state in review_states.items() if state == "CHANGES_REQUESTED"],
+            [reviewer for reviewer, state in review_states.items() if state == "COMMENTED"],
+            [user["login"] for user in pull_request.get("requested_reviewers", [])]
+            + [f"{organization}/{team['slug']}" for team in pull_request.get("requested_teams", [])],
+        )
+
+    def request_reviewers(self, reviewers: List[str]):
+        """
+        Ask `reviewers` to review this PR. Entries like `org/team` request a
+        review from a team of the PR's organization.
+
+        Raises `requests.HTTPError` if the request fails.
+        """
+        users = [reviewer.lstrip("@") for reviewer in reviewers if "/" not in reviewer]
+        teams = [reviewer.split("/", 1)[1] for reviewer in reviewers if "/" in reviewer]
+        request_reviewers(self.owner, self.repo, self.pr_number, users, teams, get_api_token())
+
+    def directory(self) -> str:
+        """
+        Return the directory this review is stored in, which also holds the
+        caches shared by the reviews of the same repository.
+        """
+        if self.remote_only:
+            return get_remote_review_directory(self.owner, self.repo)
+        return get_review_directory()
+
+    def fetch_collaborators(self, max_age: float = 24 * 3600) -> List[str]:
+        """
+        Return the logins of the repository's collaborators, from a cache file
+        refreshed from GitHub once it is older than `max_age` seconds.
+
+        Raises `requests.HTTPError` if the cache has to be refreshed and the
+        request fails.
+        """
+        if self.local_only:
+            return []
+        cache_file = os.path.join(self.directory(), "collaborators.json")
+        if os.path.exists(cache_file) and time.time() - os.path.getmtime(cache_file) < max_age:
+            with open(cache_file, encoding="utf-8") as f:
+                return json.load(f)
+        collaborators = list_collaborators(self.owner, self.repo, get_api_token())
+        with open(cache_file, "w", encoding="utf-8") as f:
+            json.dump(collaborators, f, indent=2)
+        return collaborators
+
+    def metadata_cache_file(self) -> str:
+        return os.path.join(self.directory(), f"{self.pr_number}-metadata.json")
+
+    def read_metadata_cache(self) -> dict:
+        """
+        Return the cached metadata of this PR: the PR itself as last fetched
+        from GitHub, and values derived from it keyed by the PR head they
+        were computed for.
+        """
+        cache_file = self.metadata_cache_file()
+        if not os.path.exists(cache_file):
+            return {}
+        with open(cache_file, encoding="utf-8") as f:
+            return json.load(f)
+
+    def write_metadata_cache(self, metadata: dict):
+        # Written aside and moved into place, so that a job writing it at the
+        # same time as the editor never leaves it half-written.
+        cache_file = self.metadata_cache_file()
+        fd, temporary_file = tempfile.mkstemp(dir=os.path.dirname(cache_file), suffix=".tmp")
+        with os.fdopen(fd, "w", encoding="utf-8") as f:
+            json.dump(metadata, f, indent=2)
+        os.replace(temporary_file, cache_file)
+
+    def clear_metadata_cache(self):
+        if os.path.exists(self.metadata_cache_file()):
+            os.remove(self.metadata_cache_file())
+
+    def pull_request(self, refresh: bool = False) -> dict:
+        """
+        Return the PR metadata from GitHub. The copy cached in the review
+        directory is used as is for `PULL_REQUEST_CACHE_TTL` seconds after it
+        was last checked, unless `refresh` is set; after that, it is checked
+        with a conditional request (on its ETag), and kept when GitHub can't
+        be reached.
+
+        When the PR head moved, the values derived for previous heads are
+        dropped.
+
+        Raises `requests.HTTPError` if the request fails and there is no
+        cached copy to fall back to (or `refresh` is set).
+        """
+        metadata = self.read_metadata_cache()
+        cached = metadata.get("pull_request")
+        fresh = time.time() - metadata.get("pull_request_checked_at", 0) < PULL_REQUEST_CACHE_TTL
+        if cached is not None and fresh and not refresh:
+            self.pr_author = (cached.get("user") or {}).get("login")
+            return cached
+        etag = metadata.get("pull_request_etag") if cached is not None else None
+        try:
+            pull_request, etag = get_pull_request_if_changed(
+                self.owner, self.repo, self.pr_number, get_api_token(), etag
+            )
+        except requests.RequestException:
+            if cached is None or refresh:
+                raise
+            return cached
+        if pull_request is None:
+            pull_request = cached
+        elif (cached or {}).get("head", {}).get("sha") != pull_request["head"]["sha"]:
+            metadata = {}
+        metadata = {
+            **metadata,
+            "pull_request": pull_request,
+            "pull_request_etag": etag,
+            "pull_request_checked_at": time.time(),
+        }
+        self.write_metadata_cache(metadata)
+        self.pr_author = (pull_request.get("user") or {}).get("login")
+        return pull_request
+
+    def known_head_sha(self) -> Optional[str]:
+        """
+        Return the PR head as last fetched from GitHub (see `pull_request`),
+        without fetching anything. None if it isn't known.
+        """
+        try:
+            return self.read_metadata_cache().get("pull_request", {}).get("head", {}).get("sha")
+        except (OSError, ValueError):
+            return None
+
+    def known_pr_author(self) -> Optional[str]:
+        """
+        Return the login of the PR's author as recorded in this review or, for
+        reviews saved before it was recorded, in the cached PR metadata,
+        without fetching anything. None if it isn't known.
+        """
+        if self.pr_author is not None or self.pr_number is None:
+            return self.pr_author
+        try:
+            return (self.read_metadata_cache().get("pull_request", {}).get("user") or {}).get("login")
+        except (OSError, ValueError):
+            return None
+
+    def fetch_labels(self) -> List[str]:
+        """
+        Fetch the labels that can be applied to this PR.
+        """
+        return list_labels(self.owner, self.repo, get_api_token())
+
+    def add_label(self, label: str):
+        add_labels(self.owner, self.repo, self.pr_number, [label], get_api_token())
+
+    def remove_label(self, label: str):
+        remove_label(self.owner, self.repo, self.pr_number, label, get_api_token())
+
+    def suggest_reviewers(self, limit: int = 5) -> List[ReviewerSuggestion]:
+        """
+        Blame the lines changed by this PR at its base, and rank the authors of
+        those lines by how many they wrote, favoring recent work. The PR's
+        author is left out.
+
+        Requires the PR's commits to be available locally.
+        """
+        base, head = self.base_sha(), self.head_sha()
+        diff = subprocess.check_output(["git", "diff", "-U0", "-M", base, head]).decode("utf-8", errors="replace")
+        now = time.time()
+        suggestions: Dict[str, ReviewerSuggestion] = {}
+        for file_diff in parse_unified_diff(diff):
+            if file_diff.old_path is None:
+                continue
+            for hunk in file_diff.hunks:
+                if hunk.old_count == 0:
+                    # Pure additions: blame the line they were inserted after.
+                    start_line = end_line = max(hunk.old_start, 1)
+                else:
+                    start_line, end_line = hunk.old_start, hunk.old_start + hunk.old_count - 1
+                for entry in get_blame(base, file_diff.old_path, start_line, end_line):
+                    # Halve the weight of lines for every six months since they were written.
+                    weight = 0.5 ** ((now - entry.author_time) / (180 * 24 * 3600))
+                    suggestion = suggestions.setdefault(
+                        entry.author, ReviewerSuggestion(entry.author, None, 0, 0, entry.commit_sha, 0)
+                    )
+                    suggestion.score += entry.line_count * weight
+                    suggestion.lines += entry.line_count
+                    if entry.author_time > suggestion.last_commit_time:
+                        suggestion.last_commit_sha = entry.commit_sha
+                        suggestion.last_commit_time = entry.author_time
+
+        ranked = sorted(suggestions.values(), key=lambda suggestion: suggestion.score, reverse=True)
+        if self.local_only:
+            return ranked[:limit]
+
+        token = get_api_token()
+        author = self.pull_request()["user"]["login"]
+        results: List[ReviewerSuggestion] = []
+        for suggestion in ranked:
+            if len(results) == limit:
+                break
+            try:
+                commit_author = get_commit(self.owner, self.repo, suggestion.last_commit_sha, token)["author"]
+            except requests.HTTPError:
+                commit_author = None
+            # Commits by emails not linked to any GitHub account have no author.
+            suggestion.login = commit_author["login"] if commit_author else None
+            if suggestion.login == author:
+                continue
+            if suggestion.login is not None and any(other.login == suggestion.login for other in results):
+                # The same person committing under several names.
+                continue
+            results.append(suggestion)
+        return results
+
+    def fetch_merge_status(self) -> MergeStatus:
+        """
+        Fetch the mergeability of this PR and the CI status of its head.
+
+        Raises `requests.HTTPError` if the request fails.
+        """
+        token = get_api_token()
+        pull_request = self.pull_request(refresh=True)
+        head_sha = pull_request["head"]["sha"]
+        failing, pending = [], []
+        combined_status = get_combined_status(self.owner, self.repo, head_sha, token)
+        for status in combined_status["statuses"]:
+            if status["state"] in ("failure", "error"):
+                failing.append(status["context"])
+            elif status["state"] == "pending":
+                pending.append(status["context"])
+        check_runs = get_check_runs(self.owner, self.repo, head_sha, token)
+        for check_run in check_runs:
+            if check_run["status"] != "completed":
+                pending.append(check_run["name"])
+            elif check_run["conclusion"] not in ("success", "neutral", "skipped"):
+                failing.append(check_run["name"])
+        if failing:
+            ci_state = "failure"
+        elif pending:
+            ci_state = "pending"
+        elif combined_status["statuses"] or check_runs:
+            ci_state = "success"
+        else:
+            ci_state = "none"
+        return MergeStatus(head_sha, pull_request["mergeable"], pull_request["mergeable_state"], ci_state, failing)
+
+    def merge(self, method: str, head_sha: str) -> str:
+        """
+        Merge this PR, provided its head is still `head_sha`. Returns GitHub's
+        message about the merge.
+
+        Raises `requests.HTTPError` if the request fails.
+        """
+        return merge_pull_request(self.owner, self.repo, self.pr_number, method, head_sha, get_api_token())["message"]
+
+    def set_state(self, state: Literal["open", "closed"]):
+        """
+        Close or reopen this PR.
+
+        Raises `requests.HTTPError` if the request fails.
+        """
+        update_pull_request(self.owner, self.repo, self.pr_number, get_api_token(), state=state)
+
+    def fetch_previous_reviewers(self) -> List[str]:
+        """
+        Fetch the logins of the users who already submitted a review of this
+        PR, other than its author.
+
+        Raises `requests.HTTPError` if the request fails.
+        """
+        token = get_api_token()
+        author = self.pull_request()["user"]["login"]
+        reviewers = []
+        for submitted_review in get_pull_request_reviews(self.owner, self.repo, self.pr_number, token):
+            reviewer = submitted_review["user"]["login"]
+            if reviewer != author and reviewer not in reviewers:
+                reviewers.append(reviewer)
+        return reviewers
+
+    def fetch_paths_changed_between(self, old_sha: str, new_sha: str) -> List[str]:
+        """
+        Fetch the paths of the files that differ between two commits.
+
+        Raises `requests.HTTPError` if the request fails.
+        """
+        comparison = get_comparison(self.owner, self.repo, old_sha, new_sha, get_api_token())
+        return [f["filename"] for f in comparison["files"]]
+
+    def mark_outdated(self, paths: List[str]) -> List[Comment]:
+        """
+        Flag the comments and notes on any of `paths` as outdated, returning
+        them.
+        """
+        outdated = [c for c in self.comments + self.notes if c.path in paths]
+        for comment in outdated:
+            comment.outdated = True
+        if outdated:
+            self.save()
+        return outdated
+
+    def fetch_overview(self, use_graphql: bool = True) -> PullRequestOverview:
+        """
+        Fetch the changed files, the number of comments posted on each and
+        their viewed state on GitHub.
+
+        For whole-PR reviews this takes a single GraphQL query, unless
+        `use_graphql` is False or the token can't be used with GraphQL, in
+        which case several REST requests are made instead (and the viewed
+        states are unknown).
+        """
+        if use_graphql and not self.local_only and self.commit_range is None:
+            try:
+                overview = get_pull_request_overview(self.owner, self.repo, self.pr_number, get_api_token())
+            except requests.HTTPError:
+                pass
+            else:
+                remote_comment_counts: Dict[str, int] = {}
+                for thread in overview["reviewThreads"]["nodes"]:
+                    count = thread["comments"]["totalCount"]
+                    remote_comment_counts[thread["path"]] = remote_comment_counts.get(thread["path"], 0) + count
+                return PullRequestOverview(
+                    [
+                        ChangedFile(
+                            changed_file["path"],
+                            GRAPHQL_CHANGE_TYPES.get(changed_file["changeType"], "changed"),
+                            changed_file["additions"],
+                            changed_file["deletions"],
+                            None,
+                        )
+                        for changed_file in overview["files"]["nodes"]
+                    ],
+                    remote_comment_counts,
+                    [
+                        changed_file["path"] for changed_file in overview["files"]["nodes"]
+                        if changed_file["viewerViewedState"] == "VIEWED"
+                    ],
+                )
+        return PullRequestOverview(self.fetch_changed_files(), self.fetch_remote_comment_counts(), None)
+
+    def fetch_remote_comment_counts(self) -> Dict[str, int]:
+        """
+        Fetch the number of comments already posted on GitHub for each file.
+        """
+        counts: Dict[str, int] = {}
+        for remote_comment in self.fetch_remote_comments():
+            counts[remote_comment["path"]] = counts.get(remote_comment["path"], 0) + 1
+        return counts
+
+    def comment_counts(self) -> Dict[str, int]:
+        """
+        Return the number of draft comments in this review for each file.
+        """
+        counts: Dict[str, int] = {}
+        for comment in self.comments:
+            counts[comment.path] = counts.get(comment.path, 0) + 1
+        return counts
+
+    def note_counts(self) -> Dict[str, int]:
+        counts: Dict[str, int] = {}
+        for note in self.notes:
+            counts[note.path] = counts.get(note.path, 0) + 1
+        return counts
+
+    def is_file_viewed(self, path: str) -> bool:
+        return path in self.viewed_files
+
+    def toggle_file_viewed(self, path: str) -> bool:
+        """
+        Flip the viewed flag of the file at `path`, returning the new state.
+        """
+        if path in self.viewed_files:
+            self.viewed_files.remove(path)
+        else:
+            self.viewed_files.append(path)
+        self.save()
+        return path in self.viewed_files
+
+    def sync_file_viewed(self, path: str):
+        """
+        Mirror the viewed flag of the file at `path` to GitHub.
+
+        Raises `requests.HTTPError` if the request fails.
+        """
+        set_file_viewed(self.pull_request()["node_id"], path, self.is_file_viewed(path), get_api_token())
+
+    def patches(self) -> List[Patch]:
+        """
+        Parse the patch file this review is about.
+        """
+        with open(self.patch_file, encoding="utf-8", errors="replace") as f:
+            return parse_mbox(f.read())
+
+    def patch_changed_files(self) -> List[ChangedFile]:
+        """
+        Return the files touched by any patch of the reviewed patch series.
+        """
+        changed_files: Dict[str, ChangedFile] = {}
+        for patch in self.patches():
+            for file_diff in patch.files:
+                if file_diff.old_path is None:
+                    status = "added"
+                elif file_diff.new_path is None:
+                    status = "removed"
+                elif file_diff.old_path != file_diff.new_path:
+                    status = "renamed"
+                else:
+                    status = "modified"
+                lines = [line for hunk in file_diff.hunks for line in hunk.lines]
+                changed_file = changed_files.setdefault(
+                    file_diff.path, ChangedFile(file_diff.path, status, 0, 0, None)
+                )
+                changed_file.additions += len([line for line in lines if line.kind == "+"])
+                changed_file.deletions += len([line for line in lines if line.kind == "-"])
+                if file_diff.old_path != file_diff.new_path:
+                    changed_file.previous_path = file_diff.old_path
+        return list(changed_files.values())
+
+    def comment_source_line(self, patches: List[Patch], comment: Comment) -> Optional[int]:
+        """
+        Return the line of the patch file that `comment` is anchored to.
+        """
+        if comment.patch_index is None or comment.patch_index >= len(patches):
+            return None
+        file_diff = patches[comment.patch_index].find_file(comment.path)
+        if file_diff is None:
+            return None
+        diff_line = file_diff.find_line(comment.line, comment.side)
+        return diff_line.source_line if diff_line is not None else None
+
+    def patch_replies(
+        self, patches: List[Patch], include_held: bool = True, unsent_only: bool = False
+    ) -> Dict[int, Dict[int, List[str]]]:
+        """
+        Return the comment bodies of this review of a patch series, keyed by
+        patch index and then by the line of the patch file they refer to.
+        Held comments are left out unless `include_held` is set, and those
+        already sent as is with `unsent_only`.
+        """
+        replies: Dict[int, Dict[int, List[str]]] = {}
+        for comment in self.comments:
+            if not comment.ready and not include_held:
+                continue
+            if unsent_only and comment.publish_state == "published":
+                continue
+            source_line = self.comment_source_line(patches, comment)
+            if source_line is not None:
+                replies.setdefault(comment.patch_index, {}).setdefault(source_line, []).append(comment.body)
+        return replies
+
+    def to_reply_emails(
+        self, sender: str, reviewed_by: bool = False, include_held: bool = True, unsent_only: bool = False
+    ) -> List[Tuple[str, str]]:
+        """
+        Render this review of a patch series as one reply email per patch,
+        returning `(file name, email)` pairs suitable for `git send-email`.
+
+        Only commented patches get a reply, unless `reviewed_by` is set, in
+        which case every patch gets one with a `Reviewed-by:` trailer. The
+        review body, if any, is sent as a reply to the first patch (usually
+        the cover letter). With `unsent_only`, comments already sent (see
+        `mark_published`) are left out.
+        """
+        patches = self.patches()
+        replies = self.patch_replies(patches, include_held, unsent_only)
+        emails = []
+        for patch_index, patch in enumerate(patches):
+            if patch_index not in replies and not reviewed_by and not (patch_index == 0 and self.body):
+                continue
+            email = reply_email(patch, replies.get(patch_index, {}), sender, reviewed_by)
+            if patch_index == 0 and self.body:
+                headers, body = email.split("\n\n", 1)
+                email = f"{headers}\n\n{self.body.rstrip()}\n\n{body}"
+            emails.append((f"{patch_index + 1:04d}-reply.eml", email))
+        return emails
+
+    def to_email_reply(self) -> str:
+        """
+        Render this review of a patch series as the body of an inline reply,
+        with the review body first and the comments interleaved with the
+        quoted patches they refer to.
+        """
+        patches = self.patches()
+        replies = self.patch_replies(patches)
+        sections = [self.body] if self.body else []
+        for patch_index, patch in enumerate(patches):
+            if patch_index in replies:
+                sections.append(f"{patch.subject}\n\n{quote_reply(patch, replies[patch_index])}")
+        return "\n\n".join(sections).rstrip("\n") + "\n"
+
+    def add_checklist_item(self, text: str, required: bool = False):
+        self.checklist.append(ChecklistItem(text, required=required))
+
+    def toggle_checklist_item(self, index: int) -> bool:
+        """
+        Check or uncheck the checklist item at `index`, returning whether it
+        is now checked.
+        """
+        item = self.checklist[index]
+        item.checked = not item.checked
+        self.save()
+        return item.checked
+
+    def unchecked_required_items(self) -> List[ChecklistItem]:
+        return [item for item in self.checklist if item.required and not item.checked]
+
+    def unfinished_reasons(self) -> List[str]:
+        """
+        Return what is left to do in this (local) review, e.g. `2 comments to
+        address`, or an empty list if it is finished: every changed file is
+        viewed, no comments are left and the required checklist items are
+        checked.
+        """
+        reasons = []
+        if self.comments:
+            reasons.append(f"{len(self.comments)} comments to address")
+        unviewed = [f for f in self.fetch_changed_files() if not self.is_file_viewed(f.path)]
+        if unviewed:
+            reasons.append(f"{len(unviewed)} files not viewed")
+        if self.unchecked_required_items():
+            reasons.append(f"{len(self.unchecked_required_items())} required checklist items unchecked")
+        return reasons
+
+    def sorted_comments(self, order: str = "file") -> List[Comment]:
+        """
+        Return the comments in the given order (one of `COMMENT_ORDERS`):
+        by file and line, by severity (then file and line), in the order they
+        were written, or by published state (new, then edited since published,
+        then published; then file and line).
+        """
+        def position(comment: Comment):
+            return (comment.path, comment.side, comment.start_line or comment.line)
+
+        def severity_rank(comment: Comment) -> int:
+            if comment.severity is None:
+                return len(SEVERITY_ORDER) + 1
+            if comment.severity in SEVERITY_ORDER:
+                return SEVERITY_ORDER.index(comment.severity)
+            return len(SEVERITY_ORDER)
+
+        if order == "created":
+            # Comments from before timestamps were recorded keep their place first.
+            return sorted(self.comments, key=lambda comment: comment.created_at or "")
+        if order == "severity":
+            return sorted(self.comments, key=lambda comment: (severity_rank(comment), position(comment)))
+        if order == "published":
+            states = ("new", "edited", "published")
+            return sorted(self.comments, key=lambda comment: (states.index(comment.publish_state), position(comment)))
+        return sorted(self.comments, key=position)
+
+    def to_markdown(self, order: str = "file") -> str:
+        """
+        Render this review as a Markdown document, with the review body first
+        followed by the checklist and the comments, grouped by file or (with
+        the `severity` order) by severity.
+        """
+        sections = [f"# {self.title}"]
+        if self.body:
+            sections.append(self.body)
+        if self.checklist:
+            sections.append("## Checklist\n\n" + "\n".join(
+                f"- [{'x' if item.checked else ' '}] {item.text}" for item in self.checklist
+            ))
+        group = None
+        for comment in self.sorted_comments(order):
+            if order == "severity":
+                comment_group = (comment.severity or "Other").capitalize()
+            else:
+                comment_group = f"`{comment.path}`"
+            # Comments in creation order are grouped by runs of the same file.
+            if comment_group != group:
+                sections.append(f"## {comment_group}")
+                group = comment_group
+            if comment.start_line is not None:
+                location = f"Lines {comment.start_line}-{comment.line}"
+            else:
+                location = f"Line {comment.line}"
+            if order == "severity":
+                location = f"`{comment.path}` {location.lower()}"
+            if comment.side == "LEFT":
+                location += " (base)"
+            body = comment.body if order == "severity" else comment.decorated_body
+            sections.append(f"### {location}\n\n{body}")
+        return "\n\n".join(sections) + "\n"
+
+    def pending_diff(self) -> str:
+        """
+        Return a Markdown report of what publishing this review would post:
+        the new comments, the edited ones with a diff against what was
+        published, and the published or held ones that are left out.
+        """
+        states = {"new": [], "edited": [], "published": []}
+        held = []
+        for comment in self.sorted_comments("file"):
+            if not comment.ready and comment.publish_state != "published":
+                held.append(comment)
+            else:
+                states[comment.publish_state].append(comment)
+        sections = [
+            f"# Pending changes of {self.title}",
+            f"{len(states['new'])} new, {len(states['edited'])} edited and "
+            f"{len(states['published'])} unchanged published comments, {len(held)} held.",
+        ]
+        for comment in states["new"]:
+            sections.append(f"## New: {comment_location(comment)}\n\n{comment.decorated_body}")
+        for comment in states["edited"]:
+            diff = difflib.unified_diff(
+                comment.published_body.split("\n"),
+                comment.decorated_body.split("\n"),
+                "published",
+                "pending",
+                lineterm="",
+            )
+            sections.append(
+                f"## Edited, published again as a new comment: {comment_location(comment)}\n\n"
+                "```diff\n" + "\n".join(diff) + "\n```"
+            )
+        if states["published"]:
+            sections.append("## Published and unchanged, left out\n\n" + "\n".join(
+                f"- {comment_location(comment)}" for comment in states["published"]
+            ))
+        if held:
+            sections.append("## Held, left out unless publishing with `--include-held`\n\n" + "\n".join(
+                f"- {comment_location(comment)}" for comment in held
+            ))
+        return "\n\n".join(sections) + "\n"
+
+    def summary(self) -> str:
+        """
+        Return a Markdown outline of the comments to start the review body
+        from: one section per severity, most pressing first, listing the
+        first line of each comment by file.
+        """
+        sections: Dict[str, List[str]] = {}
+        for comment in self.sorted_comments("severity"):
+            heading = SEVERITY_HEADINGS.get(comment.severity or "", (comment.severity or "Other").capitalize())
+            first_line = comment.body.strip().split("\n")[0]
+            sections.setdefault(heading, []).append(
+                f"- `{comment.path}` line {comment.start_line or comment.line}: {first_line}"
+            )
+        return "\n\n".join(
+            f"**{heading} ({len(items)})**\n\n" + "\n".join(items) for heading, items in sections.items()
+        )
+
+    def find_comment(self, comment_id: str) -> Optional[Comment]:
+        return next((comment for comment in self.comments if comment.id == comment_id), None)
+
+    def comment_number(self, comment: Comment) -> int:
+        """
+        Return the number `comment` is listed with, e.g. in the quickfix list.
+        """
+        return self.comments.index(comment) + 1
+
+    def delete_comment(self, comment: Comment):
+        self.comments = list(filter(lambda c: c != comment, self.comments))
+        self.log_action("delete", comment)
+        self.save()
+
+    def find_note(self, note_id: str) -> Optional[Comment]:
+        return next((note for note in self.notes if note.id == note_id), None)
+
+    def delete_note(self, note: Comment):
+        self.notes = [n for n in self.notes if n.id != note.id]
+        self.log_action("delete-note", note)
+        self.save()
+
+    def promote_note(self, note: Comment):
+        """
+        Turn a private note into a draft comment at the same anchor.
+        """
+        self.notes = [n for n in self.notes if n.id != note.id]
+        self.comments.append(note)
+        self.log_action("promote", note)
+        self.save()
+
+    def demote_comment(self, comment: Comment):
+        """
+        Turn a draft comment back into a private note. Comments that were
+        already published can't be taken back this way.
+        """
+        if comment.published_body is not None:
+            raise ValueError("this comment was already published")
+        self.comments = [c for c in self.comments if c.id != comment.id]
+        self.notes.append(comment)
+        self.log_action("demote", comment)
+        self.save()
+
+
+def get_review_directory() -> str:
+    """
+    Returns the directory storing in-progress reviews. Creates this directory if it does not exist.
+
+    This directory is within the `.git` directory of the local repository.
+    """
+    git_dir = (
+        subprocess.check_output(["git", "rev-parse", "--git-dir"])
+        .decode("utf-8")
+        .strip()
+    )
+    reviews_path = os.path.join(git_dir, "reviews")
+    os.makedirs(reviews_path, exist_ok=True)
+    logger.debug("Review directory at %s", reviews_path)
+    return reviews_path
+
+
+def resolve_revision(revision: str, git_dir: Optional[str] = None) -> Optional[str]:
+    """
+    Return the full commit SHA that `revision` points to, or None if it does
+    not name a commit in the repository.
+
+    `git_dir` selects the repository to look in; the repository containing
+    the current directory is used if it is not provided.
+    """
+    command = ["git"]
+    if git_dir:
+        command += ["--git-dir", git_dir]
+    command += ["rev-parse", "--verify", "--quiet", f"{revision}^{{commit}}"]
+    result = subprocess.run(command, stdout=subprocess.PIPE, stderr=subprocess.DEVNULL)
+    if result.returncode != 0:
+        return None
+    return result.stdout.decode("utf-8").strip()
+
+
+def get_git_identity() -> str:
+    """
+    Return the user's git identity as `Name <email>`.
+    """
+    name = subprocess.check_output(["git", "config", "user.name"]).decode("utf-8").strip()
+    email = subprocess.check_output(["git", "config", "user.email"]).decode("utf-8").strip()
+    return f"{name} <{email}>"
+
+
+def git_config_value(name: str) -> Optional[str]:
+    result = subprocess.run(["git", "config", name], stdout=subprocess.PIPE, stderr=subprocess.DEVNULL)
+    value = result.stdout.decode("utf-8").strip()
+    return value if result.returncode == 0 and value else None
+
+
+def sign_file(path: str) -> str:
+    """
+    Sign the file at `path` with the user's git signing key, the way git
+    signs commits: with GPG (`<path>.asc`, an armored detached signature),
+    or with SSH when `gpg.format` is `ssh` (`<path>.sig`). Returns the path
+    to the signature.
+
+    Raises `ValueError` if signing fails, e.g. without a signing key.
+    """
+    key = git_config_value("user.signingkey")
+    if git_config_value("gpg.format") == "ssh":
+        if key is None:
+            raise ValueError("Set git's user.signingkey to the SSH key to sign exports with.")
+        program = git_config_value("gpg.ssh.program") or "ssh-keygen"
+        signature = f"{path}.sig"
+        command = [program, "-Y", "sign", "-n", "file", "-f", os.path.expanduser(key), path]
+    else:
+        program = git_config_value("gpg.openpgp.program") or git_config_value("gpg.program") or "gpg"
+        signature = f"{path}.asc"
+        command = [program, "--batch", "--yes", "--armor", "--detach-sign", "--output", signature]
+        # Without a configured key, gpg picks the default one, like git does
+        # from the committer identity.
+        command += ["--local-user", key] if key else []
+        command.append(path)
+    try:
+        result = subprocess.run(command, stdout=subprocess.PIPE, stderr=subprocess.PIPE)
+    except OSError as e:
+        raise ValueError(f"Could not run {program}: {e}")
+    if result.returncode != 0:
+        raise ValueError(f"Signing {path} failed: {result.stderr.decode('utf-8', 'replace').strip()}")
+    return signature
+
+
+def get_merge_base(revision: str = "origin/HEAD", other: str = "HEAD") -> Optional[str]:
+    """
+    Return the merge base of `other` and `revision`, or None if there is
+    none (or either isn't available locally).
+    """
+    result = subprocess.run(
+        ["git", "merge-base", other, revision],
+        stdout=subprocess.PIPE,
+        stderr=subprocess.DEVNULL,
+    )
+    if result.returncode != 0:
+        return None
+    return result.stdout.decode("utf-8").strip()
+
+
+def list_refs() -> List[str]:
+    """
+    Return the short names of the branches and tags of the repository.
+    """
+    result = subprocess.run(
+        ["git", "for-each-ref", "--format=%(refname:short)", "refs/heads", "refs/remotes", "refs/tags"],
+        stdout=subprocess.PIPE,
+        stderr=subprocess.DEVNULL,
+    )
+    if result.returncode != 0:
+        return []
+    return result.stdout.decode("utf-8").split()
+
+
+def get_file_at_revision(revision: str, path: str) -> Optional[str]:
+    """
+    Return the contents of the file at `path` as of `revision`, or None if
+    the file does not exist at that revision.
+    """
+    result = subprocess.run(
+        ["git", "show", f"{revision}:{path}"],
+        stdout=subprocess.PIPE,
+        stderr=subprocess.DEVNULL,
+    )
+    if result.returncode != 0:
+        return None
+    return result.stdout.decode("utf-8")
+
+
+# Each line of `git blame --porcelain` output starts with a header like
+# `<sha> <original-line> <final-line>`, followed by the commit's details the
+# first time it appears.
+BLAME_HEADER_PATTERN = re.compile(r"^[0-9a-f]{40} \d+ \d+")
+
+
+def get_blame(revision: str, path: str, start_line: int, end_line: int) -> List[BlameEntry]:
+    """
+    Return the commits that last changed lines `start_line` to `end_line` of
+    `path` as of `revision`, most lines first. Returns an empty list if the
+    file can't be blamed, e.g. because `revision` isn't available locally.
+    """
+    result = subprocess.run(
+        ["git", "blame", "--porcelain", "-L", f"{start_line},{end_line}", revision, "--", path],
+        stdout=subprocess.PIPE,
+        stderr=subprocess.DEVNULL,
+    )
+    if result.returncode != 0:
+        return []
+
+    entries: Dict[str, BlameEntry] = {}
+    current = None
+    for line in result.stdout.decode("utf-8", errors="replace").split("\n"):
+        if line.startswith("\t"):
+            # The line's contents end its block.
+            current.line_count += 1
+            continue
+        fields = line.split(" ", 1)
+        if BLAME_HEADER_PATTERN.match(line):
+            current = entries.setdefault(fields[0], BlameEntry(fields[0], "", "", 0, 0))
+        elif current is not None and fields[0] == "author":
+            current.author = fields[1]
+        elif current is not None and fields[0] == "author-time":
+            current.author_time = int(fields[1])
+        elif current is not None and fields[0] == "summary":
+            current.summary = fields[1]
+    return sorted(entries.values(), key=lambda entry: entry.line_count, reverse=True)
+
+
+def pull_request_branch(pr_number: int) -> str:
+    """
+    Return the name of the local branch used to check out PR `pr_number`.
+    """
+    return f"pr/{pr_number}"
+
+
+def checkout_pull_request(pr_number: int, worktree_path: Optional[str] = None, remote: str = "origin"):
+    """
+    Fetch the head of PR `pr_number` into a local branch and check it out.
+
+    If `worktree_path` is provided, the branch is checked out in a new
+    worktree at that path instead of in the current one.
+
+    Raises `subprocess.CalledProcessError` if any git command fails.
+    """
+    branch = pull_request_branch(pr_number)
+    subprocess.run(
+        ["git", "fetch", remote, f"pull/{pr_number}/head"],
+        check=True, stdout=subprocess.PIPE, stderr=subprocess.PIPE,
+    )
+    if worktree_path:
+        command = ["git", "worktree", "add", "-B", branch, worktree_path, "FETCH_HEAD"]
+    else:
+        command = ["git", "checkout", "-B", branch, "FETCH_HEAD"]
+    subprocess.run(command, check=True, stdout=subprocess.PIPE, stderr=subprocess.PIPE)
+
+
+GIT_STATUSES = {
+    "A": "added",
+    "D": "removed",
+    "M": "modified",
+    "R": "renamed",
+    "C": "copied",
+    "T": "changed",
+}
+
+
+def get_local_changed_files(base: str, head: str) -> List[ChangedFile]:
+    """
+    Return the files changed between `base` and `head`, according to the
+    local repository.
+    """
+    name_status = subprocess.check_output(
+        ["git", "diff", "--name-status", "-M", base, head]
+    ).decode("utf-8")
+    numstat = subprocess.check_output(
+        ["git", "diff", "--numstat", "-M", "-z", base, head]
+    ).decode("utf-8")
+
+    # With `-z`, renamed entries are `<added>\t<deleted>\t\0<old>\0<new>\0`.
+    line_counts: Dict[str, Tuple[int, int]] = {}
+    fields = numstat.split("\0")
+    index = 0
+    while index < len(fields) and fields[index]:
+        added, deleted, path = fields[index].split("\t", 2)
+        if not path:
+            path = fields[index + 2]
+            index += 2
+        index += 1
+        # Binary files report `-` for both counts.
+        line_counts[path] = (int(added) if added != "-" else 0, int(deleted) if deleted != "-" else 0)
+
+    changed_files = []
+    for line in name_status.splitlines():
+        status, *paths = line.split("\t")
+        path = paths[-1]
+        additions, deletions = line_counts.get(path, (0, 0))
+        changed_files.append(ChangedFile(
+            path,
+            GIT_STATUSES.get(status[0], "changed"),
+            additions,
+            deletions,
+            paths[0] if len(paths) > 1 else None,
+        ))
+    return changed_files
+
+
+def get_local_review_file(commit_range: str) -> str:
+    """
+    Return the path to the review file for the local review of `commit_range`.
+    """
+    review_directory = get_review_directory()
+    return os.path.join(review_directory, f"local-{commit_range.replace('/', '_')}-review.json")
+
+
+def get_or_create_local_review(commit_range: str) -> Review:
+    """
+    Return the local review of `commit_range`, creating a new one if none
+    exists yet.
+    """
+    review_file = get_local_review_file(commit_range)
+    if os.path.exists(review_file):
+        return Review.load(review_file)
+    owner, repo = get_repo_from_config() if is_configured() else ("", "")
+    return Review(owner, repo, None, "", [], commit_range=commit_range, local_only=True)
+
+
+def list_local_reviews(kind: str = "local") -> List[Review]:
+    """
+    Return the local reviews saved in this repository, or the reviews of
+    patch series with `kind` `"patch"`. Encrypted ones that can't be
+    decrypted are left out.
+    """
+    review_directory = get_review_directory()
+    reviews = []
+    for file_name in sorted(os.listdir(review_directory)):
+        if not re.match(rf"^{kind}-.*-review\.json$", file_name):
+            continue
+        try:
+            reviews.append(Review.load(os.path.join(review_directory, file_name)))
+        except ValueError:
+            continue
+    return reviews
+
+
+# The first lines of the pre-push hook installed by `install_pre_push_hook`,
+# which tell it apart from other hooks.
+PRE_PUSH_HOOK_MARKER = "# Installed by vim-reviewer:"
+NULL_SHA = "0" * 40
+
+
+def get_hooks_directory() -> str:
+    # Honors `core.hooksPath`, and finds the hooks of the main repository
+    # from a worktree.
+    path = subprocess.check_output(["git", "rev-parse", "--git-path", "hooks"]).decode("utf-8").strip()
+    return os.path.abspath(path)
+
+
+def pre_push_hook_script(block: bool) -> str:
+    """
+    Return the pre-push hook that checks for unfinished local reviews of the
+    pushed commits, run with the Python interpreter running this module
+    (written into the hook by its absolute path). The hook only reminds of
+    them unless `block`, in which case the push fails. If that interpreter
+    is gone or can't import this package anymore, the hook lets the push
+    through.
+    """
+    code = (
+        "import sys\n"
+        "try:\n"
+        "    from offline_pr_review import run_pre_push_hook\n"
+        "except ImportError:\n"
+        "    sys.stderr.write('vim-reviewer: offline_pr_review is not installed, skipping the review check.\\n')\n"
+        "    sys.exit(0)\n"
+        f"sys.exit(run_pre_push_hook(sys.stdin, {block}))\n"
+    )
+    python = os.path.abspath(sys.executable)
+    return (
+        "#!/bin/sh\n"
+        f"{PRE_PUSH_HOOK_MARKER} {'blocks' if block else 'reminds of'} pushes with unfinished local reviews.\n"
+        "# Skip it with `git push --no-verify`.\n"
+        f"python={shlex.quote(python)}\n"
+        '[ -x "$python" ] || exit 0\n'
+        # Messages go to stderr; stdout only has the module's chatter.
+        f'exec "$python" -c {shlex.quote(code)} >/dev/null\n'
+    )
+
+
+def installed_pre_push_hook() -> Optional[str]:
+    """
+    Return the contents of the repository's pre-push hook, or None if it has
+    none.
+    """
+    try:
+        with open(os.path.join(get_hooks_directory(), "pre-push"), encoding="utf-8") as f:
+            return f.read()
+    except (FileNotFoundError, UnicodeDecodeError):
+        return None
+
+
+def install_pre_push_hook(block: bool, overwrite: bool = False) -> str:
+    """
+    Install the pre-push hook (see `pre_push_hook_script`), replacing a
+    previous version of it, and return its path.
+
+    Raises `ValueError` if the repository has another pre-push hook, unless
+    `overwrite`.
+    """
+    existing = installed_pre_push_hook()
+    if existing is not None and PRE_PUSH_HOOK_MARKER not in existing and not overwrite:
+        raise ValueError("This repository already has a pre-push hook; add a bang to replace it.")
+    hooks_directory = get_hooks_directory()
+    os.makedirs(hooks_directory, exist_ok=True)
+    hook_path = os.path.join(hooks_directory, "pre-push")
+    with open(hook_path, "w", encoding="utf-8") as f:
+        f.write(pre_push_hook_script(block))
+    os.chmod(hook_path, 0o755)
+    return hook_path
+
+
+def remove_pre_push_hook() -> bool:
+    """
+    Remove the pre-push hook if it is the one `install_pre_push_hook`
+    installed. Returns whether it was removed.
+    """
+    existing = installed_pre_push_hook()
+    if existing is None or PRE_PUSH_HOOK_MARKER not in existing:
+        return False
+    os.remove(os.path.join(get_hooks_directory(), "pre-push"))
+    return True
+
+
+def pushed_commits(lines: Iterable[str]) -> List[str]:
+    """
+    Return the commits a push sends, given the lines git feeds the pre-push
+    hook: `<local ref> <local sha> <remote ref> <remote sha>` for each ref.
+    """
+    commits: List[str] = []
+    for line in lines:
+        fields = line.split()
+        if len(fields) != 4 or fields[1] == NULL_SHA:
+            # Deleted refs send nothing.
+            continue
+        local_sha, remote_sha = fields[1], fields[3]
+        if remote_sha != NULL_SHA and resolve_revision(remote_sha) is not None:
+            excluded = [f"^{remote_sha}"]
+        else:
+            # New branches (or remote heads we haven't fetched) send what no
+            # remote-tracking branch has.
+            excluded = ["--not", "--remotes"]
+        result = subprocess.run(
+            ["git", "rev-list", local_sha, *excluded], stdout=subprocess.PIPE, stderr=subprocess.DEVNULL
+        )
+        commits += result.stdout.decode("utf-8").split()
+    return commits
+
+
+def unfinished_local_reviews(commits: List[str]) -> List[Tuple[Review, List[str]]]:
+    """
+    Return the unfinished local reviews whose commit range ends at one of
+    `commits`, along with what is left to do in each.
+    """
+    commits_set = set(commits)
+    unfinished = []
+    for review in list_local_reviews():
+        head = resolve_revision(review.commit_range_bounds()[1])
+        if head not in commits_set:
+            continue
+        reasons = review.unfinished_reasons()
+        if reasons:
+            unfinished.append((review, reasons))
+    return unfinished
+
+
+def run_pre_push_hook(lines: Iterable[str], block: bool) -> int:
+    """
+    Report the unfinished local reviews of the commits being pushed, and
+    return the hook's exit status: non-zero to stop the push if `block`.
+    """
+    unfinished = unfinished_local_reviews(pushed_commits(lines))
+    for review, reasons in unfinished:
+        message = f"the local review of {review.commit_range} is unfinished: {', '.join(reasons)}."
+        print(f"vim-reviewer: {message}", file=sys.stderr)
+    if unfinished and block:
+        print("vim-reviewer: finish it before pushing, or push with --no-verify.", file=sys.stderr)
+        return 1
+    return 0
+
+
+def write_audit_export(path: str, records: List[dict]):
+    """
+    Write the records of an audit export to `path`: as CSV with the
+    `AUDIT_COLUMNS` if it ends with `.csv`, as JSON Lines otherwise.
+
+    Raises `OSError` if the file can't be written.
+    """
+    with open(path, "w", encoding="utf-8", newline="") as f:
+        if path.lower().endswith(".csv"):
+            writer = csv.DictWriter(f, fieldnames=AUDIT_COLUMNS)
+            writer.writeheader()
+            writer.writerows(records)
+        else:
+            for record in records:
+                f.write(json.dumps({column: record.get(column) for column in AUDIT_COLUMNS}, ensure_ascii=False) + "\n")
+
+
+def get_patch_review_file(patch_file: str) -> str:
+    """
+    Return the path to the review file for the review of `patch_file`.
+
+    The file is named after a hash of the absolute path of `patch_file`, so
+    patch files with the same name in different directories (every
+    `0001-*.patch` of `git format-patch`) get reviews of their own.
+    """
+    review_directory = get_review_directory()
+    patch_file = os.path.abspath(patch_file)
+    digest = hashlib.sha256(patch_file.encode("utf-8")).hexdigest()[:16]
+    return os.path.join(review_directory, f"patch-{os.path.basename(patch_file)}-{digest}-review.json")
+
+
+def get_or_create_patch_review(patch_file: str) -> Review:
+    """
+    Return the review of the patch series in `patch_file`, creating a new one
+    if none exists yet.
+    """
+    patch_file = os.path.abspath(patch_file)
+    review_file = get_patch_review_file(patch_file)
+    if os.path.exists(review_file):
+        return Review.load(review_file)
+    # Reviews used to be named after the base name of the patch file alone;
+    # pick such a review up if it's of this very file. It's saved under the
+    # new name from then on.
+    legacy_file = os.path.join(get_review_directory(), f"patch-{os.path.basename(patch_file)}-review.json")
+    if os.path.exists(legacy_file):
+        review = Review.load(legacy_file)
+        if review.patch_file == patch_file:
+            return review
+    return Review("", "", None, "", [], local_only=True, patch_file=patch_file)
+
+
+def list_saved_reviews() -> List[int]:
+    """
+    Return the numbers of the PRs with a review saved in this repository.
+    """
+    numbers = []
+    for file_name in os.listdir(get_review_directory()):
+        match = re.match(r"^(\d+)-review\.json$", file_name)
+        if match:
+            numbers.append(int(match.group(1)))
+    return sorted(numbers)
+
+
+def list_digest_reviews(author: Optional[str] = None) -> List[Review]:
+    """
+    Return the reviews saved in this repository, PR reviews first, for a
+    learning digest. With `author`, only the reviews of that user's PRs are
+    returned, going by the author recorded with each review (see
+    `Review.known_pr_author`) so that nothing is fetched; PR reviews whose
+    author isn't known are left out, while local and patch reviews, which
+    have no PR, are all kept. Encrypted reviews that can't be decrypted are
+    left out.
+    """
+    reviews = []
+    for pr_number in list_saved_reviews():
+        try:
+            reviews.append(get_review(pr_number))
+        except ValueError:
+            continue
+    if author is not None:
+        reviews = [
+            review for review in reviews
+            if (review.known_pr_author() or "").lower() == author.lstrip("@").lower()
+        ]
+    return reviews + list_local_reviews() + list_local_reviews("patch")
+
+
+def learning_digest(reviews: List[Review], teaching_labels: Iterable[str]) -> Optional[str]:
+    """
+    Compile the comments of `reviews` that have one of `teaching_labels`
+    into a Markdown digest grouped by label, e.g. for a mentee. Comments
+    with several teaching labels are listed under each. Returns None if no
+    comment has any.
+    """
+    teaching_labels = [label.lower() for label in teaching_labels]
+    sections: Dict[str, List[str]] = {label: [] for label in teaching_labels}
+    comment_count = 0
+    review_count = 0
+    for review in reviews:
+        source = f"[{review.title}]({review.web_url})" if review.pr_number is not None else review.title
+        tagged = [
+            comment for comment in review.sorted_comments()
+            if any(label.lower() in teaching_labels for label in comment.labels)
+        ]
+        if tagged:
+            review_count += 1
+        for comment in tagged:
+            comment_count += 1
+            for label in {label.lower() for label in comment.labels} & set(teaching_labels):
+                sections[label].append(f"### {source}, {comment_location(comment)}\n\n{comment.body}")
+    if not comment_count:
+        return None
+    parts = [
+        "# Learning digest",
+        f"{comment_count} comment{'s' if comment_count != 1 else ''} "
+        f"from {review_count} review{'s' if review_count != 1 else ''}, by topic.",
+    ]
+    for label in teaching_labels:
+        if sections[label]:
+            parts.append(f"## {label.capitalize()}")
+            parts += sections[label]
+    return "\n\n".join(parts) + "\n"
+
+
+def get_review_file(pr_number: int) -> str:
+    """
+    Return the path to the review file for the PR specified by `pr_number`.
+    """
+    review_directory = get_review_directory()
+    return os.path.join(review_directory, f"{pr_number}-review.json")
+
+def get_remote_review_directory(owner: str, repo: str) -> str:
+    """
+    Returns the directory storing reviews of `owner/repo` made without a
+    local checkout. Creates this directory if it does not exist.
+
+    This directory is within the user's data directory.
+    """
+    reviews_path = os.path.join(get_remote_reviews_root(), owner, repo)
+    os.makedirs(reviews_path, exist_ok=True)
+    return reviews_path
+
+
+def get_data_directory() -> str:
+    """
+    Return the directory the plugin keeps data not tied to a repository in.
+    """
+    data_home = os.getenv("XDG_DATA_HOME") or os.path.join(os.path.expanduser("~"), ".local", "share")
+    return os.path.join(data_home, "vim-reviewer")
+
+
+def get_remote_reviews_root() -> str:
+    return os.path.join(get_data_directory(), "reviews")
+
+
+def get_inbox_triage_file() -> str:
+    return os.path.join(get_data_directory(), "inbox.json")
+
+
+def list_saved_remote_reviews() -> List[Tuple[str, str, int]]:
+    """
+    Return the owner, repository and PR number of the reviews saved without
+    a local checkout.
+    """
+    root = get_remote_reviews_root()
+    reviews = []
+    for owner in sorted(os.listdir(root)) if os.path.isdir(root) else []:
+        for repo in sorted(os.listdir(os.path.join(root, owner))):
+            for file_name in os.listdir(os.path.join(root, owner, repo)):
+                match = re.match(r"^(\d+)-review\.json$", file_name)
+                if match:
+                    reviews.append((owner, repo, int(match.group(1))))
+    return reviews
+
+
+def get_remote_review_file(owner: str, repo: str, pr_number: int) -> str:
+    """
+    Return the path to the review file for a PR reviewed without a local checkout.
+    """
+    return os.path.join(get_remote_review_directory(owner, repo), f"{pr_number}-review.json")
+
+
+def get_or_create_remote_review(owner: str, repo: str, pr_number: int) -> Review:
+    """
+    Return the review of PR `pr_number` of `owner/repo` made without a local
+    checkout, creating a new one if none exists yet.
+    """
+    review_file = get_remote_review_file(owner, repo, pr_number)
+    if os.path.exists(review_file):
+        return Review.load(review_file)
+    return Review(owner, repo, pr_number, "", [], remote_only=True)
+
+
+def get_or_create_review(pr_number: int, commit_range: Optional[str] = None) -> Review:
+    """
+    Return the review for `pr_number`, creating a new one if none exists yet.
+
+    If `commit_range` is provided, the review is (re)scoped to that range of
+    commits, or unscoped again if it's `"all"`.
+    """
+    review_file = get_review_file(pr_number)
+    if os.path.exists(review_file):
+        review = Review.load(review_file)
+    else:
+        review = new_blank_review(pr_number)
+    if commit_range is not None:
+        review.commit_range = None if commit_range == "all" else commit_range
+        review.save()
+    return review
+
+
+def get_repo_from_config() -> Tuple[str, str]:
+    """
+    Return the `owner` and `repo` reviews of the current repository target,
+    and point the API at its host, with the API token set up for that host.
+
+    Raises `FileNotFoundError` if the repository isn't set up for reviews.
+    """
+    config = load_config()
+    if "owner" not in config:
+        raise FileNotFoundError(
+            f"This repository isn't set up for reviews; run :ReviewerInit or commit a {SHARED_CONFIG_FILE}."
+        )
+    host = config.get("host", "github.com")
+    if host != SOURCEHUT_HOST:
+        set_host(host)
+    set_token_source(*user_token_source(host))
+    set_repo_token(config.get("token"))
+    return config["owner"], config["repo"]
+
+
+def is_configured() -> bool:
+    """
+    Whether the current repository is set up for reviews, by
+    `.git/reviews/config.json` or by its `vim-reviewer.json`.
+    """
+    return "owner" in load_config()
+
+
+def new_blank_review(pr_number: int) -> Review:
+    owner, repo = get_repo_from_config()
+    return Review(owner, repo, pr_number, "", [])
+
+
+def get_review(pr_number: int) -> Review:
+    """
+    Return the `Review` object representing the current review for `pr_number`.
+    Creates a new `Review` object if no review already exists.
+    """
+    review_file_path = get_review_file(pr_number)
+    if os.path.exists(review_file_path):
+        return Review.load(review_file_path)
+    else:
+        return new_blank_review(pr_number)
+
+
+def fetch_owner_identities() -> List[str]:
+    """
+    Return the names the user can appear as in CODEOWNERS: `@login` and
+    `@org/team` for each of their teams (if the token may list them).
+    """
+    token = get_api_token()
+    identities = [f"@{get_authenticated_user(token)}"]
+    try:
+        identities += [f"@{team}" for team in list_user_teams(token)]
+    except requests.HTTPError:
+        pass
+    return identities
+
+
+def fetch_rate_limits() -> Dict[str, dict]:
+    """
+    Return the current API rate limits of the user's token, by resource.
+    """
+    return get_rate_limits(get_api_token())
+
+
+def create_snippet(file_name: str, content: str, description: str, public: bool = False) -> str:
+    """
+    Upload `content` as a gist, e.g. a counter-proposal too long for an inline
+    comment, and return its URL. The gist is secret unless `public`.
+
+    Raises `requests.HTTPError` if the request fails.
+    """
+    return create_gist({file_name: content}, description, public, get_api_token())
+
+
+def fetch_review_requests() -> List[ReviewRequest]:
+    """
+    Return the open PRs where the user's review is requested, oldest first.
+    """
+    return [ReviewRequest.from_json(item) for item in search_review_requests(get_api_token())]
+
+
+def get_config_file_path():
+    review_dir = get_review_directory()
+    return os.path.join(review_dir, "config.json")
+
+
+# The configuration meant to be committed, at the root of the working tree:
+# the settings the whole team shares, e.g. `{"repository": "owner/repo",
+# "host": "github.example.com", "ignore": ["*.lock"]}`. Those of
+# `.git/reviews/config.json` take precedence over them.
+SHARED_CONFIG_FILE = "vim-reviewer.json"
+SHARED_SETTINGS = ("repository", "host", "remote", "ignore", "checklist_template", "compliance")
+# Settings that hold or lead to the API token. They belong in the user's own
+# configuration (see `get_user_config_file_path`); repository files may only
+# hold a plaintext `token`, and only when `GH_REVIEW_ALLOW_REPO_TOKEN` is set.
+SECRET_SETTINGS = ("token", "token_source", "token_command")
+# The settings of the repository's `vim-reviewer.json`, once validated by
+# `load_repository_config` (None until then). They are read once rather than
+# on every use, so that a broken file is reported once instead of breaking
+# every save.
+SHARED_CONFIG: Optional[dict] = None
+
+
+def repo_tokens_allowed() -> bool:
+    return bool(os.getenv("GH_REVIEW_ALLOW_REPO_TOKEN"))
+
+
+def check_no_secrets(config: dict, file_name: str):
+    """
+    Raises `ValueError` if the repository configuration `config`, read from
+    `file_name`, holds credentials that aren't allowed there.
+    """
+    secrets = [name for name in SECRET_SETTINGS if name in config]
+    if not secrets or (secrets == ["token"] and repo_tokens_allowed()):
+        return
+    allow = ", or set GH_REVIEW_ALLOW_REPO_TOKEN to allow a plaintext token there" if secrets == ["token"] else ""
+    raise ValueError(
+        f"{file_name} must not hold API token settings ({', '.join(secrets)}); "
+        f"set the token up with :ReviewerInit instead{allow}."
+    )
+
+
+def read_shared_config() -> dict:
+    """
+    Return the settings of the repository's `vim-reviewer.json`, or an empty
+    dictionary if it has none.
+
+    Raises `ValueError` if the file is invalid or holds credentials.
+    """
+    worktree_root = get_worktree_root()
+    if worktree_root is None or not os.path.exists(os.path.join(worktree_root, SHARED_CONFIG_FILE)):
+        return {}
+    try:
+        with open(os.path.join(worktree_root, SHARED_CONFIG_FILE), encoding="utf-8") as f:
+            config = json.load(f)
+    except ValueError as e:
+        raise ValueError(f"{SHARED_CONFIG_FILE} isn't valid JSON: {e}")
+    if not isinstance(config, dict):
+        raise ValueError(f"{SHARED_CONFIG_FILE} must hold a JSON object.")
+    check_no_secrets(config, SHARED_CONFIG_FILE)
+    unknown = sorted(set(config) - set(SHARED_SETTINGS) - set(SECRET_SETTINGS))
+    if unknown:
+        raise ValueError(f"Unknown settings in {SHARED_CONFIG_FILE}: {', '.join(unknown)}.")
+    if "repository" in config and str(config["repository"]).count("/") != 1:
+        raise ValueError(f"The repository in {SHARED_CONFIG_FILE} must be given as owner/repo.")
+    compliance = config.get("compliance", False)
+    if isinstance(compliance, dict):
+        unknown = sorted(set(compliance) - set(COMPLIANCE_RULES))
+        if unknown:
+            raise ValueError(
+                f"Unknown requirements in the compliance setting of {SHARED_CONFIG_FILE}: {', '.join(unknown)}; "
+                f"use {', '.join(COMPLIANCE_RULES)}."
+            )
+    elif not isinstance(compliance, (bool, int)):
+        raise ValueError(f"The compliance setting of {SHARED_CONFIG_FILE} must be true, false or an object.")
+    return config
+
+
+def read_local_config() -> dict:
+    """
+    Return the settings of `.git/reviews/config.json`, or an empty dictionary
+    if there is none. A plaintext token is left out unless
+    `GH_REVIEW_ALLOW_REPO_TOKEN` allows it (`load_repository_config` reports
+    it).
+    """
+    config_path = get_config_file_path()
+    if not os.path.exists(config_path):
+        return {}
+    with open(config_path, encoding="utf-8") as f:
+        config = json.load(f)
+    if not repo_tokens_allowed():
+        config.pop("token", None)
+    return config
+
+
+def migrate_local_config():
+    """
+    Move the token settings earlier versions saved in
+    `.git/reviews/config.json` to the user's configuration.
+    """
+    config_path = get_config_file_path()
+    if not os.path.exists(config_path):
+        return
+    with open(config_path, encoding="utf-8") as f:
+        config = json.load(f)
+    if "token_source" not in config and "token_command" not in config:
+        return
+    host = config.get("host", "github.com")
+    if user_token_source(host) == (None, None):
+        save_user_token_source(host, config.get("token_source"), config.get("token_command"))
+    config.pop("token_source", None)
+    config.pop("token_command", None)
+    with open(config_path, "w", encoding="utf-8") as f:
+        json.dump(config, f, indent=2)
+
+
+def load_repository_config():
+    """
+    Read and validate the configuration of the current repository, e.g. when
+    the editor starts or a review is activated: remember the settings of its
+    `vim-reviewer.json` for `load_config`, and move token settings left in
+    `.git/reviews/config.json` to the user's configuration.
+
+    Raises `ValueError` if either file is invalid or holds credentials; the
+    shared settings are then ignored until the next call.
+    """
+    global SHARED_CONFIG
+    SHARED_CONFIG = {}
+    migrate_local_config()
+    local_config_path = get_config_file_path()
+    if os.path.exists(local_config_path):
+        with open(local_config_path, encoding="utf-8") as f:
+            check_no_secrets(json.load(f), local_config_path)
+    SHARED_CONFIG = read_shared_config()
+
+
+def shared_settings() -> dict:
+    """
+    Return the settings of the repository's `vim-reviewer.json`, loading
+    them the first time; an invalid file counts as none (see
+    `load_repository_config` for reporting it).
+    """
+    if SHARED_CONFIG is None:
+        try:
+            load_repository_config()
+        except ValueError:
+            pass
+    return SHARED_CONFIG
+
+
+def load_config() -> dict:
+    """
+    Return the configuration of the current repository: the shared settings
+    of `vim-reviewer.json` overridden by those of `.git/reviews/config.json`,
+    with the repository as `owner` and `repo`.
+    """
+    shared = shared_settings()
+    config = {name: value for name, value in shared.items() if name != "repository"}
+    if "repository" in shared:
+        config["owner"], config["repo"] = shared["repository"].split("/")
+    config.update(read_local_config())
+    return config
+
+
+def get_user_config_file_path() -> str:
+    """
+    Return the path of the user's own configuration, which isn't tied to a
+    repository: where the API token of each host comes from.
+    """
+    return os.path.join(config_home("vim-reviewer"), "config.json")
+
+
+def read_user_config() -> dict:
+    config_path = get_user_config_file_path()
+    if not os.path.exists(config_path):
+        return {}
+    with open(config_path, encoding="utf-8") as f:
+        return json.load(f)
+
+
+def user_token_source(host: str) -> Tuple[Optional[str], Optional[str]]:
+    """
+    Return where the API token of `host` comes from and the command printing
+    it, as passed to `set_token_source`.
+    """
+    settings = read_user_config().get("hosts", {}).get(host, {})
+    return settings.get("token_source"), settings.get("token_command")
+
+
+def save_user_token_source(host: str, source: Optional[str], command: Optional[str] = None):
+    config = read_user_config()
+    hosts = config.setdefault("hosts", {})
+    hosts.pop(host, None)
+    if source is not None:
+        hosts[host] = {"token_source": source}
+    if source == "command":
+        hosts[host]["token_command"] = command
+    os.makedirs(os.path.dirname(get_user_config_file_path()), exist_ok=True)
+    with open(get_user_config_file_path(), "w", encoding="utf-8") as f:
+        json.dump(config, f, indent=2)
+
+
+def write_shared_configuration() -> str:
+    """
+    Write the repository, host and remote reviews target to the repository's
+    `vim-reviewer.json`, keeping its other settings, so that they can be
+    committed. Returns the path of the file.
+
+    Raises `ValueError` if the repository isn't set up for reviews.
+    """
+    config = load_config()
+    if "owner" not in config:
+        raise ValueError("This repository isn't set up for reviews; run :ReviewerInit first.")
+    shared = read_shared_config()
+    shared.pop("token", None)
+    shared["repository"] = f"{config['owner']}/{config['repo']}"
+    for name in ("host", "remote"):
+        if config.get(name):
+            shared[name] = config[name]
+    path = os.path.join(get_worktree_root(), SHARED_CONFIG_FILE)
+    with open(path, "w", encoding="utf-8") as f:
+        json.dump(shared, f, indent=2)
+        f.write("\n")
+    load_repository_config()
+    return path
+
+
+def update_configuration(repository: str, remote: Optional[str] = None, host: Optional[str] = None):
+    """
+    Make `repository` (`owner/repo`) the target of reviews in the current
+    repository's configuration, along with the git `remote` and the `host`
+    it was found through if given. The other settings, including a remote
+    and host set before, are kept.
+    """
+    config_file_path = get_config_file_path()
+    config = {}
+    if os.path.exists(config_file_path):
+        with open(config_file_path, encoding="utf-8") as f:
+            config = json.load(f)
+    config["owner"], config["repo"] = repository.split("/")
+    if remote is not None:
+        config["remote"] = remote
+    if host == "github.com":
+        config.pop("host", None)
+    elif host is not None:
+        config["host"] = host
+    with open(config_file_path, "w", encoding="utf-8") as f:
+        json.dump(config, f, indent=2)
+    set_token_source(*user_token_source(config.get("host", "github.com")))
+
+
+def configure_token_source(source: Optional[str], command: Optional[str] = None):
+    """
+    Remember where the API token of the current repository's host comes
+    from: the system keyring, the output of the shell command `command`, or
+    only the `GH_REVIEW_API_TOKEN` environment variable (None). This goes in
+    the user's configuration, not the repository's, and the token itself is
+    never written to either.
+    """
+    host = load_config().get("host", "github.com")
+    save_user_token_source(host, source, command)
+    set_token_source(source, command)
+
+
+def encryption_enabled(remote_only: bool = False) -> bool:
+    """
+    Whether review files are to be encrypted: for every review when the
+    `GH_REVIEW_ENCRYPT` environment variable is set, or for the reviews of
+    the current repository when its configuration says so.
+    """
+    if os.getenv("GH_REVIEW_ENCRYPT"):
+        return True
+    if remote_only:
+        return False
+    return bool(read_local_config().get("encrypt", False))
+
+
+def set_encryption(enabled: bool) -> int:
+    """
+    Turn the encryption of the current repository's review files on or off,
+    and rewrite its existing review files (and their action logs)
+    accordingly. Returns how many reviews were rewritten.
+
+    Raises `ValueError` if encrypting or decrypting fails, e.g. without a key.
+    """
+    config_path = get_config_file_path()
+    config = {}
+    if os.path.exists(config_path):
+        with open(config_path, encoding="utf-8") as f:
+            config = json.load(f)
+    config["encrypt"] = enabled
+    review_directory = get_review_directory()
+    review_files = [
+        os.path.join(review_directory, file_name)
+        for file_name in sorted(os.listdir(review_directory)) if file_name.endswith("-review.json")
+    ]
+    # Load everything first, so that a missing key doesn't leave the files
+    # half converted.
+    reviews = [Review.load(review_file) for review_file in review_files]
+    histories = [review.history() for review in reviews]
+    if enabled:
+        # Fails without a key or without PyNaCl.
+        encrypt_text("")
+    with open(config_path, "w", encoding="utf-8") as f:
+        json.dump(config, f, indent=2)
+    for review, history in zip(reviews, histories):
+        review.save()
+        review.rewrite_history(history)
+    return len(reviews)
+
+
+def targets_sourcehut() -> bool:
+    """
+    Whether reviews of the current repository target a sourcehut project.
+    """
+    return load_config().get("host") == SOURCEHUT_HOST
+
+
+def download_sourcehut_patchset(url: str) -> str:
+    """
+    Download the mbox of a patchset on lists.sr.ht, given the URL of its page
+    (`https://lists.sr.ht/~owner/list/patches/12345`), into the review
+    directory. Returns the path it was saved at.
+
+    Raises `ValueError` for other URLs and `requests.HTTPError` if the
+    download fails.
+    """
+    match = SOURCEHUT_PATCHSET_URL_PATTERN.match(url)
+    if match is None:
+        raise ValueError(f"Not the URL of a lists.sr.ht patchset: {url}")
+    owner, mailing_list, patchset = match.groups()
+    response = requests.get(f"https://{SOURCEHUT_LISTS_HOST}/{owner}/{mailing_list}/patches/{patchset}/mbox")
+    response.raise_for_status()
+    patches_directory = os.path.join(get_review_directory(), "patches")
+    os.makedirs(patches_directory, exist_ok=True)
+    patch_file = os.path.join(patches_directory, f"{owner.lstrip('~')}-{mailing_list}-{patchset}.mbox")
+    with open(patch_file, "wb") as f:
+        f.write(response.content)
+    return patch_file
+
+
+def get_configured_remote() -> Optional[str]:
+    """
+    Return the git remote chosen to define the repository reviews target,
+    if one was chosen.
+    """
+    return load_config().get("remote")
+
+
+SOURCEHUT_HOST = "git.sr.ht"
+SOURCEHUT_LISTS_HOST = "lists.sr.ht"
+SOURCEHUT_PATCHSET_URL_PATTERN = re.compile(r"^https://lists\.sr\.ht/(~[^/]+)/([^/]+)/patches/(\d+)/?")
+
+# Matches the host and `owner/repo` of remote URLs, whether over SSH
+# (`git@github.com:owner/repo.git`, `ssh://git@host:22/owner/repo`) or HTTPS.
+REMOTE_URL_PATTERN = re.compile(
+    r"^(?:[a-z+]+://)?(?:[^@/]+@)?(?P<host>[^:/]+)(?::\d+)?[:/](?:.*/)?(?P<owner>[^/:]+)/(?P<repo>[^/]+?)(?:\.git)?/?$"
+)
+
+
+def config_home(name: str) -> str:
+    """
+    Return the configuration directory of the tool `name`, e.g. `gh`.
+    """
+    config = os.getenv("XDG_CONFIG_HOME") or os.path.join(os.path.expanduser("~"), ".config")
+    return os.path.join(config, name)
+
+
+def configured_hosts(path: str, section: Optional[str] = None) -> List[str]:
+    """
+    Return the hosts configured in the YAML file at `path`: its top-level
+    keys, or the keys of the top-level `section`. This is just enough YAML to
+    read the host lists of `gh` and `glab`.
+    """
+    try:
+        with open(path, encoding="utf-8") as f:
+            lines = f.read().split("\n")
+    except OSError:
+        return []
+    hosts = []
+    in_section = section is None
+    indent = None
+    for line in lines:
+        if not line.strip() or line.lstrip().startswith("#"):
+            continue
+        depth = len(line) - len(line.lstrip())
+        key = line.strip()[:-1] if line.rstrip().endswith(":") else None
+        if section is None:
+            if depth == 0 and key:
+                hosts.append(key.strip("'\""))
+            continue
+        if depth == 0:
+            in_section = line.strip() == f"{section}:"
+            indent = None
+        elif in_section and key:
+            indent = depth if indent is None else indent
+            if depth == indent:
+                hosts.append(key.strip("'\""))
+    return hosts
+
+
+def detect_host_kind(host: str, probe: bool = True) -> Optional[str]:
+    """
+    Return whether `host` runs "github", "gitlab", "gitea" or "sourcehut",
+    or None if that can't be told. Hosts whose name doesn't give it away
+    (e.g. a GitHub Enterprise Server at `git.example.com`) are looked up in
+    the host lists of the `gh` and `glab` CLIs and in the hosts identified
+    before, and failing that, with `probe`, probed over their APIs (see
+    `probe_host_kind`). Probing can take several seconds, so it is left to
+    commands the user runs, never done when the editor starts.
+    """
+    if host == SOURCEHUT_HOST:
+        return "sourcehut"
+    if "github" in host:
+        return "github"
+    if "gitlab" in host:
+        return "gitlab"
+    gh_config = os.getenv("GH_CONFIG_DIR") or config_home("gh")
+    if host in configured_hosts(os.path.join(gh_config, "hosts.yml")):
+        return "github"
+    glab_config = os.getenv("GLAB_CONFIG_DIR") or config_home("glab-cli")
+    if host in configured_hosts(os.path.join(glab_config, "config.yml"), section="hosts"):
+        return "gitlab"
+    known_hosts = load_host_kinds()
+    if host in known_hosts:
+        return known_hosts[host]
+    if not probe:
+        return None
+    kind = probe_host_kind(host)
+    # Failed probes aren't remembered, since the host may just be unreachable.
+    if kind is not None:
+        known_hosts[host] = kind
+        save_host_kinds(known_hosts)
+    return kind
+
+
+# Endpoints only each kind of forge answers the way it does, tried in order.
+BACKEND_PROBES = [
+    ("github", "/api/v3/meta"),
+    ("gitlab", "/api/v4/version"),
+    ("gitea", "/api/v1/version"),
+]
+PROBE_TIMEOUT = 5
+
+
+def probe_host_kind(host: str) -> Optional[str]:
+    """
+    Tell which forge runs on `host` from how it answers well-known API
+    endpoints, or return None if none of them gives it away.
+    """
+    for kind, path in BACKEND_PROBES:
+        try:
+            response = requests.get(f"https://{host}{path}", timeout=PROBE_TIMEOUT)
+        except requests.RequestException:
+            continue
+        try:
+            body = response.json()
+        except ValueError:
+            body = None
+        if not isinstance(body, dict):
+            body = {}
+        if kind == "github" and (
+            "X-GitHub-Enterprise-Version" in response.headers or (response.ok and "installed_version" in body)
+        ):
+            return kind
+        # GitLab requires authentication even to tell its version.
+        if kind == "gitlab" and (
+            (response.ok and "revision" in body)
+            or (response.status_code == 401 and body.get("message") == "401 Unauthorized")
+        ):
+            return kind
+        if kind == "gitea" and response.ok and set(body) == {"version"}:
+            return kind
+    return None
+
+
+def get_host_kinds_file() -> str:
+    return os.path.join(get_data_directory(), "hosts.json")
+
+
+def load_host_kinds() -> Dict[str, str]:
+    try:
+        with open(get_host_kinds_file(), encoding="utf-8") as f:
+            return json.load(f)
+    except FileNotFoundError:
+        return {}
+
+
+def save_host_kinds(host_kinds: Dict[str, str]):
+    host_kinds_file = get_host_kinds_file()
+    os.makedirs(os.path.dirname(host_kinds_file), exist_ok=True)
+    with open(host_kinds_file, "w", encoding="utf-8") as f:
+        json.dump(host_kinds, f, indent=2)
+
+
+def get_worktree_root() -> Optional[str]:
+    """
+    Return the root of the working tree of the current repository, or None
+    outside of one.
+    """
+    result = subprocess.run(["git", "rev-parse", "--show-toplevel"], stdout=subprocess.PIPE, stderr=subprocess.DEVNULL)
+    if result.returncode != 0:
+        return None
+    return result.stdout.decode("utf-8").strip()
+
+
+def list_submodules(worktree_root: str) -> List[str]:
+    """
+    Return the paths of the submodules of the repository at `worktree_root`,
+    as listed in its `.gitmodules`.
+    """
+    result = subprocess.run(
+        ["git", "config", "--file", os.path.join(worktree_root, ".gitmodules"), "--get-regexp", r"^submodule\..*\.path$"],
+        stdout=subprocess.PIPE,
+        stderr=subprocess.DEVNULL,
+    )
+    if result.returncode != 0:
+        return []
+    return [
+        normalize_path(line.split(" ", 1)[1]).strip("/")
+        for line in result.stdout.decode("utf-8").splitlines() if " " in line
+    ]
+
+
+def submodule_containing(path: str, submodules: List[str]) -> Optional[str]:
+    """
+    Return the submodule (one of `submodules`) that repository path `path`
+    belongs to, or None if it belongs to the repository itself.
+    """
+    return next((submodule for submodule in submodules if path.startswith(submodule + "/")), None)
+
+
+def list_remotes() -> List[str]:
+    result = subprocess.run(["git", "remote"], stdout=subprocess.PIPE, stderr=subprocess.DEVNULL)
+    if result.returncode != 0:
+        return []
+    return result.stdout.decode("utf-8").split()
+
+
+def default_remote(remotes: List[str]) -> Optional[str]:
+    """
+    Return the remote among `remotes` git pushes and pulls the current branch
+    with by default, like Fugitive: the branch's upstream remote, `origin`
+    or else the first one. None if there are no remotes.
+    """
+    result = subprocess.run(
+        ["git", "rev-parse", "--abbrev-ref", "HEAD"], stdout=subprocess.PIPE, stderr=subprocess.DEVNULL
+    )
+    branch = result.stdout.decode("utf-8").strip() if result.returncode == 0 else ""
+    if branch and branch != "HEAD":
+        result = subprocess.run(
+            ["git", "config", "--get", f"branch.{branch}.remote"], stdout=subprocess.PIPE, stderr=subprocess.DEVNULL
+        )
+        upstream = result.stdout.decode("utf-8").strip()
+        if upstream in remotes:
+            return upstream
+    if "origin" in remotes:
+        return "origin"
+    return remotes[0] if remotes else None
+
+
+def remote_repository(remote: str) -> Optional[Tuple[str, str]]:
+    """
+    Return the host and `owner/repo` the git remote `remote` points to, or
+    None if its URL can't be read.
+    """
+    result = subprocess.run(
+        ["git", "remote", "get-url", remote], stdout=subprocess.PIPE, stderr=subprocess.DEVNULL
+    )
+    if result.returncode != 0:
+        return None
+    match = REMOTE_URL_PATTERN.search(result.stdout.decode("utf-8").strip())
+    if match is None:
+        return None
+    return match.group("host"), f"{match.group('owner')}/{match.group('repo')}"
+
+
+def configure_remote(remote: str, kind: Optional[str] = None, probe: bool = True) -> str:
+    """
+    Make the repository git remote `remote` points to the target of reviews,
+    and remember that choice. Returns that repository as `owner/repo`.
+
+    The kind of forge the remote's host runs is detected (probing the host
+    only with `probe`, see `detect_host_kind`), unless given as `kind` (e.g.
+    by the user), in which case it is remembered for the host.
+
+    Raises `ValueError` if the remote's URL can't be read or doesn't point
+    to a GitHub instance or sourcehut.
+    """
+    location = remote_repository(remote)
+    if location is None:
+        raise ValueError(f"Can't read the URL of remote {remote!r}.")
+    host, repository = location
+    if kind is None:
+        kind = detect_host_kind(host, probe)
+    else:
+        host_kinds = load_host_kinds()
+        host_kinds[host] = kind
+        save_host_kinds(host_kinds)
+    if kind == "sourcehut":
+        # Reviews of sourcehut projects are replies on their mailing list,
+        # so there is no API to point at.
+        update_configuration(repository, remote, host)
+        return repository
+    if kind == "gitlab":
+        raise ValueError(f"{host} is a GitLab instance, which isn't supported.")
+    if kind == "gitea":
+        raise ValueError(f"{host} runs Gitea, which isn't supported.")
+    if kind is None:
+        raise ValueError(
+            f"Can't tell whether {host} is a GitHub instance; log in to it with `gh auth login --hostname {host}`"
+            + ("." if probe else ", or run :ReviewerInit.")
+        )
+    set_host(host)
+    update_configuration(repository, remote, host)
+    return repository
 
 
 def add_comment(
@@ -240,7 +3985,7 @@ def list_review(pull_request: int):
 
 def submit_review(pull_request: int):
     review = get_review(pull_request)
-    review.publish(os.getenv("GH_REVIEW_API_TOKEN"))
+    review.publish(get_api_token())
 
 
 def get_args():
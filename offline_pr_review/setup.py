@@ -9,5 +9,11 @@ setup(
     install_requires=[
         'requests',
     ],
+    extras_require={
+        # Authenticating as a GitHub App.
+        'app': ['PyJWT[crypto]'],
+        # Encrypting review files, with the key kept in the system keyring.
+        'encryption': ['PyNaCl', 'keyring'],
+    },
     python_requires='>= 3.7',
 )
@@ -1,234 +1,4194 @@
+import logging
 import os
-from typing import Optional
+import re
+import subprocess
+import threading
+import time
+import webbrowser
+from typing import Callable, Dict, List, Optional, Set, Tuple
 from tempfile import NamedTemporaryFile
 
 import pynvim
+import requests
 import offline_pr_review
 
 MAX_QF_BODY_LENGTH = 500
 
+BufferLocation = Tuple[str, offline_pr_review.Side]
+
+# Fugitive buffers for a file at a specific revision are named like
+# `fugitive:///path/to/repo/.git//<revision>/path/to/file`. The revision is
+# usually a full commit SHA, or `0`-`3` for the index and merge stages, but
+# abbreviated objects and refs are accepted too. Buffers for a commit itself
+# (no path) are not files and do not match.
+FUGITIVE_BUFFER_PATTERN = re.compile(r'^fugitive://(?P<git_dir>.*?)//(?P<revision>[^/]+)/(?P<path>.+)$')
+
+# diffview.nvim names the buffers for non-working-tree revisions like
+# `diffview:///path/to/repo/.git/<abbreviated-sha>/path/to/file`, or with
+# `:<stage>:` in place of the commit for index buffers. The git directory is
+# the first `.git` of the name (or a worktree's or submodule's directory under
+# it), so that directories of the repository path named like a commit aren't
+# mistaken for the revision.
+DIFFVIEW_BUFFER_PATTERN = re.compile(
+    r'^diffview://(?P<git_dir>.*?/\.git(?:/(?:worktrees|modules)/[^/]+)*)'
+    r'/(?P<revision>[0-9a-f]{7,40}|:[0-3]:)/(?P<path>.*)$'
+)
+
+# Ask diffview which panel of the current view a buffer is shown in. Returns
+# 'a' for the left panel, 'b' (or 'c'/'d' for three-way layouts) otherwise, or
+# nil if the buffer is not part of the current view.
+DIFFVIEW_PANEL_LUA = """
+local bufnr = ...
+local ok, lib = pcall(require, 'diffview.lib')
+if not ok then return nil end
+local view = lib.get_current_view()
+if not view or not view.cur_layout then return nil end
+for _, panel in ipairs({ 'a', 'b', 'c', 'd' }) do
+  local win = view.cur_layout[panel]
+  if win and win.file and win.file.bufnr == bufnr then return panel end
+end
+return nil
+"""
+
+# gitsigns.nvim names the buffers opened by `:Gitsigns diffthis <revision>`
+# like `gitsigns:///path/to/repo/.git//<revision>:path/to/file`, where the
+# revision is `:0` for the index.
+GITSIGNS_BUFFER_PATTERN = re.compile(r'^gitsigns://(?P<git_dir>.*)//(?P<revision>:[0-3]|[^:]+):(?P<path>.*)$')
+
+# Generic git object buffers, e.g. `git://<revision>:path/to/file`.
+GIT_BUFFER_PATTERN = re.compile(r'^git://(?P<revision>[^:]+):(?P<path>.*)$')
+
+# Read-only buffers holding a file fetched from GitHub for reviews without a
+# local checkout are named like `reviewer://<side>/path/to/file`.
+REVIEWER_BUFFER_PATTERN = re.compile(r'^reviewer://(?P<side>LEFT|RIGHT)/(?P<path>.*)$')
+
+# Call the user's `vim.g.reviewer_buffer_resolver` Lua function, if any. It
+# receives the buffer number and name, and returns either nil to defer to the
+# built-in resolvers, or a table `{ path = "path/in/repo", side = "LEFT" }`.
+USER_RESOLVER_LUA = """
+local bufnr, name = ...
+local resolver = vim.g.reviewer_buffer_resolver
+if type(resolver) ~= 'function' then return nil end
+local location = resolver(bufnr, name)
+if type(location) ~= 'table' or location.path == nil then return nil end
+return { location.path, location.side or 'RIGHT' }
+"""
+
+# The subcommands of `:Reviewer` and the commands they run.
+REVIEWER_SUBCOMMANDS = {
+    'start': 'StartReview',
+    'resume': 'ResumeReview',
+    'remote': 'StartRemoteReview',
+    'local': 'StartLocalReview',
+    'patch': 'StartPatchReview',
+    'browse': 'BrowseReview',
+    'checkout': 'ReviewCheckout',
+    'inbox': 'ReviewInbox',
+    'refresh': 'ReviewRefresh',
+    'since-last': 'ReviewChangesSinceLast',
+    'stack-prev': 'ReviewStackPrev',
+    'stack-next': 'ReviewStackNext',
+    'comment': 'ReviewComment',
+    'edit': 'EditComment',
+    'delete': 'DeleteComment',
+    'note': 'ReviewNote',
+    'edit-note': 'EditNote',
+    'delete-note': 'DeleteNote',
+    'promote': 'PromoteNote',
+    'demote': 'DemoteComment',
+    'ready': 'ToggleCommentReady',
+    'body': 'ReviewBody',
+    'summarize': 'ReviewSummarize',
+    'goto': 'GotoComment',
+    'search': 'SearchComments',
+    'comments': 'QuickfixAllComments',
+    'file-comments': 'LoclistFileComments',
+    'files': 'ReviewFiles',
+    'next-file': 'ReviewNextFile',
+    'prev-file': 'ReviewPrevFile',
+    'viewed': 'ToggleFileViewed',
+    'files-to-args': 'ReviewFilesToArgs',
+    'files-to-quickfix': 'ReviewFilesToQuickfix',
+    'diff': 'ReviewDiffFile',
+    'status': 'ReviewStatus',
+    'history': 'ReviewHistory',
+    'checklist': 'ReviewChecklist',
+    'checklist-add': 'ReviewChecklistAdd',
+    'publish': 'PublishReview',
+    'pending': 'ReviewPendingDiff',
+    'export': 'ExportReview',
+    'signs': 'UpdateReviewSigns',
+    'request': 'RequestReviewers',
+    're-request': 'RequestReReview',
+    'suggest': 'SuggestReviewers',
+    'label': 'PrLabel',
+    'merge': 'MergePR',
+    'close': 'ClosePR',
+    'reopen': 'ReopenPR',
+    'open': 'OpenPR',
+    'open-comment': 'OpenComment',
+    'preview': 'PreviewComment',
+    'snippet': 'ReviewSnippet',
+    'yank-url': 'YankReviewUrl',
+    'jobs': 'ReviewerJobs',
+    'rate-limit': 'ReviewerRateLimit',
+    'cancel-job': 'ReviewerJobCancel',
+    'init': 'ReviewerInit',
+    'install-hooks': 'ReviewerInstallHooks',
+    'select-remote': 'ReviewerSelectRemote',
+    'encryption': 'ReviewerEncryption',
+}
+
+# Subcommands whose commands take a range, which `:Reviewer` passes on.
+REVIEWER_RANGE_SUBCOMMANDS = {'comment', 'edit', 'delete', 'note', 'edit-note', 'delete-note', 'promote', 'demote', 'ready', 'yank-url', 'snippet'}
+
+# Subcommands whose commands accept a bang, which `:Reviewer` passes on.
+REVIEWER_BANG_SUBCOMMANDS = {'signs', 'inbox', 'checklist-add', 'suggest', 'close', 'publish', 'install-hooks', 'snippet', 'delete-note', 'delete', 'next-file', 'prev-file', 'goto'}
+
+
+def comment_sign_name(count: int) -> str:
+    """
+    Return the name of the sign for a line with `count` comments.
+    """
+    if count == 1:
+        return 'PrReviewComment'
+    return f'PrReviewComment{count}' if count < 10 else 'PrReviewCommentMany'
+
+
+def completion_position(command_line: str) -> int:
+    """
+    Return the index of the argument being completed in `command_line`.
+    """
+    words = command_line.split()
+    return len(words) - 1 if command_line.endswith(' ') else len(words) - 2
+
+
+# What the plugin echoes (`g:reviewer_verbosity`): only errors, also the
+# results of commands, or also details for debugging, such as the payloads
+# published, the API requests made and the signs placed in each buffer.
+VERBOSITY_LEVELS = ('quiet', 'normal', 'verbose')
+
+
+class MessagesHandler(logging.Handler):
+    """
+    Echo the log records of the `offline_pr_review` package, from whichever
+    thread logs them.
+    """
+
+    def __init__(self, nvim: pynvim.api.Nvim):
+        super().__init__()
+        self.nvim = nvim
+
+    def emit(self, record: logging.LogRecord):
+        message = self.format(record)
+        self.nvim.async_call(lambda: self.nvim.out_write(f"{message}\n"))
+
+
+# Asks which remote reviews target, once Neovim is idle.
+SELECT_REMOTE_LUA = """
+local remotes = ...
+vim.schedule(function()
+  vim.ui.select(remotes, { prompt = 'Remote of the repository to review PRs of:' }, function(choice)
+    if choice then vim.fn.ReviewerSetRemote(choice) end
+  end)
+end)
+"""
+
+# Runs `git send-email` on the reply emails in a terminal, and tells the plugin
+# once they were sent.
+SEND_EMAIL_LUA = """
+local directory = ...
+vim.fn.termopen({ 'git', 'send-email', '--confirm=always', directory }, {
+  on_exit = function(_, code)
+    if code == 0 then vim.fn.ReviewerPatchRepliesSent() end
+  end,
+})
+"""
+
+
 @pynvim.plugin
 class TestPlugin(object):
     review_active: bool
     review: Optional[offline_pr_review.Review]
     in_progress_comment: Optional[offline_pr_review.Comment]
+    changed_files: List[offline_pr_review.ChangedFile]
 
     def __init__(self, nvim: pynvim.api.Nvim):
         self.review_active = False
         self.nvim = nvim
         self.in_progress_comment = None
+        self.changed_files = []
+        self.pull_request_head_sha = None
+        self.patches = []
+        self.jobs = offline_pr_review.JobRunner(lambda callback: self.nvim.async_call(callback))
+        logging.getLogger('offline_pr_review').addHandler(MessagesHandler(nvim))
+        self.verbosity()
+        self.poll_job = None
+        self.poll_state = None
+        self.files_panel_paths = []
+        # The line to jump to for each line of the files panel (for comment
+        # threads), and the fold each line of the panel starts, if any.
+        self.files_panel_lines = []
+        self.files_panel_folds = []
+        self.files_panel_remote_counts = {}
+        self.inbox_requests = []
+        # The requests listed in the inbox panel, in order, and whether it
+        # lists the ones set aside too.
+        self.inbox_panel_requests = []
+        self.inbox_show_all = False
+        self.repository_labels = {}
+        # Bumped whenever the review's comments may have changed; each
+        # buffer remembers the generation (and name) its signs were placed
+        # for, so that redisplaying it doesn't recompute them.
+        self.signs_generation = 0
+        self.signed_buffers: Dict[int, Tuple[int, str]] = {}
+        self.pending_sign_buffers: Set[int] = set()
+        self.sign_timer = None
         # Determine the upstream github URL from the configured git remote
-        remote_info = self.nvim.call('FugitiveRemote')
-        offline_pr_review.update_configuration(remote_info['path'].replace('.git', ''))
+        # (only possible inside a git repository; reviews without a local
+        # checkout are started with `:StartRemoteReview` instead).
+        if self.nvim.call('FugitiveGitDir'):
+            self.configure_review_target()
         self.nvim.command('sign define PrReviewComment text=C> texthl=Search linehl=DiffText')
+        # Lines with several comments show how many instead.
+        for count in range(2, 10):
+            self.nvim.command(f'sign define PrReviewComment{count} text={count}› texthl=Search linehl=DiffText')
+        self.nvim.command('sign define PrReviewCommentMany text=+› texthl=Search linehl=DiffText')
+        self.nvim.command('sign define PrReviewNote text=N> texthl=Todo')
+        self.preview_window = None
+        self.preview_position = None
+        self.codeowners = None
+        self.owner_identities = None
+        self.ignore_patterns = None
+        # The root of the reviewed repository's working tree and its
+        # submodules, looked up once per review.
+        self.worktree_submodules = None
+        # How many of the PR's changed files were left out of
+        # `self.changed_files` by the ignore patterns.
+        self.ignored_file_count = 0
+        # The folds of the files panel (files, comment threads and the notes
+        # section) the user left open, kept while the review is active.
+        self.files_panel_open_folds: Set[str] = set()
+        # The review file, comment IDs and `include_held` of the replies
+        # `git send-email` is sending, see `send_patch_replies`.
+        self.sent_patch_replies: Optional[Tuple[str, List[str], bool]] = None
+
+    def verbosity(self) -> str:
+        """
+        Return the `g:reviewer_verbosity` level, `normal` by default, and let
+        through the log records of the `offline_pr_review` package it shows.
+        """
+        level = self.nvim.vars.get('reviewer_verbosity', 'normal')
+        if level not in VERBOSITY_LEVELS:
+            level = 'normal'
+        logging.getLogger('offline_pr_review').setLevel(
+            {'quiet': logging.ERROR, 'normal': logging.WARNING, 'verbose': logging.DEBUG}[level]
+        )
+        return level
+
+    def info(self, message: str):
+        """
+        Echo the result of a command, unless `g:reviewer_verbosity` is `quiet`.
+        """
+        if self.verbosity() != 'quiet':
+            self.nvim.out_write(message)
+
+    def debug(self, message: str):
+        """
+        Echo a detail for debugging, if `g:reviewer_verbosity` is `verbose`.
+        """
+        if self.verbosity() == 'verbose':
+            self.nvim.out_write(message)
+
+    @pynvim.command("UpdateReviewSigns", bang=True)
+    def update_signs_command(self, bang):
+        """
+        Place the signs missing from the displayed buffers. With a bang,
+        rebuild the signs of every buffer from scratch.
+        """
+        if bang:
+            self.update_signs()
+        elif self.is_review_active():
+            self.pending_sign_buffers.update(
+                self.nvim.api.win_get_buf(window).handle for window in self.nvim.api.list_wins()
+            )
+            self.flush_sign_updates()
 
-    # TODO: Can only show signs for files that are already loaded in a buffer.
-    # Need to update signs if a new file is opened.
-    @pynvim.command("UpdateReviewSigns")
     def update_signs(self):
+        """
+        Place signs on the commented lines of every displayed buffer. Hidden
+        buffers get theirs when they are displayed again.
+        """
         if not self.is_review_active():
             return
 
-        self.nvim.command('sign unplace * group=PrReviewSigns')
-        self.sign_idx = 0
+        self.nvim.call('sign_unplace', 'PrReviewSigns')
+        namespace = self.nvim.api.create_namespace('reviewer-browse')
+        for buffer in self.nvim.buffers:
+            if buffer.handle in self.signed_buffers:
+                buffer.clear_namespace(namespace)
+        self.signs_generation += 1
+        self.signed_buffers = {}
+        displayed = {self.nvim.api.win_get_buf(window).handle for window in self.nvim.api.list_wins()}
         for buffer in self.nvim.buffers:
-            self.update_signs_in_buffer(buffer)
+            if buffer.handle in displayed:
+                self.place_signs(buffer)
 
-    def update_signs_in_buffer(self, buffer: pynvim.api.Buffer):
-        comments_in_buffer = [
-            c for c in self.review.comments
-            if os.path.join(self.repository_absolute_path(), c.path) == buffer.name
-        ]
-        for comment in comments_in_buffer:
-            start_line = comment.start_line or comment.line
-            end_line = comment.line
-            for line in range(start_line, end_line + 1):
-                self.sign_idx += 1
-                self.nvim.command(f'sign place {self.sign_idx} line={line} name=PrReviewComment group=PrReviewSigns buffer={buffer.handle}')
+    @pynvim.autocmd('BufWinEnter', pattern='*', eval='expand("<abuf>")')
+    def update_signs_on_display(self, buffer_number):
+        """
+        Queue the signs of a newly displayed buffer. Updates are debounced by
+        `g:reviewer_sign_debounce` milliseconds, so that quickly switching
+        through buffers only places signs in the ones that stay displayed.
+        """
+        if not self.is_review_active() or not buffer_number:
+            return
+        self.pending_sign_buffers.add(int(buffer_number))
+        if self.sign_timer is not None:
+            self.sign_timer.cancel()
+        delay = self.nvim.vars.get('reviewer_sign_debounce', 50) / 1000
+        self.sign_timer = threading.Timer(delay, lambda: self.nvim.async_call(self.flush_sign_updates))
+        self.sign_timer.daemon = True
+        self.sign_timer.start()
 
+    def flush_sign_updates(self):
+        pending, self.pending_sign_buffers = self.pending_sign_buffers, set()
+        if not self.is_review_active():
+            return
+        displayed = {self.nvim.api.win_get_buf(window).handle for window in self.nvim.api.list_wins()}
+        for buffer in self.nvim.buffers:
+            if buffer.handle not in pending or buffer.handle not in displayed:
+                continue
+            if self.signed_buffers.get(buffer.handle) == (self.signs_generation, buffer.name):
+                continue
+            self.nvim.call('sign_unplace', 'PrReviewSigns', {'buffer': buffer.handle})
+            self.place_signs(buffer)
 
-    def save(self):
-        self.review.save()
-        self.update_signs()
+    def place_signs(self, buffer: pynvim.api.Buffer):
+        """
+        Place the signs of `buffer` with a single `sign_placelist()` call,
+        which stays fast on reviews with many commented lines.
+        """
+        self.signed_buffers[buffer.handle] = (self.signs_generation, buffer.name)
+        self.update_buffer_status(buffer)
+        if self.is_patch_buffer(buffer):
+            lines = self.commented_patch_lines(self.review.comments)
+            note_lines = self.commented_patch_lines(self.review.notes)
+        elif self.is_ignored_buffer(buffer):
+            return
+        else:
+            lines = self.commented_lines(buffer, self.review.comments)
+            note_lines = self.commented_lines(buffer, self.review.notes)
+        if not lines and not note_lines:
+            return
+        self.debug(f"Placed {len(lines)} comment and {len(note_lines)} note signs in {buffer.name}.\n")
+        # Comments take precedence over notes on the lines that have both.
+        self.nvim.call('sign_placelist', [
+            {'buffer': buffer.handle, 'group': 'PrReviewSigns', 'name': comment_sign_name(count), 'lnum': line}
+            for line, count in sorted(lines.items())
+        ] + [
+            {'buffer': buffer.handle, 'group': 'PrReviewSigns', 'name': 'PrReviewNote', 'lnum': line}
+            for line in sorted(note_lines) if line not in lines
+        ])
+        if self.review.read_only and not self.is_patch_buffer(buffer):
+            self.place_comment_previews(buffer)
 
-    @pynvim.command('StartReview', nargs=1)
-    def start_review(self, args):
-        self.review = offline_pr_review.get_or_create_review(args[0])
-        self.review_active = True
-        self.update_signs()
+    def place_comment_previews(self, buffer: pynvim.api.Buffer):
+        """
+        Show the author and first line of each comment at the end of its last
+        line, for browsing reviews without opening every comment.
+        """
+        path, side = self.buffer_location(buffer)
+        namespace = self.nvim.api.create_namespace('reviewer-browse')
+        line_count = len(buffer)
+        for comment in self.review.comments:
+            if comment.path != path or comment.side != side or comment.line > line_count:
+                continue
+            first_line = comment.body.strip().split('\n')[0]
+            author = f'@{comment.author}: ' if comment.author else ''
+            buffer.api.set_extmark(namespace, comment.line - 1, 0, {
+                'virt_text': [(f'  {author}{first_line}', 'Comment')],
+                'virt_text_pos': 'eol',
+            })
+
+    def update_buffer_status(self, buffer: pynvim.api.Buffer):
+        """
+        Set `b:reviewer_status` to a summary of the review of the file in
+        `buffer`, like `● 3 comments ✓ viewed`, for use in a winbar or
+        statusline.
+        """
+        location = None if self.is_patch_buffer(buffer) else self.buffer_location(buffer)
+        if location is None:
+            buffer.vars['reviewer_status'] = ''
+            self.set_review_winbar(buffer, False)
+            return
+        path, _ = location
+        count = sum(1 for comment in self.review.comments if comment.path == path)
+        parts = []
+        if count:
+            parts.append(f'● {count} comment{"s" if count != 1 else ""}')
+        if self.review.is_file_viewed(path):
+            parts.append('✓ viewed')
+        owners = offline_pr_review.owners_of(self.codeowner_rules(), path)
+        if owners:
+            parts.append(' '.join(owners))
+        buffer.vars['reviewer_status'] = ' '.join(parts)
+        self.set_review_winbar(buffer, True)
+
+    def set_review_winbar(self, buffer: pynvim.api.Buffer, show: bool):
+        """
+        Show `b:reviewer_status` in the winbar of the windows displaying
+        `buffer` if `show` and `g:reviewer_winbar` is set, or take it out of
+        those it was put in. The winbar is set locally to each window, so
+        that other windows keep their own.
+        """
+        show = show and bool(self.nvim.vars.get('reviewer_winbar'))
+        for window in self.nvim.windows:
+            if window.buffer.handle != buffer.handle:
+                continue
+            if show:
+                self.nvim.call('win_execute', window.handle, "setlocal winbar=%{get(b:,'reviewer_status','')}")
+                window.vars['reviewer_winbar'] = 1
+            elif window.vars.get('reviewer_winbar'):
+                self.nvim.call('win_execute', window.handle, 'setlocal winbar<')
+                del window.vars['reviewer_winbar']
 
-    @pynvim.command('PublishReview')
-    def publish_review(self):
+    def codeowner_rules(self) -> List[offline_pr_review.OwnershipRule]:
         """
-        Publish the in-progress review to GitHub.
+        Return the CODEOWNERS rules of the active review's repository,
+        loading them the first time.
         """
-        if self.review_active:
-            self.review_active = False
-            result = self.review.publish(os.getenv("GH_REVIEW_API_TOKEN"))
-            self.nvim.out_write(f'{result}: {result.reason}\n')
+        if self.codeowners is None:
             try:
-                result.raise_for_status()
-            except Exception as e:
-                self.nvim.err_write(f'{result.text}\n')
-            self.update_signs()
-        else:
-            self.nvim.err_write("Cannot publish since no review is currently active.\n")
+                self.codeowners = self.review.fetch_codeowners()
+            except requests.RequestException:
+                self.codeowners = []
+        return self.codeowners
 
-    @pynvim.function('IsReviewActive', sync=True)
-    def is_review_active(self):
-        return self.review_active
+    def shared_setting(self, name: str, default):
+        """
+        Return the setting `name` of the reviewed repository's committed
+        `vim-reviewer.json`, or `default` if it doesn't set it (or the review
+        has no local checkout).
+        """
+        if self.review.remote_only:
+            return default
+        return offline_pr_review.shared_settings().get(name, default)
 
-    def repository_absolute_path(self) -> str:
-        return self.nvim.call('FugitiveWorkTree')
+    def load_repository_config(self):
+        """
+        Read and validate the configuration of the current repository (see
+        `offline_pr_review.load_repository_config`), reporting any problem.
+        """
+        try:
+            offline_pr_review.load_repository_config()
+        except (ValueError, OSError) as e:
+            self.nvim.err_write(f"{e}\n")
 
-    def current_buffer_path(self) -> Optional[str]:
+    def review_ignore_patterns(self) -> List[str]:
         """
-        Return the buffer's current path in the git repository, or None if it does not exist.
+        Return the patterns of the files to leave out of the review UI: those
+        of `g:reviewer_ignore` followed by the `ignore` setting of
+        `vim-reviewer.json` and the repository's `.reviewerignore`, loading
+        the latter two the first time.
+        """
+        if self.ignore_patterns is None:
+            self.ignore_patterns = list(self.shared_setting('ignore', []))
+            try:
+                self.ignore_patterns += self.review.fetch_ignore_patterns()
+            except requests.RequestException:
+                pass
+        return list(self.nvim.vars.get('reviewer_ignore', [])) + self.ignore_patterns
 
-        For example, a file called "test.py" within a parent directory called
-        "project" would return the path `project/test.py`.
+    def is_ignored_buffer(self, buffer: pynvim.api.Buffer) -> bool:
+        location = self.buffer_location(buffer)
+        return location is not None and offline_pr_review.is_ignored(location[0], self.review_ignore_patterns())
+
+    def without_ignored(self, changed_files: List[offline_pr_review.ChangedFile]) -> List[offline_pr_review.ChangedFile]:
         """
-        repository_root = self.nvim.call('FugitiveWorkTree')
-        current_buffer_path = self.nvim.current.buffer.name
-        if current_buffer_path.startswith('/') and repository_root:
-            return current_buffer_path.replace(repository_root + '/', '')
-        return None
+        Return `changed_files` minus the ignored ones, remembering how many
+        were left out for the files panel.
+        """
+        patterns = self.review_ignore_patterns()
+        kept = [f for f in changed_files if not offline_pr_review.is_ignored(f.path, patterns)]
+        self.ignored_file_count = len(changed_files) - len(kept)
+        return kept
 
-    # TODO: Add additional comments to an already-published review
+    def files_owned_by_others(self) -> List[str]:
+        """
+        Return the commented files that have code owners, none of which is
+        the user or one of their teams. The user's identities are taken from
+        `g:reviewer_codeowners_identities` (e.g. `['@me', '@org/team']`), or
+        looked up on GitHub.
+        """
+        rules = self.codeowner_rules()
+        if not rules:
+            return []
+        if self.owner_identities is None:
+            identities = self.nvim.vars.get('reviewer_codeowners_identities')
+            if identities is None:
+                try:
+                    identities = offline_pr_review.fetch_owner_identities()
+                except requests.RequestException:
+                    return []
+            self.owner_identities = {identity.lower() for identity in identities}
+        foreign = set()
+        for comment in self.review.comments:
+            owners = offline_pr_review.owners_of(rules, comment.path)
+            if owners and not {owner.lower() for owner in owners} & self.owner_identities:
+                foreign.add(comment.path)
+        return sorted(foreign)
+
+    def clear_buffer_statuses(self):
+        for buffer in self.nvim.buffers:
+            if buffer.vars.get('reviewer_status'):
+                buffer.vars['reviewer_status'] = ''
+        for window in self.nvim.windows:
+            if window.vars.get('reviewer_winbar'):
+                self.nvim.call('win_execute', window.handle, 'setlocal winbar<')
+                del window.vars['reviewer_winbar']
 
-    def new_temporary_buffer(self, on_save_command: Optional[str] = None):
+    def commented_lines(self, buffer: pynvim.api.Buffer, comments: List[offline_pr_review.Comment]) -> Dict[int, int]:
         """
-        Create a new buffer for a temporary file and open it in a split.
+        Return the number of `comments` (the review's comments or its notes)
+        on each commented line of `buffer`.
+        """
+        location = self.buffer_location(buffer)
+        if location is None:
+            return {}
+        path, side = location
+        lines: Dict[int, int] = {}
+        for comment in comments:
+            if comment.path == path and comment.side == side:
+                for line in range(comment.start_line or comment.line, comment.line + 1):
+                    lines[line] = lines.get(line, 0) + 1
+        return lines
 
-        Additionally, set the provided `on_save_command` to be executed on
-        buffer save.
+    def commented_patch_lines(self, comments: List[offline_pr_review.Comment]) -> Dict[int, int]:
+        lines: Dict[int, int] = {}
+        for comment in comments:
+            if comment.patch_index is None or comment.patch_index >= len(self.patches):
+                continue
+            file_diff = self.patches[comment.patch_index].find_file(comment.path)
+            if file_diff is None:
+                continue
+            for line in range(comment.start_line or comment.line, comment.line + 1):
+                diff_line = file_diff.find_line(line, comment.side)
+                if diff_line is not None:
+                    lines[diff_line.source_line] = lines.get(diff_line.source_line, 0) + 1
+        return lines
+
+    def is_patch_buffer(self, buffer: pynvim.api.Buffer) -> bool:
         """
-        with NamedTemporaryFile('w') as f:
-            # Open a new buffer and focus it
-            self.nvim.command(f'sp {f.name}')
-            # Use markdown highlighting
-            self.nvim.command('set ft=markdown')
-            if on_save_command:
-                # Set the on-save behavior for this buffer. This uses the buffer-local
-                # autocommands feature.
-                self.nvim.command(f'autocmd BufWritePost <buffer> :{on_save_command}')
+        Return whether `buffer` shows the patch series under review.
+        """
+        return bool(self.review_active and self.review.patch_file and buffer.name == self.review.patch_file)
 
-    def current_buffer_contents(self) -> str:
-        buffer_contents = self.nvim.current.buffer[:]
-        return '\n'.join(buffer_contents)
+    def save(self):
+        """
+        Save the active review, first asking what to do if its file was
+        changed by something else since it was loaded: merge both versions,
+        overwrite the file, or reload it and drop the change being saved.
+        Read-only reviews are never saved.
 
-    @pynvim.command('ReviewComment', sync=True, nargs="?", range='')
-    def review_comment(self, args, range):
+        If the review can't be written (e.g. without the encryption key), the
+        error is reported and the review stays as it is in memory, so that
+        saving can be tried again.
         """
-        Initiate a review comment for the given range selection.
+        try:
+            if not self.review.read_only and self.review.changed_on_disk():
+                choice = self.nvim.call(
+                    'confirm',
+                    "The review file was changed outside of this editor since it was loaded.",
+                    "&Merge\n&Overwrite\n&Reload",
+                    1,
+                )
+                if choice == 1:
+                    self.review = self.review.merged_with(self.review.reload())
+                elif choice == 3:
+                    self.review = self.review.reload()
+                    self.update_signs()
+                    self.info("Reloaded the review; your change was not saved.\n")
+                    return
+                elif choice != 2:
+                    self.info("The review was not saved.\n")
+                    return
+            self.review.save()
+        except ValueError as e:
+            self.nvim.err_write(f"Could not save the review: {e}\n")
+            return
+        self.update_signs()
 
-        This will open up a new buffer for the comment. The comment is saved to
-        disk at every write.
+    @pynvim.command('StartReview', nargs='+', complete='customlist,ReviewerCompleteStartReview')
+    def start_review(self, args):
         """
-        if self.in_progress_comment is not None:
-            self.nvim.err_write("A review comment is already being edited.\n")
+        Start (or resume) the review of a PR, e.g. `:StartReview 101`.
+
+        `:StartReview 101 --commits <sha1>..<sha2>` scopes the review to the
+        given range of commits, and `--commits all` removes that scope again.
+        """
+        commit_range = None
+        if len(args) == 3 and args[1] == '--commits':
+            commit_range = args[2]
+        elif len(args) != 1:
+            self.nvim.err_write("Usage: StartReview <pr-number> [--commits <start>..<end>]\n")
+            return
+        if commit_range is not None and commit_range != 'all' and commit_range.count('..') != 1:
+            self.nvim.err_write(f"Invalid commit range: {commit_range}\n")
+            return
+        try:
+            if offline_pr_review.targets_sourcehut():
+                self.nvim.err_write(
+                    "sourcehut projects are reviewed on their mailing list; use "
+                    ":StartPatchReview <lists.sr.ht patchset URL or mbox file> instead.\n"
+                )
+                return
+            review = offline_pr_review.get_or_create_review(args[0], commit_range)
+        except ValueError as e:
+            self.nvim.err_write(f"{e}\n")
             return
+        self.activate_review(review)
 
-        path = self.current_buffer_path()
-        if path is None:
-            self.nvim.err_write("Current buffer is not a valid path in the git repository.\n")
+    @pynvim.command('ResumeReview', nargs=1, complete='customlist,ReviewerCompletePullRequest')
+    def resume_review(self, args):
+        """
+        Resume the review of a PR, e.g. `:ResumeReview 101`, reopening the
+        files, splits and cursor positions it had when Neovim was last closed
+        or another review was started.
+        """
+        if not args[0].isdigit():
+            self.nvim.err_write("Usage: ResumeReview <pr-number>\n")
             return
-        multi_line = range[0] != range[1]
-        self.in_progress_comment = offline_pr_review.Comment(
-            body="",
-            path=path,
-            line=range[1],
-            start_line=range[0] if multi_line else range[0] - 1,
-            # TODO: eventually get better side detection from buffer names
-            side='RIGHT',
-            start_side='RIGHT'
+        try:
+            review = offline_pr_review.get_or_create_review(args[0])
+        except ValueError as e:
+            self.nvim.err_write(f"{e}\n")
+            return
+        self.activate_review(review)
+        if not self.review_active or self.review is not review:
+            return
+        layout = self.review.load_layout()
+        if layout is None:
+            self.info(f"No window layout was saved for {self.review.title}.\n")
+            return
+        self.nvim.command('only')
+        self.restore_layout(layout)
+        self.nvim.command('wincmd =')
+
+    @pynvim.function('ReviewerCompletePullRequest', sync=True)
+    def complete_pull_request(self, args):
+        return [number for number in self.pull_request_numbers() if number.startswith(args[0])]
+
+    @pynvim.autocmd('VimLeavePre', pattern='*', sync=True)
+    def save_layout(self):
+        """
+        Save the window layout of the current tab page for `:ResumeReview`:
+        which files are shown, how the windows are split, where the cursors
+        are and which windows are diffed. Panels and comment buffers are left
+        out.
+        """
+        if not self.review_active or self.review.read_only:
+            return
+        layout = self.window_layout(self.nvim.call('winlayout'))
+        if layout is not None:
+            self.review.save_layout(layout)
+
+    def window_layout(self, node: list) -> Optional[list]:
+        kind, content = node
+        if kind == 'leaf':
+            buffer = self.nvim.api.win_get_buf(content)
+            if (
+                not buffer.name
+                or buffer.options['buftype'] not in ('', 'nowrite')
+                or 'reviewer_comment' in buffer.vars
+            ):
+                return None
+            return ['leaf', {
+                'name': buffer.name,
+                'cursor': self.nvim.api.win_get_cursor(content),
+                'diff': bool(self.nvim.call('getwinvar', content, '&diff')),
+            }]
+        children = [layout for layout in (self.window_layout(child) for child in content) if layout is not None]
+        if len(children) <= 1:
+            return children[0] if children else None
+        return [kind, children]
+
+    def restore_layout(self, node: list):
+        """
+        Recreate, in the current window, a layout saved by `save_layout`.
+        """
+        kind, content = node
+        if kind == 'leaf':
+            try:
+                self.nvim.command(f'edit {self.nvim.call("fnameescape", content["name"])}')
+                self.nvim.current.window.cursor = tuple(content['cursor'])
+            except pynvim.NvimError as e:
+                self.nvim.err_write(f"Could not restore {content['name']}: {e}\n")
+            if content['diff']:
+                self.nvim.command('diffthis')
+            return
+        windows = [self.nvim.current.window]
+        for _ in content[1:]:
+            self.nvim.command('belowright vsplit' if kind == 'row' else 'belowright split')
+            windows.append(self.nvim.current.window)
+        for window, child in zip(windows, content):
+            self.nvim.current.window = window
+            self.restore_layout(child)
+        self.nvim.current.window = windows[0]
+
+    @pynvim.function('ReviewerCompleteStartReview', sync=True)
+    def complete_start_review(self, args):
+        """
+        Complete `:StartReview`: a PR number, then `--commits`, then `all`.
+        """
+        arg_lead, command_line = args[0], args[1]
+        position = completion_position(command_line)
+        if position == 0:
+            candidates = self.pull_request_numbers()
+        elif position == 1:
+            candidates = ['--commits']
+        else:
+            candidates = ['all']
+        return [candidate for candidate in candidates if candidate.startswith(arg_lead)]
+
+    def pull_request_numbers(self) -> List[str]:
+        """
+        Return the numbers of the PRs of this repository with a saved review
+        or waiting in the review inbox, for completion.
+        """
+        try:
+            numbers = set(offline_pr_review.list_saved_reviews())
+            owner, repo = offline_pr_review.get_repo_from_config()
+        except (subprocess.CalledProcessError, OSError, KeyError, ValueError):
+            return []
+        numbers.update(
+            request.pr_number for request in self.inbox_requests
+            if (request.owner, request.repo) == (owner, repo)
         )
-        self.new_temporary_buffer(on_save_command='SaveComment new')
+        return [str(number) for number in sorted(numbers)]
 
+    @pynvim.command('Reviewer', nargs='+', range='', bang=True, complete='customlist,ReviewerCompleteSubcommand', sync=True)
+    def reviewer(self, args, range, bang):
+        """
+        Run one of the plugin's commands through a subcommand, e.g.
+        `:Reviewer start 101` for `:StartReview 101` or `:Reviewer publish
+        approve` for `:PublishReview approve`.
+        """
+        subcommand, arguments = args[0], args[1:]
+        command = REVIEWER_SUBCOMMANDS.get(subcommand)
+        if command is None:
+            self.nvim.err_write(f"Unknown subcommand {subcommand!r}; see :Reviewer <Tab>.\n")
+            return
+        if bang and subcommand not in REVIEWER_BANG_SUBCOMMANDS:
+            self.nvim.err_write(f":Reviewer {subcommand} doesn't accept a bang.\n")
+            return
+        line_range = f'{range[0]},{range[1]}' if subcommand in REVIEWER_RANGE_SUBCOMMANDS else ''
+        self.nvim.command(f'{line_range}{command}{"!" if bang else ""} {" ".join(arguments)}'.rstrip())
 
-    @pynvim.command('SaveComment', nargs="*", sync=True)
-    def save_comment(self, args):
+    @pynvim.function('ReviewerCompleteSubcommand', sync=True)
+    def complete_subcommand(self, args):
         """
-        Save the contents of the comment buffer to disk.
+        Complete the subcommands of `:Reviewer`, then the arguments of the
+        command the subcommand runs, with that command's own completion.
+        """
+        arg_lead, command_line = args[0], args[1]
+        if completion_position(command_line) == 0:
+            return [subcommand for subcommand in REVIEWER_SUBCOMMANDS if subcommand.startswith(arg_lead)]
+        subcommand, _, rest = command_line.split(None, 1)[1].partition(' ')
+        command = REVIEWER_SUBCOMMANDS.get(subcommand)
+        if command is None:
+            return []
+        return self.nvim.call('getcompletion', f'{command} {rest}', 'cmdline')
 
-        This command is set to be triggered on `BufWritePost` for the comment
-        buffer (e.g., on every write).
+    @pynvim.command('ReviewInbox', bang=True, sync=True)
+    def review_inbox(self, bang):
+        """
+        Open a panel listing the open PRs, across all repositories, where your
+        review is requested. `<CR>` starts the review of the PR under the cursor,
+        `s` snoozes it and `d` dismisses it.
 
-        Note that this command _must_ be `sync=True`, otherwise the buffer
-        contents will be empty before they can be accessed in the case of a
-        save-and-exit command (`:wq`).
+        `:ReviewInbox!` also lists the snoozed and dismissed PRs, which `r`
+        restores.
         """
-        is_new_comment = args[0] == 'new'
-        self.in_progress_comment.body = self.current_buffer_contents()
-        if is_new_comment:
-            self.review.add_comment(self.in_progress_comment)
-        self.in_progress_comment = None
-        self.save()
+        try:
+            self.inbox_requests = offline_pr_review.fetch_review_requests()
+        except requests.RequestException as e:
+            self.nvim.err_write(f"Could not fetch your review requests: {e}\n")
+            return
+        triage = offline_pr_review.InboxTriage.load()
+        triage.prune(self.inbox_requests)
+        self.inbox_show_all = bang
+        self.inbox_panel_requests = [
+            request for request in self.inbox_requests if bang or triage.status(request) is None
+        ]
+        if not self.inbox_panel_requests:
+            set_aside = len(self.inbox_requests)
+            self.nvim.out_write(
+                "No PRs are waiting for your review"
+                f"{f' ({set_aside} snoozed or dismissed; see :ReviewInbox!)' if set_aside else ''}.\n"
+            )
+            return
+        self.nvim.command(f'botright {min(len(self.inbox_panel_requests), 15)}new')
+        self.nvim.command('setlocal buftype=nofile bufhidden=wipe noswapfile nobuflisted nowrap')
+        self.nvim.command('setlocal filetype=reviewer-inbox')
+        self.render_inbox(self.nvim.current.buffer, triage)
+        self.nvim.command("nnoremap <buffer> <silent> <CR> :call ReviewInboxStart()<CR>")
+        self.nvim.command("nnoremap <buffer> <silent> s :call ReviewInboxTriage('snooze')<CR>")
+        self.nvim.command("nnoremap <buffer> <silent> d :call ReviewInboxTriage('dismiss')<CR>")
+        self.nvim.command("nnoremap <buffer> <silent> r :call ReviewInboxTriage('restore')<CR>")
 
-    @pynvim.command('ReviewBody', sync=True)
-    def review_body(self):
-        if self.is_review_active():
-            self.new_temporary_buffer(on_save_command='SaveReviewBody')
-            self.nvim.current.buffer[:] = self.review.body.split('\n')
+    def render_inbox(self, buffer: pynvim.api.Buffer, triage: offline_pr_review.InboxTriage):
+        lines = []
+        for request in self.inbox_panel_requests:
+            line = f'{request.key}  {request.age:>5}  {request.title} ({request.author})'
+            status = triage.status(request)
+            if status is not None:
+                line += f'  [{status}]'
+            lines.append(line)
+        buffer.options['modifiable'] = True
+        buffer[:] = lines
+        buffer.options['modifiable'] = False
+
+    @pynvim.function('ReviewInboxTriage', sync=True)
+    def review_inbox_triage(self, args):
+        """
+        Snooze, dismiss or restore the PR under the cursor in the inbox panel.
+        Snoozing asks for how long, e.g. `4h`, `2d` or `1w`.
+        """
+        action = args[0]
+        line_number = self.nvim.current.window.cursor[0]
+        if line_number > len(self.inbox_panel_requests):
+            return
+        request = self.inbox_panel_requests[line_number - 1]
+        triage = offline_pr_review.InboxTriage.load()
+        if action == 'snooze':
+            answer = self.nvim.call('input', f'Snooze {request.key} for: ', '1d')
+            if not answer:
+                return
+            duration = offline_pr_review.parse_duration(answer)
+            if duration is None:
+                self.nvim.err_write(f"\nInvalid duration {answer!r}; use e.g. 4h, 2d or 1w.\n")
+                return
+            triage.snooze(request, duration)
+        elif action == 'dismiss':
+            triage.dismiss(request)
         else:
-            self.nvim.err_write("No review is currently active.\n")
+            triage.restore(request)
+        if not self.inbox_show_all and triage.status(request) is not None:
+            self.inbox_panel_requests.remove(request)
+        if not self.inbox_panel_requests:
+            self.nvim.command('bwipeout')
+            self.info("No more PRs are waiting for your review.\n")
+            return
+        self.render_inbox(self.nvim.current.buffer, triage)
 
-    @pynvim.command('SaveReviewBody', sync=True)
-    def save_review_body(self):
+    @pynvim.function('ReviewInboxStart', sync=True)
+    def review_inbox_start(self, args):
         """
-        Save the contents of the review body buffer to disk.
+        Start the review of the PR under the cursor in the inbox panel.
 
-        This command is set to be triggered on `BufWritePost` for the review body
-        buffer (e.g., on every write).
+        PRs of the current repository are reviewed like with `:StartReview`,
+        others like with `:StartRemoteReview`.
+        """
+        line_number = self.nvim.current.window.cursor[0]
+        if line_number > len(self.inbox_panel_requests):
+            return
+        request = self.inbox_panel_requests[line_number - 1]
+        try:
+            current_repo = offline_pr_review.get_repo_from_config()
+        except (OSError, subprocess.CalledProcessError, ValueError):
+            current_repo = None
+        self.nvim.command('bwipeout')
+        try:
+            if current_repo == (request.owner, request.repo):
+                review = offline_pr_review.get_or_create_review(request.pr_number)
+            else:
+                review = offline_pr_review.get_or_create_remote_review(request.owner, request.repo, request.pr_number)
+        except ValueError as e:
+            self.nvim.err_write(f"{e}\n")
+            return
+        self.activate_review(review)
+        if not self.review_active or self.review is not review:
+            return
+        self.info(f"Started the review of {self.review.title}: {request.title}\n")
 
-        Note that this command _must_ be `sync=True`, otherwise the buffer
-        contents will be empty before they can be accessed in the case of a
-        save-and-exit command (`:wq`).
+    @pynvim.autocmd('FocusGained', pattern='*')
+    def check_review_file(self):
         """
-        if self.is_review_active():
-            self.review.body = self.current_buffer_contents()
-            self.save()
+        Pick up changes made to the review file while Neovim was in the
+        background, e.g. by another instance or a `git pull`.
+        """
+        self.is_review_active()
 
-    @pynvim.command('EditComment', nargs="*", range="")
-    def edit_comment(self, args, range):
+    @pynvim.autocmd('VimEnter', pattern='*')
+    def check_inbox_on_startup(self):
         """
-        Open up the comment for the line under the cursor, if one exists.
+        With `g:reviewer_inbox_on_startup` set, tell how many PRs are waiting
+        for your review when Vim starts.
         """
-        path = self.current_buffer_path()
-        if path is None:
-            self.nvim.err_write("Current buffer is not a valid path in the git repository.\n")
+        if not self.nvim.vars.get('reviewer_inbox_on_startup'):
             return
-        comment_to_edit = self.review.get_comment_at_position(path, range[0])
-        if comment_to_edit is None:
-            self.nvim.err_write("No comment under the cursor.\n")
+
+        def report(pending):
+            triage = offline_pr_review.InboxTriage.load()
+            actionable = [request for request in pending if triage.status(request) is None]
+            if actionable:
+                self.notify(f"{len(actionable)} PRs are waiting for your review. Use :ReviewInbox to list them.")
+
+        self.jobs.submit('Check the review inbox', lambda job: offline_pr_review.fetch_review_requests(), on_done=report)
+
+    @pynvim.command('StartRemoteReview', nargs='+', complete='customlist,ReviewerCompleteRemoteReview')
+    def start_remote_review(self, args):
+        """
+        Start a review of a PR in a repository without a local checkout, e.g.
+        `:StartRemoteReview owner/repo 101`.
+
+        Files are fetched from GitHub into read-only buffers when opened from
+        `:ReviewFiles` or `:ReviewNextFile`.
+        """
+        if len(args) != 2 or args[0].count('/') != 1:
+            self.nvim.err_write("Usage: StartRemoteReview <owner>/<repo> <pr-number>\n")
+            return
+        owner, repo = args[0].split('/')
+        try:
+            review = offline_pr_review.get_or_create_remote_review(owner, repo, int(args[1]))
+        except ValueError as e:
+            self.nvim.err_write(f"{e}\n")
             return
+        self.activate_review(review)
 
-        self.in_progress_comment = comment_to_edit
-        self.new_temporary_buffer(on_save_command='SaveComment existing')
-        self.nvim.current.buffer[:] = self.in_progress_comment.body.split('\n')
+    @pynvim.function('ReviewerCompleteRemoteReview', sync=True)
+    def complete_remote_review(self, args):
+        """
+        Complete `:StartRemoteReview` from the reviews saved without a local
+        checkout: the repository, then the PR number.
+        """
+        arg_lead, command_line = args[0], args[1]
+        reviews = offline_pr_review.list_saved_remote_reviews()
+        if completion_position(command_line) == 0:
+            candidates = sorted({f'{owner}/{repo}' for owner, repo, _ in reviews})
+        else:
+            repository = command_line.split()[1]
+            candidates = [str(number) for owner, repo, number in reviews if f'{owner}/{repo}' == repository]
+        return [candidate for candidate in candidates if candidate.startswith(arg_lead)]
 
-    @pynvim.command('DeleteComment', nargs="*", range="")
-    def delete_comment(self, args, range):
+    @pynvim.command('StartLocalReview', nargs=1, complete='customlist,ReviewerCompleteCommitRange')
+    def start_local_review(self, args):
         """
-        Delete the comment for the line under the cursor, if one exists.
+        Start a review of a range of commits that isn't tied to any PR, e.g.
+        `:StartLocalReview origin/main..HEAD`.
+
+        Local reviews can't be published; use `:ExportReview` instead.
         """
-        path = self.current_buffer_path()
-        if path is None:
-            self.nvim.err_write("Current buffer is not a valid path in the git repository.\n")
+        commit_range = args[0]
+        if commit_range.count('..') != 1:
+            self.nvim.err_write("Usage: StartLocalReview <base-ref>..<head-ref>\n")
             return
-        comment_to_delete = self.review.get_comment_at_position(path, range[0])
-        if comment_to_delete is None:
-            self.nvim.err_write("No comment under the cursor.\n")
+        self.activate_review(offline_pr_review.get_or_create_local_review(commit_range))
+
+    @pynvim.function('ReviewerCompleteCommitRange', sync=True)
+    def complete_commit_range(self, args):
+        """
+        Complete `<base-ref>..<head-ref>` with the branches and tags of the
+        repository.
+        """
+        arg_lead = args[0]
+        base, separator, head = arg_lead.rpartition('..')
+        prefix = base + separator
+        return [prefix + ref for ref in offline_pr_review.list_refs() if ref.startswith(head)]
+
+    @pynvim.command('StartPatchReview', nargs=1, complete='file')
+    def start_patch_review(self, args):
+        """
+        Start a review of a `.patch` file or mbox series, e.g. one produced by
+        `git format-patch` or saved from a mailing list.
+
+        Comments are left from the patch file itself. Patch reviews can't be
+        published; `:ExportReview` produces an inline reply instead. In
+        sourcehut projects, the URL of a patchset on lists.sr.ht can be given
+        instead of a file, and `:PublishReview` sends the replies.
+        """
+        patch_file = os.path.expanduser(args[0])
+        if patch_file.startswith('https://'):
+            try:
+                patch_file = offline_pr_review.download_sourcehut_patchset(patch_file)
+            except (ValueError, requests.RequestException) as e:
+                self.nvim.err_write(f"Could not download the patchset: {e}\n")
+                return
+        if not os.path.isfile(patch_file):
+            self.nvim.err_write(f"No such patch file: {patch_file}\n")
             return
+        review = offline_pr_review.get_or_create_patch_review(patch_file)
+        self.nvim.command(f'edit {self.nvim.call("fnameescape", review.patch_file)}')
+        self.activate_review(review)
 
-        self.review.delete_comment(comment_to_delete)
-        self.nvim.out_write("Comment deleted.\n")
+    @pynvim.command('BrowseReview', nargs=1, complete='customlist,ReviewerCompleteBrowseReview')
+    def browse_review(self, args):
+        """
+        Open a review read-only: someone else's review file (e.g.
+        `:BrowseReview ~/Downloads/101.json`), or with a PR number, the
+        reviews published on that PR. Signs, comment navigation and the files
+        panel work as usual, but nothing can be changed.
+        """
+        if args[0].isdigit():
+            try:
+                owner, repo = offline_pr_review.get_repo_from_config()
+                review = offline_pr_review.Review.published(owner, repo, int(args[0]))
+            except (OSError, KeyError, ValueError) as e:
+                self.nvim.err_write(f"Could not determine the repository of the PR: {e}\n")
+                return
+            except requests.RequestException as e:
+                self.nvim.err_write(f"Could not fetch the reviews of PR #{args[0]}: {e}\n")
+                return
+        else:
+            review_file = os.path.expanduser(args[0])
+            try:
+                review = offline_pr_review.Review.load(review_file)
+            except (OSError, ValueError, KeyError) as e:
+                self.nvim.err_write(f"Could not read the review in {review_file}: {e}\n")
+                return
+            review.read_only = True
+        self.activate_review(review)
+        self.info(
+            f"Browsing {review.title} read-only ({len(review.comments)} comments); "
+            "use :QuickfixAllComments to go through them.\n"
+        )
+
+    @pynvim.function('ReviewerCompleteBrowseReview', sync=True)
+    def complete_browse_review(self, args):
+        numbers = [number for number in self.pull_request_numbers() if number.startswith(args[0])]
+        return numbers + self.nvim.call('getcompletion', args[0], 'file')
+
+    def refuse_if_read_only(self) -> bool:
+        """
+        Report and return True if the active review was opened with
+        `:BrowseReview`, which doesn't allow changing it.
+        """
+        if self.review_active and self.review.read_only:
+            self.nvim.err_write("This review is read-only; use :StartReview to write your own.\n")
+            return True
+        return False
+
+    def check_access(self, review: offline_pr_review.Review) -> bool:
+        """
+        Check that the PR of `review` exists and that the API token works,
+        reporting any problem now rather than when publishing. Returns False
+        if the PR doesn't exist (or can't be accessed).
+
+        Without network access, the review is started anyway so that it can
+        be drafted offline.
+        """
+        token = None
+        try:
+            token = offline_pr_review.get_api_token()
+            if not token:
+                self.nvim.err_write(
+                    "There is no API token. Create a token with access to the repository "
+                    "(https://github.com/settings/tokens) and export it as GH_REVIEW_API_TOKEN (or set it up "
+                    "with :ReviewerInit) to fetch PR data and publish.\n"
+                )
+            review.pull_request(refresh=True)
+        except ValueError as e:
+            self.nvim.err_write(f"{e}\n")
+        except requests.HTTPError as e:
+            status = e.response.status_code
+            if status == 401:
+                self.nvim.err_write("GitHub rejected the API token; it may have expired or been revoked.\n")
+            elif status == 404:
+                self.nvim.err_write(
+                    f"PR #{review.pr_number} was not found in {review.owner}/{review.repo}"
+                    f"{'' if token else ' (private repositories require a token)'}.\n"
+                )
+                return False
+            elif status == 403:
+                self.nvim.err_write(f"GitHub denied access to {review.owner}/{review.repo}: {e}\n")
+        except requests.RequestException:
+            pass
+        return True
+
+    def activate_review(self, review: offline_pr_review.Review):
+        if not review.remote_only:
+            self.load_repository_config()
+        if not review.local_only and not review.read_only and not self.check_access(review):
+            return
+        if self.review_active and self.review.file_path() != review.file_path():
+            self.save_layout()
+        self.review = review
+        self.review_active = True
+        self.changed_files = []
+        self.pull_request_head_sha = None
+        self.patches = review.patches() if review.patch_file else []
+        self.codeowners = None
+        self.ignore_patterns = None
+        self.worktree_submodules = None
+        self.ignored_file_count = 0
+        self.files_panel_open_folds = set()
         self.update_signs()
+        if review.read_only:
+            self.stop_polling()
+            return
+        self.apply_checklist_template()
+        self.start_polling()
+        if not review.local_only:
+            self.report_stack_parent()
+        if review.last_reviewed_head_sha and review.commit_range is None and not review.local_only:
+            head_sha = self.fetch_head_sha()
+            if head_sha is not None and head_sha != review.last_reviewed_head_sha:
+                self.info(
+                    "The PR has changed since your last review. "
+                    "Use :ReviewChangesSinceLast to review only the new changes.\n"
+                )
 
-    @pynvim.command('QuickfixAllComments')
-    def quickfix_all_comments(self):
-        comment_dictionaries = [
-            {'filename': c.path, 'lnum': c.line, 'text': c.body[:MAX_QF_BODY_LENGTH]}
-            for c in self.review.comments
-        ]
-        self.nvim.call("setqflist", comment_dictionaries, ' ')
+    def apply_checklist_template(self):
+        """
+        Fill the checklist of a new review from `g:reviewer_checklist_template`
+        (or the `checklist_template` setting of `vim-reviewer.json`), a list of
+        items that are either strings or dictionaries like
+        `{'text': 'Migrations are reversible', 'required': v:true}`.
+        """
+        template = self.nvim.vars.get('reviewer_checklist_template')
+        if template is None:
+            template = self.shared_setting('checklist_template', [])
+        if self.review.checklist or not template:
+            return
+        for item in template:
+            if isinstance(item, dict):
+                self.review.add_checklist_item(item['text'], bool(item.get('required')))
+            else:
+                self.review.add_checklist_item(item)
+        self.save()
+
+    @pynvim.command('ReviewChecklist', sync=True)
+    def review_checklist(self):
+        """
+        Open a panel showing the review's checklist. `<CR>` or `x` toggles the
+        item under the cursor. Required items are marked with a `*`.
+        """
+        if not self.is_review_active():
+            self.nvim.err_write("No review is currently active.\n")
+            return
+        if not self.review.checklist:
+            self.nvim.err_write("The checklist is empty; add items with :ReviewChecklistAdd.\n")
+            return
+        self.nvim.command(f'botright {len(self.review.checklist) + 2}new')
+        self.nvim.command('setlocal buftype=nofile bufhidden=wipe noswapfile nobuflisted nowrap')
+        self.nvim.command('setlocal filetype=reviewer-checklist')
+        self.render_checklist(self.nvim.current.buffer)
+        self.nvim.command("nnoremap <buffer> <silent> <CR> :call ReviewChecklistToggle()<CR>")
+        self.nvim.command("nnoremap <buffer> <silent> x :call ReviewChecklistToggle()<CR>")
+
+    def render_checklist(self, buffer: pynvim.api.Buffer):
+        checked_count = len([item for item in self.review.checklist if item.checked])
+        lines = [f'{self.review.title} checklist: {checked_count}/{len(self.review.checklist)} checked', '']
+        for item in self.review.checklist:
+            lines.append(f"[{'x' if item.checked else ' '}] {item.text}{' *' if item.required else ''}")
+        buffer.options['modifiable'] = True
+        buffer[:] = lines
+        buffer.options['modifiable'] = False
+
+    @pynvim.function('ReviewChecklistToggle', sync=True)
+    def review_checklist_toggle(self, args):
+        # The items start on the third line of the panel.
+        index = self.nvim.current.window.cursor[0] - 3
+        if not 0 <= index < len(self.review.checklist) or self.refuse_if_read_only():
+            return
+        self.review.toggle_checklist_item(index)
+        self.render_checklist(self.nvim.current.buffer)
+
+    @pynvim.command('ReviewChecklistAdd', nargs='+', bang=True, sync=True)
+    def review_checklist_add(self, args, bang):
+        """
+        Add an item to the review's checklist, e.g. `:ReviewChecklistAdd Docs
+        updated`. `:ReviewChecklistAdd!` adds a required item.
+        """
+        if not self.is_review_active():
+            self.nvim.err_write("No review is currently active.\n")
+            return
+        if self.refuse_if_read_only():
+            return
+        self.review.add_checklist_item(' '.join(args), required=bang)
+        self.save()
+
+    @pynvim.command('ReviewRefresh')
+    def review_refresh(self):
+        """
+        Drop the cached PR metadata and diff, and fetch the metadata again
+        from GitHub.
+        """
+        if not self.is_review_active() or self.review.local_only:
+            self.nvim.err_write("No PR review is currently active.\n")
+            return
+        self.review.clear_metadata_cache()
+        try:
+            pull_request = self.review.pull_request(refresh=True)
+        except requests.RequestException as e:
+            self.nvim.err_write(f"Could not refresh the PR metadata: {e}\n")
+            return
+        self.pull_request_head_sha = None
+        self.changed_files = []
+        self.update_signs()
+        self.info(f"Refreshed {self.review.title} (now at {pull_request['head']['sha'][:12]}).\n")
+
+    def notify(self, message: str, level: str = 'INFO'):
+        self.nvim.exec_lua('vim.notify(...)', message, self.nvim.exec_lua(f'return vim.log.levels.{level}'))
+
+    def start_polling(self):
+        """
+        Start polling the active PR for updates every
+        `g:reviewer_poll_interval` seconds, if that is set.
+        """
+        self.stop_polling()
+        interval = self.nvim.vars.get('reviewer_poll_interval', 0)
+        if not interval or self.review.local_only:
+            return
+        self.poll_state = None
+        review = self.review
+        self.poll_job = self.jobs.submit(
+            f'Poll PR #{review.pr_number} for updates',
+            lambda job: self.poll(review, self.poll_state),
+            on_done=lambda result: self.handle_poll_result(review, *result),
+            interval=interval,
+        )
+
+    def stop_polling(self):
+        if self.poll_job is not None:
+            self.jobs.cancel(self.poll_job.id)
+            self.poll_job = None
+
+    def poll(self, review: offline_pr_review.Review, previous_state):
+        """
+        Fetch the state of `review`'s PR, and the files changed by any new
+        commits since `previous_state`.
+
+        This runs as a background job, so it only talks to GitHub.
+        """
+        state = review.fetch_state()
+        changed_paths = []
+        if previous_state is not None and state.head_sha != previous_state.head_sha:
+            changed_paths = review.fetch_paths_changed_between(previous_state.head_sha, state.head_sha)
+        return state, changed_paths
+
+    def handle_poll_result(self, review: offline_pr_review.Review, state, changed_paths: List[str]):
+        # `self.review` is replaced whenever the review file is reloaded, so
+        # it is told apart by its file rather than by identity.
+        if not self.review_active or self.review is None or review.file_path() != self.review.file_path():
+            # The review was switched or published while polling.
+            return
+        previous_state = self.poll_state
+        self.poll_state = state
+        if previous_state is not None:
+            self.report_pull_request_changes(previous_state, state, changed_paths)
+
+    def report_pull_request_changes(self, previous_state, state, changed_paths: List[str]):
+        if state.head_sha != previous_state.head_sha:
+            self.pull_request_head_sha = state.head_sha
+            self.changed_files = []
+            outdated = self.review.mark_outdated(changed_paths)
+            message = f"PR #{self.review.pr_number} has new commits (now at {state.head_sha[:12]})."
+            if outdated:
+                message += (
+                    f" {len(outdated)} of your comments and notes are on changed files and may need re-anchoring."
+                )
+            self.notify(message, 'WARN')
+            self.update_signs()
+        if state.comment_count > previous_state.comment_count:
+            new_comments = state.comment_count - previous_state.comment_count
+            self.notify(f"PR #{self.review.pr_number} has {new_comments} new review comments.")
+        for reviewer, review_state in state.review_states.items():
+            if previous_state.review_states.get(reviewer) != review_state:
+                self.notify(f"@{reviewer} reviewed PR #{self.review.pr_number}: {review_state.lower().replace('_', ' ')}.")
+
+    def report_stack_parent(self):
+        """
+        Point out when the active PR is stacked on another open PR.
+        """
+        try:
+            parent = self.review.stack_parent()
+        except requests.RequestException:
+            return
+        if parent is not None:
+            self.info(
+                f"PR #{self.review.pr_number} is stacked on PR #{parent}. "
+                "Use :ReviewStackPrev and :ReviewStackNext to move along the stack.\n"
+            )
+
+    @pynvim.command('ReviewHistory', sync=True)
+    def review_history(self):
+        """
+        Show the action log of the active review: every comment and note
+        added, edited, deleted, promoted or demoted, edit of the body and
+        attempt to publish, oldest first, with the comment's body at the time.
+        """
+        if not self.is_review_active():
+            self.nvim.err_write("No review is currently active.\n")
+            return
+        try:
+            history = self.review.history()
+        except ValueError as e:
+            self.nvim.err_write(f"{e}\n")
+            return
+        if not history:
+            self.nvim.out_write("Nothing was recorded for this review yet.\n")
+            return
+        self.nvim.command('botright new')
+        self.nvim.command('setlocal buftype=nofile bufhidden=wipe noswapfile nowrap')
+        self.nvim.current.buffer[:] = [entry.describe() for entry in history]
+        self.nvim.command('setlocal nomodifiable')
+        self.nvim.command('normal! G')
+
+    @pynvim.command('ReviewStackPrev')
+    def review_stack_prev(self):
+        """
+        Switch the review to the PR the active PR is stacked on.
+        """
+        if not self.is_review_active() or self.review.local_only:
+            self.nvim.err_write("No PR review is currently active.\n")
+            return
+        try:
+            parent = self.review.stack_parent()
+        except requests.RequestException as e:
+            self.nvim.err_write(f"Could not look up the PR stack: {e}\n")
+            return
+        if parent is None:
+            self.nvim.err_write(f"PR #{self.review.pr_number} is not stacked on another open PR.\n")
+            return
+        self.start_review([str(parent)])
+
+    @pynvim.command('ReviewStackNext')
+    def review_stack_next(self):
+        """
+        Switch the review to the PR stacked on the active PR. If there are
+        several, the first one is picked.
+        """
+        if not self.is_review_active() or self.review.local_only:
+            self.nvim.err_write("No PR review is currently active.\n")
+            return
+        try:
+            children = self.review.stack_children()
+        except requests.RequestException as e:
+            self.nvim.err_write(f"Could not look up the PR stack: {e}\n")
+            return
+        if not children:
+            self.nvim.err_write(f"No open PR is stacked on PR #{self.review.pr_number}.\n")
+            return
+        if len(children) > 1:
+            others = ', '.join(f'#{child}' for child in children[1:])
+            self.info(f"Several PRs are stacked on this one; also see {others}.\n")
+        self.start_review([str(children[0])])
+
+    @pynvim.command('ReviewChangesSinceLast')
+    def review_changes_since_last(self):
+        """
+        Scope the active review to the commits pushed since it was last published.
+        """
+        if not self.is_review_active():
+            self.nvim.err_write("No review is currently active.\n")
+            return
+        if self.refuse_if_read_only():
+            return
+        last_reviewed = self.review.last_reviewed_head_sha
+        if last_reviewed is None:
+            self.nvim.err_write("This review has not been published yet.\n")
+            return
+        self.review.commit_range = None
+        try:
+            head_sha = self.review.head_sha()
+        except requests.RequestException as e:
+            self.nvim.err_write(f"Could not fetch the PR head commit: {e}\n")
+            return
+        if head_sha == last_reviewed:
+            self.info("No changes since your last review.\n")
+            return
+        self.review.commit_range = f'{last_reviewed}..{head_sha}'
+        self.save()
+        self.activate_review(self.review)
+        self.info(f"Reviewing changes since {last_reviewed[:12]}.\n")
+
+    @pynvim.command('ReviewCheckout', nargs='+', complete='customlist,ReviewerCompleteCheckout', sync=True)
+    def review_checkout(self, args):
+        """
+        Fetch the head of a PR and check it out as the `pr/<number>` branch.
+
+        With a second argument, the branch is checked out in a new worktree
+        at that path instead, and the review must be started from there.
+        Otherwise, the review is started right away.
+        """
+        pr_number = args[0]
+        worktree_path = args[1] if len(args) > 1 else None
+        try:
+            offline_pr_review.checkout_pull_request(
+                pr_number, worktree_path, offline_pr_review.get_configured_remote() or 'origin'
+            )
+        except subprocess.CalledProcessError as e:
+            self.nvim.err_write(f"Could not check out PR {pr_number}: {e.stderr.decode('utf-8')}\n")
+            return
+
+        branch = offline_pr_review.pull_request_branch(pr_number)
+        if worktree_path:
+            self.info(f"Checked out {branch} in a new worktree at {worktree_path}.\n")
+            return
+        self.nvim.command('checktime')
+        self.info(f"Checked out {branch}.\n")
+        self.start_review([pr_number])
+
+    @pynvim.function('ReviewerCompleteCheckout', sync=True)
+    def complete_checkout(self, args):
+        """
+        Complete `:ReviewCheckout`: a PR number, then a worktree directory.
+        """
+        arg_lead, command_line = args[0], args[1]
+        if completion_position(command_line) == 0:
+            return [number for number in self.pull_request_numbers() if number.startswith(arg_lead)]
+        return self.nvim.call('getcompletion', arg_lead, 'dir')
+
+    @pynvim.command('RequestReviewers', nargs='+', complete='customlist,ReviewerCompleteCollaborator')
+    def request_reviewers(self, args):
+        """
+        Ask users (or `org/team`s) to review the active PR, e.g.
+        `:RequestReviewers alice my-org/backend`.
+        """
+        if not self.is_review_active() or self.review.local_only:
+            self.nvim.err_write("No PR review is currently active.\n")
+            return
+        try:
+            self.review.request_reviewers(args)
+        except requests.RequestException as e:
+            self.nvim.err_write(f"Could not request reviewers: {e}\n")
+            return
+        self.info(f"Requested a review of {self.review.title} from {', '.join(args)}.\n")
+
+    def collaborators(self) -> List[str]:
+        """
+        Return the collaborators of the active review's repository, cached for
+        `g:reviewer_collaborators_cache_ttl` seconds (a day by default).
+        """
+        if not self.is_review_active():
+            return []
+        try:
+            return self.review.fetch_collaborators(self.nvim.vars.get('reviewer_collaborators_cache_ttl', 24 * 3600))
+        except requests.RequestException:
+            return []
+
+    @pynvim.function('ReviewerCompleteCollaborator', sync=True)
+    def complete_collaborator(self, args):
+        return [login for login in self.collaborators() if login.startswith(args[0].lstrip('@'))]
+
+    @pynvim.function('ReviewerCompleteMention', sync=True)
+    def complete_mention(self, args):
+        """
+        `omnifunc` of comment buffers, completing `@mentions` of the
+        repository's collaborators.
+        """
+        find_start, base = args
+        if find_start:
+            line = self.nvim.current.line[:self.nvim.current.window.cursor[1]]
+            match = re.search(r'@[\w-]*$', line)
+            # -3 cancels the completion silently when not after an `@`.
+            return match.start() if match else -3
+        return [f'@{login}' for login in self.collaborators() if f'@{login}'.startswith(base)]
+
+    @pynvim.command('RequestReReview', nargs='*', complete='customlist,ReviewerCompleteCollaborator')
+    def request_re_review(self, args):
+        """
+        Ask the users who already reviewed the active PR to review it again,
+        e.g. after addressing their feedback. With arguments, only those
+        users are asked.
+        """
+        if not self.is_review_active() or self.review.local_only:
+            self.nvim.err_write("No PR review is currently active.\n")
+            return
+        try:
+            reviewers = args or self.review.fetch_previous_reviewers()
+            if not reviewers:
+                self.nvim.err_write(f"Nobody has reviewed {self.review.title} yet.\n")
+                return
+            self.review.request_reviewers(reviewers)
+        except requests.RequestException as e:
+            self.nvim.err_write(f"Could not request a new review: {e}\n")
+            return
+        self.info(f"Requested a new review of {self.review.title} from {', '.join(reviewers)}.\n")
+
+    @pynvim.command('ReviewStatus', sync=True)
+    def review_status(self):
+        """
+        Show where the reviews of the active PR stand: who approved it, who
+        requested changes that are still outstanding, and whose review is
+        awaited, along with the state of your own draft.
+        """
+        if not self.is_review_active() or self.review.local_only:
+            self.nvim.err_write("No PR review is currently active.\n")
+            return
+        try:
+            decisions = self.review.fetch_review_decisions()
+        except requests.RequestException as e:
+            self.nvim.err_write(f"Could not fetch the reviews of the PR: {e}\n")
+            return
+
+        def people(logins: List[str]) -> str:
+            return ', '.join(f'@{login}' for login in logins) or 'nobody'
+
+        states = [comment.publish_state for comment in self.review.publishable_comments()]
+        held = len(self.review.held_comments())
+        lines = [
+            f"{self.review.title}:",
+            f"  Approved by {len(decisions.approved)}: {people(decisions.approved)}",
+            f"  Changes requested by: {people(decisions.changes_requested)}",
+            f"  Commented: {people(decisions.commented)}",
+            f"  Awaiting review from: {people(decisions.requested)}",
+            f"  Your draft: {states.count('new')} new and {states.count('edited')} edited comments to publish"
+            + (f", {held} held" if held else ""),
+        ]
+        if self.changed_files or self.fetch_changed_files():
+            viewed_count = len([f for f in self.changed_files if self.review.is_file_viewed(f.path)])
+            lines.append(
+                f"  Files covered: {viewed_count} of {len(self.changed_files)} viewed"
+                f", {self.ignored_file_count} ignored"
+            )
+        self.nvim.out_write('\n'.join(lines) + '\n')
+
+    @pynvim.command('SuggestReviewers', bang=True)
+    def suggest_reviewers(self, bang):
+        """
+        List the people who most recently wrote the code the PR changes, as
+        candidate reviewers. `:SuggestReviewers!` also requests their review.
+        """
+        if not self.is_review_active() or self.review.remote_only or self.review.patch_file:
+            self.nvim.err_write("Suggesting reviewers requires a review of local commits.\n")
+            return
+        if bang and self.review.local_only:
+            self.nvim.err_write("Local reviews have no PR to request reviews on.\n")
+            return
+        review = self.review
+
+        def report(suggestions):
+            if not suggestions:
+                self.nvim.out_write(f"No reviewers to suggest for {review.title}.\n")
+                return
+            self.nvim.out_write('\n'.join(
+                f'{suggestion.login or suggestion.name}: {suggestion.lines} changed lines, '
+                f'last in {suggestion.last_commit_sha[:12]}'
+                for suggestion in suggestions
+            ) + '\n')
+            logins = [suggestion.login for suggestion in suggestions if suggestion.login]
+            if bang and logins:
+                self.request_reviewers(logins)
+
+        self.info("Looking for reviewers...\n")
+        self.jobs.submit(
+            f'Suggest reviewers for {review.title}',
+            lambda job: review.suggest_reviewers(),
+            on_done=report,
+            on_error=lambda e: self.nvim.err_write(f"Could not suggest reviewers: {e}\n"),
+        )
+
+    @pynvim.command('PrLabel', nargs='+', complete='customlist,ReviewerCompleteLabel')
+    def pr_label(self, args):
+        """
+        Add or remove a label on the active PR, e.g. `:PrLabel add needs-changes`.
+        """
+        if len(args) < 2 or args[0] not in ('add', 'remove'):
+            self.nvim.err_write("Usage: PrLabel add|remove <label>\n")
+            return
+        if not self.is_review_active() or self.review.local_only:
+            self.nvim.err_write("No PR review is currently active.\n")
+            return
+        # Labels may contain spaces.
+        label = ' '.join(args[1:])
+        try:
+            if args[0] == 'add':
+                self.review.add_label(label)
+            else:
+                self.review.remove_label(label)
+        except requests.RequestException as e:
+            self.nvim.err_write(f"Could not {args[0]} the label {label!r}: {e}\n")
+            return
+        action = 'Added' if args[0] == 'add' else 'Removed'
+        self.info(f"{action} the label {label!r} on {self.review.title}.\n")
+
+    @pynvim.function('ReviewerCompleteLabel', sync=True)
+    def complete_label(self, args):
+        """
+        Complete `:PrLabel`: the action, then the labels of the repository.
+        """
+        arg_lead, command_line = args[0], args[1]
+        if completion_position(command_line) == 0:
+            return [action for action in ('add', 'remove') if action.startswith(arg_lead)]
+        if not self.is_review_active() or self.review.local_only:
+            return []
+        repository = (self.review.owner, self.review.repo)
+        if repository not in self.repository_labels:
+            try:
+                self.repository_labels[repository] = self.review.fetch_labels()
+            except requests.RequestException:
+                return []
+        return [label for label in self.repository_labels[repository] if label.startswith(arg_lead)]
+
+    @pynvim.command('MergePR', nargs='?', complete='customlist,ReviewerCompleteMergeMethod', sync=True)
+    def merge_pr(self, args):
+        """
+        Merge the active PR, e.g. `:MergePR squash` (`merge` by default), after
+        checking that it is mergeable and asking for confirmation.
+        """
+        method = args[0] if args else 'merge'
+        if method not in ('merge', 'squash', 'rebase'):
+            self.nvim.err_write("Usage: MergePR [merge|squash|rebase]\n")
+            return
+        if not self.is_review_active() or self.review.local_only:
+            self.nvim.err_write("No PR review is currently active.\n")
+            return
+        try:
+            status = self.review.fetch_merge_status()
+        except requests.RequestException as e:
+            self.nvim.err_write(f"Could not check whether the PR can be merged: {e}\n")
+            return
+        if status.mergeable is False or status.mergeable_state in ('dirty', 'blocked'):
+            self.nvim.err_write(f"{self.review.title} cannot be merged ({status.mergeable_state}).\n")
+            return
+
+        warnings = []
+        if status.mergeable is None:
+            warnings.append("GitHub is still checking whether it merges cleanly")
+        if status.mergeable_state == 'behind':
+            warnings.append("the branch is behind its base")
+        if status.ci_state == 'failure':
+            warnings.append(f"checks are failing: {', '.join(status.failing_checks)}")
+        elif status.ci_state == 'pending':
+            warnings.append("checks are still running")
+        prompt = f"{method.capitalize()} {self.review.title} (at {status.head_sha[:12]})?"
+        if warnings:
+            prompt += f"\nWarning: {'; '.join(warnings)}."
+        if self.nvim.call('confirm', prompt, "&Yes\n&No", 2) != 1:
+            return
+
+        try:
+            message = self.review.merge(method, status.head_sha)
+        except requests.RequestException as e:
+            self.nvim.err_write(f"Could not merge the PR: {e}\n")
+            return
+        self.stop_polling()
+        self.info(f"{message}\n")
+
+    @pynvim.function('ReviewerCompleteMergeMethod', sync=True)
+    def complete_merge_method(self, args):
+        return [method for method in ('merge', 'squash', 'rebase') if method.startswith(args[0])]
+
+    @pynvim.command('ClosePR', bang=True, sync=True)
+    def close_pr(self, bang):
+        """
+        Close the active PR without merging it. Asks for confirmation, unless
+        run as `:ClosePR!`.
+        """
+        if not self.is_review_active() or self.review.local_only:
+            self.nvim.err_write("No PR review is currently active.\n")
+            return
+        if not bang and self.nvim.call('confirm', f"Close {self.review.title}?", "&Yes\n&No", 2) != 1:
+            return
+        try:
+            self.review.set_state('closed')
+        except requests.RequestException as e:
+            self.nvim.err_write(f"Could not close the PR: {e}\n")
+            return
+        self.stop_polling()
+        self.info(f"Closed {self.review.title}.\n")
+
+    @pynvim.command('ReopenPR')
+    def reopen_pr(self):
+        """
+        Reopen the active PR after it was closed.
+        """
+        if not self.is_review_active() or self.review.local_only:
+            self.nvim.err_write("No PR review is currently active.\n")
+            return
+        try:
+            self.review.set_state('open')
+        except requests.RequestException as e:
+            self.nvim.err_write(f"Could not reopen the PR: {e}\n")
+            return
+        self.start_polling()
+        self.info(f"Reopened {self.review.title}.\n")
+
+    def remote_comment_under_cursor(self) -> Optional[dict]:
+        """
+        Return the comment posted on GitHub at the cursor, reporting an error
+        if there is none.
+        """
+        if not self.is_review_active() or self.review.local_only:
+            self.nvim.err_write("No PR review is currently active.\n")
+            return None
+        location = self.current_buffer_location()
+        if location is None:
+            self.nvim.err_write("Current buffer is not a valid path in the git repository.\n")
+            return None
+        path, side = location
+        try:
+            remote_comment = self.review.fetch_remote_comment_at(path, self.nvim.current.window.cursor[0], side)
+        except requests.RequestException as e:
+            self.nvim.err_write(f"Could not fetch the comments of the PR: {e}\n")
+            return None
+        if remote_comment is None:
+            self.nvim.err_write("No published comment under the cursor.\n")
+        return remote_comment
+
+    def open_url(self, url: str):
+        if not webbrowser.open(url):
+            self.nvim.err_write(f"Could not open {url} in a browser.\n")
+
+    @pynvim.command('OpenPR')
+    def open_pr(self):
+        """
+        Open the active PR on GitHub in the browser.
+        """
+        if not self.is_review_active() or self.review.local_only:
+            self.nvim.err_write("No PR review is currently active.\n")
+            return
+        self.open_url(self.review.web_url)
+
+    @pynvim.command('OpenComment', sync=True)
+    def open_comment(self):
+        """
+        Open the published comment under the cursor on GitHub in the browser.
+        """
+        remote_comment = self.remote_comment_under_cursor()
+        if remote_comment is not None:
+            self.open_url(remote_comment["html_url"])
+
+    @pynvim.command('YankReviewUrl', nargs='*', range='', complete='customlist,ReviewerCompleteUrlKind', sync=True)
+    def yank_review_url(self, args, range):
+        """
+        Yank a GitHub URL into a register (the unnamed one by default, or the
+        one given as second argument): `:YankReviewUrl` yanks the URL of the
+        PR, `:YankReviewUrl comment` the thread of the published comment under
+        the cursor, and `:[range]YankReviewUrl line` a permalink to the
+        selected lines at the PR head (or base, for the left side of a diff).
+        """
+        kind = args[0] if args else 'pr'
+        register = args[1] if len(args) > 1 else '"'
+        if kind not in ('pr', 'comment', 'line') or len(args) > 2 or len(register) != 1:
+            self.nvim.err_write("Usage: [range]YankReviewUrl [pr|comment|line] [register]\n")
+            return
+        if not self.is_review_active() or self.review.local_only:
+            self.nvim.err_write("No PR review is currently active.\n")
+            return
+        if kind == 'pr':
+            url = self.review.web_url
+        elif kind == 'comment':
+            remote_comment = self.remote_comment_under_cursor()
+            if remote_comment is None:
+                return
+            url = remote_comment["html_url"]
+        else:
+            location = self.current_buffer_location()
+            if location is None:
+                self.nvim.err_write("Current buffer is not a valid path in the git repository.\n")
+                return
+            path, side = location
+            try:
+                commit_sha = self.review.base_sha() if side == 'LEFT' else self.review.head_sha()
+            except requests.RequestException as e:
+                self.nvim.err_write(f"Could not fetch the PR commits: {e}\n")
+                return
+            url = self.review.permalink(commit_sha, path, range[0], range[1])
+        self.nvim.call('setreg', register, url)
+        self.info(f"Yanked {url}\n")
+
+    @pynvim.function('ReviewerCompleteUrlKind', sync=True)
+    def complete_url_kind(self, args):
+        return [kind for kind in ('pr', 'comment', 'line') if kind.startswith(args[0])]
+
+    @pynvim.command('PublishReview', nargs='*', bang=True, complete='customlist,ReviewerCompletePublishEvent')
+    def publish_review(self, args, bang):
+        """
+        Publish the in-progress review to GitHub, optionally with a verdict:
+        `approve`, `request-changes` or `comment`. Without one, the review is
+        left pending on GitHub.
+
+        Held comments are left out unless `--include-held` is given.
+
+        `:PublishReview!` doesn't ask for confirmation: comments outside the
+        diff are moved to the review body right away, and comments on files
+        owned by others (per CODEOWNERS) are published without a warning.
+        """
+        include_held = '--include-held' in args
+        args = [arg for arg in args if arg != '--include-held']
+        event = args[0] if args else None
+        if len(args) > 1:
+            self.nvim.err_write("Usage: :PublishReview [approve|request-changes|comment] [--include-held]\n")
+        elif event is not None and event not in offline_pr_review.REVIEW_EVENTS:
+            self.nvim.err_write(f"Invalid review event {event!r}; use one of {', '.join(offline_pr_review.REVIEW_EVENTS)}.\n")
+        elif self.review_active and self.review.local_only:
+            self.nvim.err_write("Local reviews cannot be published; use :ExportReview instead.\n")
+        elif self.refuse_if_read_only():
+            return
+        elif self.review_active and self.nvim.vars.get('reviewer_enforce_checklist') and self.review.unchecked_required_items():
+            unchecked = ', '.join(item.text for item in self.review.unchecked_required_items())
+            self.nvim.err_write(f"Cannot publish until the required checklist items are checked: {unchecked}\n")
+        elif self.review_active and self.invalid_autolink() is not None:
+            self.nvim.err_write(f"Invalid pattern in g:reviewer_autolinks: {self.invalid_autolink()}\n")
+        elif self.review_active and self.refuse_if_not_compliant(include_held):
+            return
+        elif self.review_active and self.review.patch_file and offline_pr_review.targets_sourcehut():
+            self.send_patch_replies(include_held)
+        elif self.review_active:
+            foreign = [] if bang or not self.nvim.vars.get('reviewer_codeowners_warning', 1) else self.files_owned_by_others()
+            if foreign and self.nvim.call(
+                'confirm',
+                f"You don't own {', '.join(foreign[:3])}{' and others' if len(foreign) > 3 else ''} per CODEOWNERS. Publish anyway?",
+                "&Yes\n&No",
+                2,
+            ) != 1:
+                return
+            review = self.review
+            self.jobs.submit(
+                f'Check the comments of {review.title} against the diff',
+                lambda job: self.comments_outside_diff(review, include_held),
+                on_done=lambda outside: self.start_publishing(review, outside, not bang, event, include_held),
+                on_error=lambda e: self.nvim.err_write(f"Could not publish the review: {e}\n"),
+            )
+        else:
+            self.nvim.err_write("Cannot publish since no review is currently active.\n")
+
+    def compliance_rules(self) -> List[str]:
+        """
+        Return the requirements the `compliance` setting of `vim-reviewer.json`
+        enforces before publishing: all of `offline_pr_review.COMPLIANCE_RULES`
+        when it is `true`, or those set in it when it is an object, e.g.
+        `{"checklist": true, "viewed": true}`.
+        """
+        setting = self.shared_setting('compliance', False)
+        if isinstance(setting, dict):
+            return [rule for rule in offline_pr_review.COMPLIANCE_RULES if setting.get(rule)]
+        return list(offline_pr_review.COMPLIANCE_RULES) if setting else []
+
+    def refuse_if_not_compliant(self, include_held: bool) -> bool:
+        """
+        Refuse to publish, with a report of what is missing, if the review
+        doesn't meet the requirements of compliance mode. Returns whether it
+        refused.
+        """
+        rules = self.compliance_rules()
+        if not rules:
+            return False
+        changed_paths = []
+        if 'viewed' in rules:
+            # Ignored files are left out of the review UI, not out of what
+            # must be viewed.
+            try:
+                changed_paths = [changed_file.path for changed_file in self.review.fetch_changed_files()]
+            except requests.RequestException as e:
+                self.nvim.err_write(f"Could not fetch the changed files of the PR: {e}\n")
+                return True
+        problems = self.review.compliance_problems(rules, changed_paths, include_held)
+        if not problems:
+            return False
+        self.nvim.err_write(
+            "Cannot publish until the review meets the compliance requirements:\n"
+            + ''.join(f"- {problem}\n" for problem in problems)
+        )
+        return True
+
+    def invalid_autolink(self) -> Optional[str]:
+        """
+        Return the first pattern of `g:reviewer_autolinks` that isn't a valid
+        regular expression, if any.
+        """
+        for pattern in self.nvim.vars.get('reviewer_autolinks', {}):
+            try:
+                re.compile(pattern)
+            except re.error:
+                return pattern
+        return None
+
+    @pynvim.command('ReviewPendingDiff', sync=True)
+    def review_pending_diff(self):
+        """
+        Show what publishing the review again would do: which comments are
+        new, which were edited since they were published (with a diff of
+        their body), and which are published and unchanged, so skipped.
+        """
+        if not self.is_review_active():
+            self.nvim.err_write("No review is currently active.\n")
+            return
+        if self.review.local_only:
+            self.nvim.err_write("Local reviews cannot be published; use :ExportReview instead.\n")
+            return
+        self.nvim.command('new')
+        self.nvim.command('setlocal buftype=nofile bufhidden=wipe noswapfile filetype=markdown')
+        self.nvim.current.buffer[:] = self.review.pending_diff().rstrip('\n').split('\n')
+
+    @pynvim.function('ReviewerCompletePublishEvent', sync=True)
+    def complete_publish_event(self, args):
+        options = list(offline_pr_review.REVIEW_EVENTS) + ['--include-held']
+        return [option for option in options if option.startswith(args[0])]
+
+    def comments_outside_diff(
+        self, review: offline_pr_review.Review, include_held: bool
+    ) -> List[offline_pr_review.Comment]:
+        """
+        Return the comments publishing `review` would post on lines outside
+        the PR diff, which would make GitHub reject the whole review.
+
+        This runs as a background job, since the diff may have to be fetched.
+        """
+        publishable = {comment.id for comment in review.publishable_comments(include_held)}
+        try:
+            return [comment for comment in review.find_comments_outside_diff() if comment.id in publishable]
+        except requests.RequestException:
+            # Let GitHub be the judge.
+            return []
+
+    def start_publishing(
+        self,
+        review: offline_pr_review.Review,
+        outside: List[offline_pr_review.Comment],
+        ask: bool,
+        event: Optional[str],
+        include_held: bool,
+    ):
+        """
+        Publish `review` in the background, once the comments `outside` the
+        diff are dealt with (see `comments_to_detach`), unless it was closed
+        or switched meanwhile.
+        """
+        if not self.review_active or self.review.file_path() != review.file_path():
+            self.nvim.err_write("The review was closed before it could be published.\n")
+            return
+        # The review may have been edited or reloaded meanwhile.
+        outside_ids = {comment.id for comment in outside}
+        outside = [comment for comment in self.review.publishable_comments(include_held) if comment.id in outside_ids]
+        detached = self.comments_to_detach(outside, ask)
+        if detached is None:
+            return
+        held = [] if include_held else self.review.held_comments()
+        self.review_active = False
+        self.stop_polling()
+        self.clear_buffer_statuses()
+        review = self.review
+        autolinks = self.nvim.vars.get('reviewer_autolinks', {})
+        self.info(
+            f"Publishing the review of {review.title}"
+            + (f", leaving out {len(held)} held comments" if held else "") + "...\n"
+        )
+        self.jobs.submit(
+            f'Publish the review of {review.title}',
+            lambda job: self.publish_in_background(review, detached, event, autolinks, include_held),
+            on_done=lambda result: self.handle_publish_result(review, *result, include_held),
+            on_error=lambda e: self.nvim.err_write(f"Could not publish the review: {e}\n"),
+        )
+
+    def comments_to_detach(self, outside: List[offline_pr_review.Comment], ask: bool = True) -> Optional[List[str]]:
+        """
+        Ask whether to publish the comments `outside` the PR diff, which
+        would make GitHub reject the whole review, as general comments in the
+        review body instead. Without `ask`, they are published that way.
+
+        Returns the IDs of the comments to publish that way, or None if
+        publishing was cancelled. The comments keep their anchoring locally.
+        """
+        if not outside:
+            return []
+        if not ask:
+            return [comment.id for comment in outside]
+        numbers = ', '.join(f'#{self.review.comment_number(comment)}' for comment in outside)
+        choice = self.nvim.call(
+            'confirm',
+            f"Comments {numbers} are on lines outside the PR diff, which GitHub rejects.",
+            "&Move them to the review body\n&Publish anyway\n&Cancel",
+            1,
+        )
+        if choice == 1:
+            return [comment.id for comment in outside]
+        if choice == 2:
+            return []
+        return None
+
+    def publish_in_background(
+        self,
+        review: offline_pr_review.Review,
+        detached: List[str],
+        event: Optional[str],
+        autolinks: Dict[str, str],
+        include_held: bool,
+    ):
+        """
+        Publish `review` with the given `event` and `autolinks` (and its held
+        comments with `include_held`), and look up the PR head it was
+        published against. The comments with the `detached` IDs are published
+        as general comments in the review body.
+
+        This runs as a background job, so it only talks to GitHub.
+        """
+        general_comments = {
+            comment.id: review.general_comment(comment)
+            for comment in review.comments if comment.id in detached
+        }
+        results = review.publish(offline_pr_review.get_api_token(), general_comments, event, autolinks, include_held)
+        try:
+            head_sha = review.head_sha() if all(response and response.ok for _, response in results) else None
+        except requests.RequestException:
+            head_sha = None
+        return results, head_sha
+
+    def handle_publish_result(
+        self, review: offline_pr_review.Review, results, head_sha: Optional[str], include_held: bool = False
+    ):
+        """
+        Report how publishing went. When the review was split into several
+        parts, say so, and which parts made it if one was rejected.
+        """
+        responses = [response for _, response in results if response is not None]
+        if len(results) > 1:
+            sizes = ', '.join(f"{len(payload['comments'])}" for payload, _ in results)
+            self.info(
+                f"The review was too big for a single submission, so it was split into "
+                f"{len(results)} parts with {sizes} comments.\n"
+            )
+        result = responses[-1]
+        self.debug(f'{result}: {result.reason}\n')
+        if not result.ok:
+            self.nvim.err_write(f'GitHub rejected the review ({result.status_code} {result.reason}): {result.text}\n')
+            if len(responses) > 1:
+                self.nvim.err_write(
+                    f"Parts 1 to {len(responses) - 1} of {len(results)} were published; "
+                    "publishing again would post their comments twice.\n"
+                )
+        try:
+            review.record_publication(results, head_sha)
+            if result.ok:
+                review.mark_published(include_held)
+                if head_sha is not None:
+                    review.mark_reviewed(head_sha)
+        except ValueError as e:
+            self.nvim.err_write(f"Could not save the review: {e}\n")
+        if result.ok:
+            self.info(f"Published the review of {review.title}.\n")
+        self.update_signs()
+
+    @pynvim.command('ReviewerJobs')
+    def reviewer_jobs(self):
+        """
+        List the background jobs (publishing, polling, ...) and their status.
+        """
+        jobs = self.jobs.list()
+        if not jobs:
+            self.nvim.out_write("No background jobs.\n")
+            return
+        self.nvim.out_write('\n'.join(job.describe() for job in jobs) + '\n')
+
+    @pynvim.command('ReviewerJobCancel', nargs=1, complete='customlist,ReviewerCompleteJob')
+    def reviewer_job_cancel(self, args):
+        """
+        Cancel the background job with the given ID (see `:ReviewerJobs`).
+        """
+        try:
+            job_id = int(args[0])
+        except ValueError:
+            job_id = None
+        if job_id is None or not self.jobs.cancel(job_id):
+            self.nvim.err_write(f"No unfinished job with ID {args[0]}.\n")
+            return
+        if self.poll_job is not None and self.poll_job.id == job_id:
+            self.poll_job = None
+        self.info(f"Cancelled job {job_id}.\n")
+
+    @pynvim.function('ReviewerCompleteJob', sync=True)
+    def complete_job(self, args):
+        return [
+            str(job.id) for job in self.jobs.list()
+            if job.status not in ('done', 'failed', 'cancelled') and str(job.id).startswith(args[0])
+        ]
+
+    @pynvim.command('ReviewerRateLimit', sync=True)
+    def reviewer_rate_limit(self):
+        """
+        Show how much of GitHub's API rate limit is left for REST (`core`),
+        GraphQL and search requests, and when each resets, to tell whether a
+        big fetch or publish is safe to start now.
+        """
+        try:
+            if self.nvim.call('FugitiveGitDir') and offline_pr_review.targets_sourcehut():
+                self.nvim.err_write("Reviews of this repository go through its mailing list, which has no rate limit.\n")
+                return
+            limits = offline_pr_review.fetch_rate_limits()
+        except ValueError as e:
+            self.nvim.err_write(f"{e}\n")
+            return
+        except requests.HTTPError as e:
+            if e.response is not None and e.response.status_code == 404:
+                self.nvim.err_write("This GitHub instance doesn't rate limit its API.\n")
+            else:
+                self.nvim.err_write(f"Could not fetch the rate limits: {e}\n")
+            return
+        except requests.RequestException as e:
+            self.nvim.err_write(f"Could not fetch the rate limits: {e}\n")
+            return
+        lines = []
+        for resource in ('core', 'graphql', 'search'):
+            limit = limits.get(resource)
+            if limit is None:
+                continue
+            minutes = max(0, round((limit['reset'] - time.time()) / 60))
+            reset = time.strftime('%H:%M', time.localtime(limit['reset']))
+            low = ' (low)' if limit['remaining'] < limit['limit'] / 10 else ''
+            lines.append(
+                f"{resource}: {limit['remaining']} of {limit['limit']} left{low}, resets at {reset} (in {minutes} min)"
+            )
+        self.nvim.out_write('\n'.join(lines) + '\n')
+
+    def configure_review_target(self):
+        """
+        Configure the repository reviews target from the git remote chosen
+        before. With several remotes (e.g. `origin` and `upstream`) and no
+        choice yet, remember the default remote (see
+        `offline_pr_review.default_remote`) and ask once which one to use.
+
+        A repository set in the committed `vim-reviewer.json` is used as is,
+        unless it also names the remote.
+        """
+        remotes = offline_pr_review.list_remotes()
+        self.load_repository_config()
+        shared = offline_pr_review.shared_settings()
+        chosen = offline_pr_review.get_configured_remote()
+        if 'repository' in shared and 'remote' not in shared:
+            return
+        ask = chosen not in remotes and len(remotes) > 1
+        if chosen not in remotes:
+            chosen = offline_pr_review.default_remote(remotes)
+        if chosen is None:
+            return
+        try:
+            offline_pr_review.configure_remote(chosen, probe=False)
+        except ValueError as e:
+            self.nvim.err_write(f"{e}\n")
+        if ask:
+            self.nvim.exec_lua(SELECT_REMOTE_LUA, remotes)
+
+    @pynvim.command('ReviewerSelectRemote', nargs='?', complete='customlist,ReviewerCompleteRemote', sync=True)
+    def reviewer_select_remote(self, args):
+        """
+        Choose the git remote whose repository reviews target, e.g.
+        `:ReviewerSelectRemote upstream`. Without an argument, pick it from a
+        list.
+        """
+        remotes = offline_pr_review.list_remotes()
+        if not remotes:
+            self.nvim.err_write("This repository has no git remotes.\n")
+        elif args:
+            self.set_remote([args[0]])
+        else:
+            self.nvim.exec_lua(SELECT_REMOTE_LUA, remotes)
+
+    @pynvim.function('ReviewerSetRemote', sync=True)
+    def set_remote(self, args):
+        remote = args[0]
+        if remote not in offline_pr_review.list_remotes():
+            self.nvim.err_write(f"No git remote named {remote!r}.\n")
+            return
+        try:
+            repository = offline_pr_review.configure_remote(remote)
+        except ValueError as e:
+            self.nvim.err_write(f"{e}\n")
+            return
+        self.info(f"Reviews now target {repository} (remote {remote}).\n")
+
+    @pynvim.function('ReviewerCompleteRemote', sync=True)
+    def complete_remote(self, args):
+        return [remote for remote in offline_pr_review.list_remotes() if remote.startswith(args[0])]
+
+    @pynvim.command('ReviewerEncryption', nargs=1, complete='customlist,ReviewerCompleteEncryption', sync=True)
+    def reviewer_encryption(self, args):
+        """
+        Turn the encryption of this repository's review files `on` or `off`,
+        rewriting the existing ones. Turning it on without a key offers to
+        generate one and store it in the system keyring.
+        """
+        if args[0] not in ('on', 'off'):
+            self.nvim.err_write("Usage: :ReviewerEncryption on|off\n")
+            return
+        if not self.nvim.call('FugitiveGitDir'):
+            self.nvim.err_write("Not in a git repository.\n")
+            return
+        enabled = args[0] == 'on'
+        if enabled:
+            try:
+                offline_pr_review.encryption_key()
+            except ValueError as e:
+                if os.getenv('GH_REVIEW_ENCRYPTION_KEY_COMMAND') or self.nvim.call(
+                    'confirm', "There is no review encryption key yet. Generate one in the system keyring?", "&Yes\n&No", 2
+                ) != 1:
+                    self.nvim.err_write(f"{e}\n")
+                    return
+                try:
+                    offline_pr_review.store_new_encryption_key()
+                except ValueError as e:
+                    self.nvim.err_write(f"{e}\n")
+                    return
+        try:
+            count = offline_pr_review.set_encryption(enabled)
+        except ValueError as e:
+            self.nvim.err_write(f"{e}\n")
+            return
+        if self.review_active and not self.review.remote_only:
+            # Its file was just rewritten from what was saved, which the
+            # review in memory matches; pick up the new modification time.
+            try:
+                self.review = self.review.reload()
+            except ValueError as e:
+                self.nvim.err_write(f"Could not reload the review: {e}\n")
+        state = 'encrypted' if enabled else 'stored in plain text'
+        self.info(f"Review files of this repository are now {state} ({count} rewritten).\n")
+
+    @pynvim.function('ReviewerCompleteEncryption', sync=True)
+    def complete_encryption(self, args):
+        return [option for option in ('on', 'off') if option.startswith(args[0])]
+
+    @pynvim.command('ReviewerInit', nargs='?', complete='customlist,ReviewerCompleteInit', sync=True)
+    def reviewer_init(self, args):
+        """
+        Set up reviews in the current repository step by step: choose the git
+        remote whose repository they target, check which forge its host runs
+        (or say so when it can't be told), and choose where the API token
+        comes from. That choice is saved in the user's configuration, and the
+        token itself is never written to any configuration.
+
+        `:ReviewerInit share` instead writes the repository, host and remote
+        chosen to `vim-reviewer.json`, to be committed for the whole team.
+        """
+        if not self.nvim.call('FugitiveGitDir'):
+            self.nvim.err_write("Not in a git repository.\n")
+            return
+        if args and args[0] != 'share':
+            self.nvim.err_write("Usage: ReviewerInit [share]\n")
+            return
+        if args:
+            try:
+                path = offline_pr_review.write_shared_configuration()
+            except ValueError as e:
+                self.nvim.err_write(f"{e}\n")
+                return
+            self.info(f"Wrote {path}; commit it to share the setup. It holds no token.\n")
+            return
+        remotes = offline_pr_review.list_remotes()
+        if not remotes:
+            self.nvim.err_write("This repository has no git remotes.\n")
+            return
+        remote = remotes[0] if len(remotes) == 1 else self.choose(
+            'Remote of the repository to review PRs of:', [(remote, remote) for remote in remotes]
+        )
+        if remote is None:
+            return
+        location = offline_pr_review.remote_repository(remote)
+        if location is None:
+            self.nvim.err_write(f"Can't read the URL of remote {remote!r}.\n")
+            return
+        host, _ = location
+        if self.verbosity() != 'quiet':
+            self.nvim.command(f'redraw | echo "Looking up what {host} runs..."')
+        kind = offline_pr_review.detect_host_kind(host)
+        if kind not in ('github', 'sourcehut'):
+            detected = f"{host} runs {kind}, which isn't supported" if kind else f"Can't tell what {host} runs"
+            kind = self.choose(f'{detected}. Review its PRs as:', [
+                ('github', 'GitHub or GitHub Enterprise Server'),
+                ('sourcehut', 'sourcehut, replying on the mailing list'),
+            ])
+            if kind is None:
+                return
+        try:
+            repository = offline_pr_review.configure_remote(remote, kind)
+        except ValueError as e:
+            self.nvim.err_write(f"{e}\n")
+            return
+        if kind == 'sourcehut':
+            self.info(
+                f"Reviews now target {repository} on sourcehut; they are sent as replies with git send-email.\n"
+            )
+            return
+
+        source = self.choose('Where the GitHub API token comes from:', [
+            ('env', 'The GH_REVIEW_API_TOKEN environment variable'),
+            ('keyring', 'The system keyring (the token is asked for now)'),
+            ('command', 'The output of a shell command, e.g. gh auth token'),
+        ])
+        if source is None:
+            return
+        try:
+            if source == 'env':
+                offline_pr_review.configure_token_source(None)
+            elif source == 'keyring':
+                token = self.nvim.call('inputsecret', 'API token: ').strip()
+                if token:
+                    offline_pr_review.store_keyring_token(token)
+                offline_pr_review.configure_token_source('keyring')
+            else:
+                command = self.nvim.call('input', 'Command printing the API token: ', 'gh auth token').strip()
+                if not command:
+                    return
+                offline_pr_review.configure_token_source('command', command)
+            self.nvim.command('redraw')
+            token = offline_pr_review.get_api_token()
+            if not token:
+                self.nvim.err_write(
+                    f"Reviews now target {repository}, but there is no API token yet. Create one with access to "
+                    "the repository and export it as GH_REVIEW_API_TOKEN, or run :ReviewerInit again.\n"
+                )
+                return
+            login = offline_pr_review.get_authenticated_user(token)
+        except ValueError as e:
+            self.nvim.err_write(f"{e}\n")
+            return
+        except requests.HTTPError as e:
+            if e.response.status_code == 401:
+                self.nvim.err_write("GitHub rejected the API token; it may have expired or been revoked.\n")
+            else:
+                self.nvim.err_write(f"Could not check the API token: {e}\n")
+            return
+        except requests.RequestException as e:
+            self.nvim.err_write(f"Reviews now target {repository}, but the API token could not be checked: {e}\n")
+            return
+        self.info(f"Reviews now target {repository}, as @{login}.\n")
+
+    @pynvim.function('ReviewerCompleteInit', sync=True)
+    def complete_init(self, args):
+        return ['share'] if 'share'.startswith(args[0]) else []
+
+    def choose(self, prompt: str, options: List[Tuple[str, str]]) -> Optional[str]:
+        """
+        Ask to pick one of `options`, pairs of a value and its description,
+        from a numbered list. Returns the chosen value, or None if cancelled.
+        """
+        lines = [prompt] + [f'{index}. {label}' for index, (_, label) in enumerate(options, start=1)]
+        choice = self.nvim.call('inputlist', lines)
+        self.nvim.command('redraw')
+        if not 1 <= choice <= len(options):
+            return None
+        return options[choice - 1][0]
+
+    @pynvim.command('ReviewerInstallHooks', nargs='?', bang=True, complete='customlist,ReviewerCompleteHookMode', sync=True)
+    def reviewer_install_hooks(self, args, bang):
+        """
+        Install a pre-push hook that reminds of unfinished local reviews (see
+        `:StartLocalReview`) of the commits being pushed, or with `block`,
+        stops the push. `remove` uninstalls it. Without an argument, the mode
+        is `g:reviewer_pre_push_hook`, `remind` by default. Add a bang to
+        replace another pre-push hook.
+        """
+        mode = args[0] if args else self.nvim.vars.get('reviewer_pre_push_hook', 'remind')
+        if mode not in ('remind', 'block', 'remove'):
+            self.nvim.err_write("Usage: :ReviewerInstallHooks[!] [remind|block|remove]\n")
+            return
+        if not self.nvim.call('FugitiveGitDir'):
+            self.nvim.err_write("Not in a git repository.\n")
+            return
+        if mode == 'remove':
+            if offline_pr_review.remove_pre_push_hook():
+                self.info("Removed the pre-push hook.\n")
+            else:
+                self.nvim.err_write("There is no vim-reviewer pre-push hook to remove.\n")
+            return
+        try:
+            path = offline_pr_review.install_pre_push_hook(mode == 'block', overwrite=bang)
+        except ValueError as e:
+            self.nvim.err_write(f"{e}\n")
+            return
+        effect = 'stops' if mode == 'block' else 'warns about'
+        self.info(f"Installed {path}; it {effect} pushes with unfinished local reviews.\n")
+
+    @pynvim.function('ReviewerCompleteHookMode', sync=True)
+    def complete_hook_mode(self, args):
+        return [mode for mode in ('remind', 'block', 'remove') if mode.startswith(args[0])]
+
+    @pynvim.function('IsReviewActive', sync=True)
+    def is_review_active(self):
+        """
+        Whether a review is active. Since every command checks this first, it
+        is also where the active review is reloaded if its file was changed
+        since it was last read or written, so commands never work on stale
+        state.
+
+        While a comment is being edited, the reload is left to `save()`, which
+        asks how to combine both versions.
+        """
+        if (
+            self.review_active
+            and not self.review.read_only
+            and self.in_progress_comment is None
+            and self.review.changed_on_disk()
+        ):
+            try:
+                self.review = self.review.reload()
+            except ValueError as e:
+                # Keep working on the version in memory.
+                self.nvim.err_write(f"Could not reload the review: {e}\n")
+            else:
+                self.update_signs()
+        return self.review_active
+
+    def repository_absolute_path(self) -> str:
+        return self.nvim.call('FugitiveWorkTree')
+
+    def worktree_filename(self, path: str) -> str:
+        """
+        Return the name of the working tree file at repository path `path`.
+        """
+        return offline_pr_review.native_path(self.repository_absolute_path(), path)
+
+    def buffer_resolvers(self) -> List[Callable[[pynvim.api.Buffer], Optional[BufferLocation]]]:
+        """
+        Return the chain of resolvers used to map a buffer to a location in
+        the diff, in the order they are tried.
+        """
+        return [
+            self.resolve_user_buffer,
+            self.resolve_reviewer_buffer,
+            self.resolve_fugitive_buffer,
+            self.resolve_diffview_buffer,
+            self.resolve_gitsigns_buffer,
+            self.resolve_git_buffer,
+            self.resolve_working_tree_buffer,
+        ]
+
+    def buffer_location(self, buffer: pynvim.api.Buffer) -> Optional[BufferLocation]:
+        """
+        Return the path in the git repository and the diff side shown by
+        `buffer`, or None if it is not a file in the repository.
+
+        The first resolver in the chain that recognizes the buffer wins.
+        Files of submodules are separate repositories, which the review can't
+        comment on, so they have no location.
+        """
+        if self.buffer_submodule(buffer) is not None:
+            return None
+        for resolver in self.buffer_resolvers():
+            location = resolver(buffer)
+            if location is not None:
+                path, side = location
+                return offline_pr_review.normalize_path(path), side
+        return None
+
+    def buffer_submodule(self, buffer: pynvim.api.Buffer) -> Optional[str]:
+        """
+        Return the path of the submodule of the reviewed repository that the
+        file in `buffer` belongs to, or None if it isn't in a submodule.
+        """
+        if not self.review_active or self.review.remote_only or not os.path.isabs(buffer.name):
+            return None
+        if self.worktree_submodules is None:
+            root = offline_pr_review.get_worktree_root()
+            self.worktree_submodules = (root, offline_pr_review.list_submodules(root) if root else [])
+        root, submodules = self.worktree_submodules
+        if not submodules:
+            return None
+        path = offline_pr_review.repository_relative_path(buffer.name, root)
+        return offline_pr_review.submodule_containing(path, submodules) if path is not None else None
+
+    def resolve_user_buffer(self, buffer: pynvim.api.Buffer) -> Optional[BufferLocation]:
+        """
+        Defer to the user's `vim.g.reviewer_buffer_resolver` for exotic setups.
+        """
+        location = self.nvim.exec_lua(USER_RESOLVER_LUA, buffer.handle, buffer.name)
+        if location is None:
+            return None
+        return location[0], location[1]
+
+    def resolve_reviewer_buffer(self, buffer: pynvim.api.Buffer) -> Optional[BufferLocation]:
+        """
+        Buffers fetched from GitHub carry their side in their name.
+        """
+        reviewer_match = REVIEWER_BUFFER_PATTERN.match(buffer.name)
+        if reviewer_match:
+            return reviewer_match.group('path'), reviewer_match.group('side')
+        return None
+
+    def resolve_fugitive_buffer(self, buffer: pynvim.api.Buffer) -> Optional[BufferLocation]:
+        """
+        Fugitive buffers showing the index (stage `0`), the checked out commit
+        or the PR's head commit are the PR's version of the file, i.e. the
+        RIGHT side. Any other revision is treated as the base, i.e. the LEFT
+        side. This is the only place files deleted by the PR can be commented
+        on, since they no longer exist in the working tree.
+        """
+        fugitive_match = FUGITIVE_BUFFER_PATTERN.match(buffer.name)
+        if fugitive_match is None:
+            return None
+        revision = fugitive_match.group('revision')
+        if revision == '0':
+            return fugitive_match.group('path'), 'RIGHT'
+        if revision in ('1', '2', '3'):
+            return fugitive_match.group('path'), 'LEFT'
+        git_dir = fugitive_match.group('git_dir')
+        commit = offline_pr_review.resolve_revision(revision, git_dir)
+        if commit is not None and commit in (offline_pr_review.resolve_revision('HEAD', git_dir), self.pull_request_head_sha):
+            return fugitive_match.group('path'), 'RIGHT'
+        return fugitive_match.group('path'), 'LEFT'
+
+    def resolve_diffview_buffer(self, buffer: pynvim.api.Buffer) -> Optional[BufferLocation]:
+        """
+        diffview.nvim buffers are mapped according to the panel they are shown
+        in, so comments can be left from either side of a diffview.
+        """
+        diffview_match = DIFFVIEW_BUFFER_PATTERN.match(buffer.name)
+        if diffview_match:
+            return diffview_match.group('path'), self.diffview_side(buffer, diffview_match.group('revision'))
+        return None
+
+    def resolve_gitsigns_buffer(self, buffer: pynvim.api.Buffer) -> Optional[BufferLocation]:
+        """
+        gitsigns.nvim diff buffers show the index (RIGHT) or an older revision
+        of the file (LEFT).
+        """
+        gitsigns_match = GITSIGNS_BUFFER_PATTERN.match(buffer.name)
+        if gitsigns_match:
+            side = 'RIGHT' if gitsigns_match.group('revision').startswith(':') else 'LEFT'
+            return gitsigns_match.group('path'), side
+        return None
+
+    def resolve_git_buffer(self, buffer: pynvim.api.Buffer) -> Optional[BufferLocation]:
+        """
+        Generic `git://` object buffers show an older revision of the file.
+        """
+        git_match = GIT_BUFFER_PATTERN.match(buffer.name)
+        if git_match:
+            return git_match.group('path'), 'LEFT'
+        return None
+
+    def resolve_working_tree_buffer(self, buffer: pynvim.api.Buffer) -> Optional[BufferLocation]:
+        """
+        Working tree files are on the RIGHT side of the diff.
+
+        This also covers mini.diff, whose overlay is drawn on top of the
+        working tree buffer rather than in a buffer of its own.
+        """
+        if not os.path.isabs(buffer.name):
+            return None
+        path = offline_pr_review.repository_relative_path(buffer.name, self.repository_absolute_path())
+        if path is None:
+            return None
+        return path, 'RIGHT'
+
+    def diffview_side(self, buffer: pynvim.api.Buffer, revision: str) -> offline_pr_review.Side:
+        """
+        Return the diff side of a diffview.nvim revision buffer.
+
+        If the buffer is part of the current diffview, its panel decides the
+        side. Otherwise, fall back to treating commits as the base (LEFT) and
+        index buffers as the PR's version of the file (RIGHT).
+        """
+        panel = self.nvim.exec_lua(DIFFVIEW_PANEL_LUA, buffer.handle)
+        if panel is not None:
+            return 'LEFT' if panel == 'a' else 'RIGHT'
+        return 'RIGHT' if revision.startswith(':') else 'LEFT'
+
+    def current_buffer_location(self) -> Optional[BufferLocation]:
+        """
+        Return the current buffer's path in the git repository and diff side,
+        or None if it does not exist.
+        """
+        return self.buffer_location(self.nvim.current.buffer)
+
+    def current_buffer_path(self) -> Optional[str]:
+        """
+        Return the buffer's current path in the git repository, or None if it does not exist.
+
+        For example, a file called "test.py" within a parent directory called
+        "project" would return the path `project/test.py`.
+        """
+        location = self.current_buffer_location()
+        if location is None:
+            return None
+        return location[0]
+
+    # TODO: Add additional comments to an already-published review
+
+    def new_temporary_buffer(self, on_save_command: Optional[str] = None, filetype: str = 'markdown'):
+        """
+        Create a new buffer for a temporary file and open it in a split.
+
+        Additionally, set the provided `on_save_command` to be executed on
+        buffer save.
+        """
+        with NamedTemporaryFile('w') as f:
+            # Open a new buffer and focus it
+            self.nvim.command(f'sp {f.name}')
+            # Use markdown highlighting (comment buffers extend it with a header)
+            self.nvim.command(f'set ft={filetype}')
+            self.nvim.command('setlocal omnifunc=ReviewerCompleteMention')
+            if on_save_command:
+                # Set the on-save behavior for this buffer. This uses the buffer-local
+                # autocommands feature.
+                self.nvim.command(f'autocmd BufWritePost <buffer> :{on_save_command}')
+
+    def current_buffer_contents(self) -> str:
+        """
+        Return the text of the current buffer. Its final newline is implied
+        rather than returned; pass the text through `normalize_text` before
+        storing it.
+        """
+        buffer_contents = self.nvim.current.buffer[:]
+        return '\n'.join(buffer_contents)
+
+    @pynvim.command('ReviewComment', sync=True, nargs="?", range='')
+    def review_comment(self, args, range):
+        """
+        Initiate a review comment for the given range selection.
+
+        This will open up a new buffer for the comment. The comment is saved to
+        disk at every write.
+        """
+        self.start_comment(range[0], range[1])
+
+    @pynvim.command('ReviewNote', sync=True, nargs="?", range='')
+    def review_note(self, args, range):
+        """
+        Write a private note on the given range, e.g. a reminder to check
+        something later. Notes are saved with the review but never published
+        or exported.
+        """
+        self.start_comment(range[0], range[1], note=True)
+
+    @pynvim.function('ReviewerCommentRange', sync=True)
+    def comment_range(self, args):
+        """
+        Start a comment on lines `args[0]` to `args[1]` of the current
+        buffer, with the optional `severity` and `labels` of the options
+        dictionary `args[2]` filled in. This backs the Lua
+        `require('reviewer').comment_selection()`.
+        """
+        # An empty Lua table arrives as an empty list.
+        options = args[2] if len(args) > 2 and isinstance(args[2], dict) else {}
+        self.start_comment(int(args[0]), int(args[1]), options.get('severity'), options.get('labels') or [])
+
+    def start_comment(
+        self,
+        start_line: int,
+        end_line: int,
+        severity: Optional[str] = None,
+        labels: Optional[List[str]] = None,
+        note: bool = False,
+    ):
+        if self.refuse_if_read_only():
+            return
+        if self.in_progress_comment is not None:
+            self.nvim.err_write("A review comment is already being edited.\n")
+            return
+
+        range = [start_line, end_line]
+        if self.is_patch_buffer(self.nvim.current.buffer):
+            self.in_progress_comment = self.new_patch_comment(range)
+            if self.in_progress_comment is not None:
+                self.in_progress_comment.severity = severity
+                self.in_progress_comment.labels = list(labels or [])
+                self.in_progress_comment.ready = not self.nvim.vars.get('reviewer_hold_new_comments')
+                self.open_comment_buffer('new', note)
+            return
+
+        submodule = self.buffer_submodule(self.nvim.current.buffer)
+        if submodule is not None:
+            self.nvim.err_write(
+                f"This file belongs to the submodule {submodule}, a separate repository that can't be "
+                f"commented on in this review. To review a PR of the submodule, start Neovim in its "
+                f"directory and use :StartReview there.\n"
+            )
+            return
+        location = self.current_buffer_location()
+        if location is None:
+            self.nvim.err_write("Current buffer is not a valid path in the git repository.\n")
+            return
+        path, side = location
+        if self.buffer_is_stale(path, side):
+            return
+        if self.is_working_tree_buffer(self.nvim.current.buffer):
+            side, start_line, end_line = self.infer_side(path, start_line, end_line)
+        multi_line = start_line != end_line
+        start_column, end_column = self.selection_columns(range)
+        self.in_progress_comment = offline_pr_review.Comment(
+            body="",
+            path=path,
+            line=end_line,
+            start_line=start_line if multi_line else None,
+            side=side,
+            start_side=side if multi_line else None,
+            start_column=start_column,
+            end_column=end_column,
+            severity=severity,
+            labels=list(labels or []),
+            ready=not self.nvim.vars.get('reviewer_hold_new_comments'),
+        )
+        self.open_comment_buffer('new', note)
+        self.show_blame_header(path, side, start_line, end_line)
+
+    def is_working_tree_buffer(self, buffer: pynvim.api.Buffer) -> bool:
+        return os.path.isabs(buffer.name) and self.resolve_user_buffer(buffer) is None
+
+    def infer_side(self, path: str, start_line: int, end_line: int) -> Tuple[offline_pr_review.Side, int, int]:
+        """
+        Work out which side of the PR diff lines `start_line` to `end_line` of
+        the current working tree buffer are on.
+
+        They are on the RIGHT side when the working tree has the PR head
+        checked out, but if it holds another version of the file (e.g. the
+        base), lines that only exist in the base have to be commented on the
+        LEFT side instead. Lines that can't be matched with the diff are
+        assumed to be on the RIGHT side.
+        """
+        try:
+            changed_files = self.review.fetch_changed_files()
+        except requests.RequestException:
+            return 'RIGHT', start_line, end_line
+        changed_file = next((f for f in changed_files if f.path == path), None)
+        file_diff = self.review.fetch_file_diff(changed_file) if changed_file is not None else None
+        if file_diff is None:
+            return 'RIGHT', start_line, end_line
+        inferred = file_diff.infer_side(start_line, self.nvim.current.buffer[start_line - 1:end_line])
+        if inferred is not None and inferred[0] == 'LEFT':
+            self.info("These lines only exist in the PR base; commenting on the LEFT side.\n")
+            return inferred
+        return 'RIGHT', start_line, end_line
+
+    def selection_columns(self, range) -> Tuple[Optional[int], Optional[int]]:
+        """
+        Return the start and end columns of the last visual selection if it
+        was character-wise and `range` comes from it, or (None, None).
+        """
+        if self.nvim.call('visualmode') != 'v':
+            return None, None
+        start, end = self.nvim.call('getpos', "'<"), self.nvim.call('getpos', "'>")
+        if (start[1], end[1]) != (range[0], range[1]):
+            return None, None
+        # Selections extended to the end of the line (`$`) report a huge column.
+        end_column = min(end[2], len(self.nvim.current.buffer[end[1] - 1]))
+        return start[2], max(end_column, 1)
+
+    def open_comment_buffer(self, kind: str, note: bool = False):
+        """
+        Open the in-progress comment (or private note) in a `reviewer-comment`
+        buffer, with its header above the body and the cursor on the body.
+        `kind` is `new` or `existing`.
+
+        `:SaveComment` finds the comment again from buffer variables: a copy
+        of the comment (`b:reviewer_comment`), whether it still has to be
+        added to the review (`b:reviewer_comment_new`) and whether it is a
+        note (`b:reviewer_comment_note`).
+        """
+        self.new_temporary_buffer(on_save_command='SaveComment', filetype='reviewer-comment')
+        self.nvim.current.buffer.vars['reviewer_comment'] = self.in_progress_comment.to_json()
+        self.nvim.current.buffer.vars['reviewer_comment_new'] = kind == 'new'
+        self.nvim.current.buffer.vars['reviewer_comment_note'] = note
+        lines = self.in_progress_comment.editor_text().split('\n')
+        self.nvim.current.buffer[:] = lines
+        self.nvim.current.window.cursor = (len(lines), 0)
+
+    @pynvim.command('ReviewSnippet', nargs='*', range='', bang=True, sync=True)
+    def review_snippet(self, args, range, bang):
+        """
+        Upload the selected lines as a secret gist (public with a bang) and
+        append a link to it to the comment being written, e.g. for a
+        counter-proposal too long to go inline. Arguments are used as the
+        gist's description.
+        """
+        if not self.is_review_active():
+            self.nvim.err_write("No review is currently active.\n")
+            return
+        comment_buffer = self.displayed_comment_buffer()
+        if comment_buffer is None:
+            self.nvim.err_write("Open a comment with :ReviewComment or :EditComment first; the link is added to it.\n")
+            return
+        buffer = self.nvim.current.buffer
+        content = '\n'.join(buffer[range[0] - 1:range[1]]) + '\n'
+        file_name = os.path.basename(buffer.name) or 'snippet.txt'
+        description = ' '.join(args) or f'Snippet for the review of {self.review.title}'
+        try:
+            url = offline_pr_review.create_snippet(file_name, content, description, public=bang)
+        except requests.RequestException as e:
+            self.nvim.err_write(f"Could not create the gist (the token needs the gist scope): {e}\n")
+            return
+        comment_buffer.append(['', f'[{description}]({url})'])
+        self.info(f"Created {url} and linked it in the comment; write the comment to save it.\n")
+
+    def displayed_comment_buffer(self) -> Optional[pynvim.api.Buffer]:
+        """
+        Return the comment buffer shown in the current tab: the one of the
+        in-progress comment, or else the only one shown. Returns None if
+        there is none, or several.
+        """
+        buffers = [
+            window.buffer for window in self.nvim.current.tabpage.windows
+            if 'reviewer_comment' in window.buffer.vars
+        ]
+        if self.in_progress_comment is not None:
+            for buffer in buffers:
+                if buffer.vars['reviewer_comment'].get('id') == self.in_progress_comment.id:
+                    return buffer
+        return buffers[0] if len({buffer.handle for buffer in buffers}) == 1 else None
+
+    def show_blame_header(self, path: str, side: offline_pr_review.Side, start_line: int, end_line: int):
+        """
+        Show who last changed the commented lines, and in which commit, as
+        virtual lines above the comment being written, once a background job
+        has looked them up.
+
+        Disabled with `let g:reviewer_comment_blame = 0`, and skipped when the
+        commented revision isn't available locally.
+        """
+        if not self.nvim.vars.get('reviewer_comment_blame', 1) or self.review.remote_only:
+            return
+        review, buffer = self.review, self.nvim.current.buffer
+        self.jobs.submit(
+            f'Blame {path}:{start_line}-{end_line}',
+            lambda job: self.blame_in_background(review, path, side, start_line, end_line),
+            on_done=lambda entries: self.add_blame_lines(buffer, entries),
+        )
+
+    def blame_in_background(
+        self,
+        review: offline_pr_review.Review,
+        path: str,
+        side: offline_pr_review.Side,
+        start_line: int,
+        end_line: int,
+    ) -> List[offline_pr_review.BlameEntry]:
+        """
+        Blame lines `start_line` to `end_line` of `path` at the commented
+        revision, with no entries if that revision isn't known. Runs in a
+        background job, so it must not touch Neovim.
+        """
+        try:
+            revision = review.base_sha() if side == 'LEFT' else review.head_sha()
+        except requests.RequestException:
+            return []
+        if revision is None:
+            return []
+        return offline_pr_review.get_blame(revision, path, start_line, end_line)
+
+    def add_blame_lines(self, buffer: pynvim.api.Buffer, entries: List[offline_pr_review.BlameEntry]):
+        """
+        Add the virtual lines describing the blame `entries` to the top of the
+        comment `buffer`, if it is still open.
+        """
+        if not entries or not buffer.valid:
+            return
+        virtual_lines = [
+            [(f'{entry.commit_sha[:12]} {entry.author}: {entry.summary}', 'Comment')]
+            for entry in entries[:3]
+        ]
+        if len(entries) > 3:
+            virtual_lines.append([(f'... and {len(entries) - 3} more commits', 'Comment')])
+        namespace = self.nvim.api.create_namespace('reviewer-blame')
+        buffer.api.set_extmark(namespace, 0, 0, {'virt_lines': virtual_lines, 'virt_lines_above': True})
+        # Virtual lines above the first line are only shown when scrolled into view.
+        for window in self.nvim.call('win_findbuf', buffer.handle):
+            self.nvim.call('win_execute', window, f"call winrestview({{'topfill': {len(virtual_lines)}}})")
+
+
+    def new_patch_comment(self, range) -> Optional[offline_pr_review.Comment]:
+        """
+        Create a comment for the given range of lines of the patch file under
+        review, which must lie within a single hunk's file.
+        """
+        start = offline_pr_review.locate_source_line(self.patches, range[0])
+        end = offline_pr_review.locate_source_line(self.patches, range[1])
+        if start is None or end is None:
+            self.nvim.err_write("Comments must be on the lines of a hunk in the patch.\n")
+            return None
+        patch_index, file_diff, end_line = end
+        if start[0] != patch_index or start[1] is not file_diff:
+            self.nvim.err_write("Comments cannot span several patches or files.\n")
+            return None
+        side = 'LEFT' if end_line.kind == '-' else 'RIGHT'
+        start_line = start[2].line_on_side(side)
+        multi_line = start_line is not None and start_line != end_line.line_on_side(side)
+        return offline_pr_review.Comment(
+            body="",
+            path=file_diff.path,
+            line=end_line.line_on_side(side),
+            start_line=start_line if multi_line else None,
+            side=side,
+            start_side=side if multi_line else None,
+            patch_index=patch_index,
+        )
+
+    def comment_under_cursor(self, line: int, notes: bool = False) -> Optional[offline_pr_review.Comment]:
+        """
+        Return the comment (or with `notes`, the note) at `line` of the
+        current buffer, reporting an error if there is none.
+        """
+        comments = self.comments_under_cursor(line, notes)
+        return comments[0] if comments else None
+
+    def comments_under_cursor(self, line: int, notes: bool = False) -> List[offline_pr_review.Comment]:
+        """
+        Return all the comments (or with `notes`, the notes) at `line` of the
+        current buffer, reporting an error if there are none.
+        """
+        at_position = self.review.get_notes_at_position if notes else self.review.get_comments_at_position
+        comments = []
+        if self.is_patch_buffer(self.nvim.current.buffer):
+            located = offline_pr_review.locate_source_line(self.patches, line)
+            if located is not None:
+                patch_index, file_diff, diff_line = located
+                side = 'LEFT' if diff_line.kind == '-' else 'RIGHT'
+                comments = at_position(file_diff.path, diff_line.line_on_side(side), side, patch_index)
+        else:
+            location = self.current_buffer_location()
+            if location is None:
+                self.nvim.err_write("Current buffer is not a valid path in the git repository.\n")
+                return []
+            path, side = location
+            comments = at_position(path, line, side)
+        if not comments:
+            self.nvim.err_write(f"No {'note' if notes else 'comment'} under the cursor.\n")
+        return comments
+
+    @pynvim.command('PreviewComment', sync=True)
+    def preview_comment(self):
+        """
+        Show the comment under the cursor in a floating window. When several
+        comments overlap the line, running it again shows the next one.
+        """
+        if not self.is_review_active():
+            self.nvim.err_write("No review is currently active.\n")
+            return
+        line = self.nvim.current.window.cursor[0]
+        comments = self.comments_under_cursor(line)
+        if not comments:
+            return
+        position = (self.nvim.current.buffer.handle, line)
+        index = 0
+        if self.preview_position is not None and self.preview_position[:2] == position and self.preview_is_open():
+            index = (self.preview_position[2] + 1) % len(comments)
+        self.close_preview()
+        comment = comments[index]
+        lines = [
+            f'#{self.review.comment_number(comment)} ({index + 1}/{len(comments)}) by {comment.describe_origin()}',
+            '',
+            *comment.decorated_body.split('\n'),
+        ]
+        buffer = self.nvim.api.create_buf(False, True)
+        buffer[:] = lines
+        buffer.options['filetype'] = 'markdown'
+        self.preview_window = self.nvim.api.open_win(buffer, False, {
+            'relative': 'cursor',
+            'row': 1,
+            'col': 0,
+            'width': max(20, min(80, max(len(text) for text in lines))),
+            'height': min(20, len(lines)),
+            'style': 'minimal',
+            'border': 'rounded',
+        })
+        self.preview_position = (*position, index)
+        # The preview closes as soon as the cursor moves. Showing the next
+        # comment replaces the autocommand of the previous preview.
+        self.nvim.command('augroup ReviewerPreview | augroup END')
+        self.nvim.command('autocmd! ReviewerPreview * <buffer>')
+        self.nvim.command(
+            f'autocmd ReviewerPreview CursorMoved,BufLeave <buffer> ++once '
+            f'if nvim_win_is_valid({self.preview_window.handle}) | call nvim_win_close({self.preview_window.handle}, v:true) | endif'
+        )
+
+    def preview_is_open(self) -> bool:
+        return self.preview_window is not None and self.preview_window.valid
+
+    def close_preview(self):
+        if self.preview_is_open():
+            self.nvim.api.win_close(self.preview_window, True)
+        self.preview_window = None
+
+    def fetch_head_sha(self) -> Optional[str]:
+        """
+        Return the PR's head SHA, fetching it from GitHub the first time.
+        """
+        if self.pull_request_head_sha is None:
+            try:
+                self.pull_request_head_sha = self.review.head_sha()
+            except requests.RequestException as e:
+                self.nvim.err_write(f"Could not fetch the PR head commit: {e}\n")
+        return self.pull_request_head_sha
+
+    def buffer_is_stale(self, path: str, side: offline_pr_review.Side) -> bool:
+        """
+        Check whether the current working tree buffer differs from the file
+        at the PR's head commit, since line numbers of comments left on stale
+        code won't match what GitHub expects.
+
+        If it does, ask whether to comment anyway or to open the head version
+        of the file instead. Returns True if the comment should not proceed.
+        """
+        if side != 'RIGHT' or not os.path.isabs(self.nvim.current.buffer.name):
+            return False
+        head_sha = self.fetch_head_sha()
+        if head_sha is None:
+            return False
+        if offline_pr_review.resolve_revision(head_sha) is None:
+            self.nvim.err_write(
+                f"The PR head commit {head_sha[:12]} is not available locally; your checkout may be behind.\n"
+            )
+            return False
+        head_contents = offline_pr_review.get_file_at_revision(head_sha, path)
+        if head_contents is not None and head_contents.rstrip('\n') == self.current_buffer_contents().rstrip('\n'):
+            return False
+
+        choice = self.nvim.call(
+            'confirm',
+            f'{path} differs from the PR head ({head_sha[:12]}). Line numbers may be wrong.',
+            "&Comment anyway\n&Open PR head version\n&Cancel",
+            3,
+        )
+        if choice == 1:
+            return False
+        if choice == 2:
+            if self.nvim.call('exists', ':Gedit') != 2:
+                self.nvim.err_write("Opening the PR head version requires vim-fugitive.\n")
+                return True
+            self.nvim.command(f'Gedit {head_sha}:{path}')
+        return True
+
+    @pynvim.command('SaveComment', sync=True)
+    def save_comment(self):
+        """
+        Save the contents of the comment buffer to disk.
+
+        This command is set to be triggered on `BufWritePost` for the comment
+        buffer (e.g., on every write), so it has to work for every write: the
+        first one adds a new comment to the review, later ones update it.
+
+        Note that this command _must_ be `sync=True`, otherwise the buffer
+        contents will be empty before they can be accessed in the case of a
+        save-and-exit command (`:wq`).
+        """
+        buffer = self.nvim.current.buffer
+        saved = buffer.vars.get('reviewer_comment')
+        if saved is None:
+            self.nvim.err_write("The current buffer is not a review comment.\n")
+            return
+        if not self.review_active:
+            self.nvim.err_write("No review is currently active.\n")
+            return
+        if self.refuse_if_read_only():
+            return
+        is_new_comment = buffer.vars.get('reviewer_comment_new', False)
+        is_note = buffer.vars.get('reviewer_comment_note', False)
+        kind = 'note' if is_note else 'comment'
+        # Look the comment up by ID, since the review may have been reloaded
+        # while it was being edited.
+        comment = self.review.find_note(saved['id']) if is_note else self.review.find_comment(saved['id'])
+        if comment is None:
+            if not is_new_comment:
+                self.nvim.err_write(
+                    f"The {kind} being edited is no longer part of the review; saving it as a new {kind}.\n"
+                )
+            comment = offline_pr_review.Comment.from_json(saved)
+            comment.update_from_editor_text(self.current_buffer_contents())
+            comment.outdated = False
+            if is_note:
+                self.review.add_note(comment)
+            else:
+                self.review.add_comment(comment)
+        else:
+            previous = (comment.body, comment.severity, comment.labels, comment.ready)
+            comment.update_from_editor_text(self.current_buffer_contents())
+            comment.outdated = False
+            comment.touch()
+            # Writing the buffer without changing anything isn't an edit.
+            if (comment.body, comment.severity, comment.labels, comment.ready) != previous:
+                self.review.log_action('edit-note' if is_note else 'edit', comment)
+        buffer.vars['reviewer_comment'] = comment.to_json()
+        buffer.vars['reviewer_comment_new'] = False
+        self.in_progress_comment = None
+        self.save()
+
+    @pynvim.command('ReviewBody', sync=True)
+    def review_body(self):
+        if self.refuse_if_read_only():
+            return
+        if self.is_review_active():
+            self.new_temporary_buffer(on_save_command='SaveReviewBody')
+            self.nvim.current.buffer[:] = self.review.body.split('\n')
+        else:
+            self.nvim.err_write("No review is currently active.\n")
+
+    @pynvim.command('ReviewSummarize', sync=True)
+    def review_summarize(self):
+        """
+        Open the review body with an outline of the comments, grouped by
+        severity, appended to it. Nothing is saved until the buffer is written.
+        """
+        if not self.is_review_active():
+            self.nvim.err_write("No review is currently active.\n")
+            return
+        if self.refuse_if_read_only():
+            return
+        if not self.review.comments:
+            self.nvim.err_write("The review has no comments to summarize.\n")
+            return
+        body = '\n\n'.join(part for part in (self.review.body.strip('\n'), self.review.summary()) if part)
+        self.new_temporary_buffer(on_save_command='SaveReviewBody')
+        self.nvim.current.buffer[:] = body.split('\n')
+
+    @pynvim.command('SaveReviewBody', sync=True)
+    def save_review_body(self):
+        """
+        Save the contents of the review body buffer to disk.
+
+        This command is set to be triggered on `BufWritePost` for the review body
+        buffer (e.g., on every write).
+
+        Note that this command _must_ be `sync=True`, otherwise the buffer
+        contents will be empty before they can be accessed in the case of a
+        save-and-exit command (`:wq`).
+        """
+        if self.refuse_if_read_only():
+            return
+        if self.is_review_active():
+            self.review.body = offline_pr_review.normalize_text(self.current_buffer_contents())
+            self.review.log_action('edit-body', details=self.review.body.strip().split('\n')[0][:60])
+            self.save()
+
+    @pynvim.command('EditComment', nargs="*", range="")
+    def edit_comment(self, args, range):
+        """
+        Open up the comment for the line under the cursor, if one exists.
+        """
+        if self.refuse_if_read_only():
+            return
+        comment_to_edit = self.comment_under_cursor(range[0])
+        if comment_to_edit is None:
+            return
+
+        self.in_progress_comment = comment_to_edit
+        self.open_comment_buffer('existing')
+
+    @pynvim.command('EditNote', range="")
+    def edit_note(self, range):
+        """
+        Open up the private note for the line under the cursor, if one exists.
+        """
+        if self.refuse_if_read_only():
+            return
+        note = self.comment_under_cursor(range[0], notes=True)
+        if note is None:
+            return
+        self.in_progress_comment = note
+        self.open_comment_buffer('existing', note=True)
+
+    @pynvim.command('DeleteNote', range="", bang=True)
+    def delete_note(self, range, bang):
+        """
+        Delete the private note for the line under the cursor, after asking
+        for confirmation. `:DeleteNote!` doesn't ask.
+        """
+        if self.refuse_if_read_only():
+            return
+        note = self.comment_under_cursor(range[0], notes=True)
+        if note is None:
+            return
+        summary = note.body.split('\n')[0][:60]
+        if not bang and self.nvim.call('confirm', f"Delete note ({summary})?", "&Yes\n&No", 2) != 1:
+            return
+        self.review.delete_note(note)
+        self.info("Note deleted.\n")
+        self.update_signs()
+
+    @pynvim.command('ToggleCommentReady', range="")
+    def toggle_comment_ready(self, range):
+        """
+        Hold the comment under the cursor back from publishing, or mark it
+        ready again. In the quickfix list of `:QuickfixAllComments` (or the
+        location list of `:LoclistFileComments`), this acts on the comment of
+        the entry under the cursor.
+        """
+        if self.refuse_if_read_only():
+            return
+        if self.nvim.current.buffer.options['buftype'] == 'quickfix':
+            if not self.is_review_active():
+                self.nvim.err_write("No review is currently active.\n")
+                return
+            what = {'idx': range[0], 'items': 1}
+            if self.nvim.call('getwininfo', self.nvim.call('win_getid'))[0]['loclist']:
+                items = self.nvim.call('getloclist', 0, what)['items']
+            else:
+                items = self.nvim.call('getqflist', what)['items']
+            user_data = items[0].get('user_data') if items else None
+            comment = self.review.find_comment(user_data['comment_id']) if isinstance(user_data, dict) else None
+            if comment is None:
+                self.nvim.err_write("No review comment on this quickfix entry.\n")
+                return
+        else:
+            comment = self.comment_under_cursor(range[0])
+            if comment is None:
+                return
+        comment.ready = not comment.ready
+        comment.touch()
+        self.review.log_action('ready' if comment.ready else 'hold', comment)
+        self.save()
+        state = 'ready to publish' if comment.ready else 'held back from publishing'
+        self.info(f"Comment #{self.review.comment_number(comment)} is {state}.\n")
+
+    @pynvim.command('PromoteNote', range="")
+    def promote_note(self, range):
+        """
+        Turn the private note under the cursor into a draft comment, to be
+        published with the rest of the review.
+        """
+        if self.refuse_if_read_only():
+            return
+        note = self.comment_under_cursor(range[0], notes=True)
+        if note is None:
+            return
+        self.review.promote_note(note)
+        self.info(f"Note promoted to comment #{self.review.comment_number(note)}.\n")
+        self.update_signs()
+
+    @pynvim.command('DemoteComment', range="")
+    def demote_comment(self, range):
+        """
+        Turn the draft comment under the cursor into a private note.
+        """
+        if self.refuse_if_read_only():
+            return
+        comment = self.comment_under_cursor(range[0])
+        if comment is None:
+            return
+        try:
+            self.review.demote_comment(comment)
+        except ValueError as e:
+            self.nvim.err_write(f"Cannot demote the comment: {e}.\n")
+            return
+        self.info("Comment demoted to a private note.\n")
+        self.update_signs()
+
+    @pynvim.command('DeleteComment', nargs="*", range="", bang=True, complete='customlist,ReviewerCompleteComment')
+    def delete_comment(self, args, range, bang):
+        """
+        Delete the comment for the line under the cursor, if one exists, or
+        the comment with the given number or ID (`:DeleteComment 3`), after
+        asking for confirmation. `:DeleteComment!` doesn't ask.
+        """
+        if self.refuse_if_read_only():
+            return
+        if args:
+            if not self.is_review_active():
+                self.nvim.err_write("No review is currently active.\n")
+                return
+            comment_to_delete = self.find_comment(args[0])
+            if comment_to_delete is None:
+                self.nvim.err_write(f"No comment {args[0]} in this review.\n")
+                return
+        else:
+            comment_to_delete = self.comment_under_cursor(range[0])
+            if comment_to_delete is None:
+                return
+
+        summary = comment_to_delete.body.split('\n')[0][:60]
+        if not bang and self.nvim.call(
+            'confirm', f"Delete comment #{self.review.comment_number(comment_to_delete)} ({summary})?", "&Yes\n&No", 2
+        ) != 1:
+            return
+        self.review.delete_comment(comment_to_delete)
+        self.info("Comment deleted.\n")
+        self.update_signs()
+
+    @pynvim.command('ReviewDiffFile', sync=True)
+    def review_diff_file(self):
+        """
+        Open a diff split of the current file against the PR's base commit.
+        """
+        if not self.is_review_active():
+            self.nvim.err_write("No review is currently active.\n")
+            return
+        path = self.current_buffer_path()
+        if path is None:
+            self.nvim.err_write("Current buffer is not a valid path in the git repository.\n")
+            return
+        if self.review.remote_only:
+            self.nvim.command('diffthis')
+            self.nvim.command('vertical split')
+            if self.open_remote_file(path, 'LEFT'):
+                self.nvim.command('diffthis')
+            return
+
+        base_sha = self.review.base_sha()
+        if base_sha is None:
+            self.nvim.err_write("Could not determine the base commit of the PR.\n")
+            return
+
+        if self.nvim.call('exists', ':Gdiffsplit') == 2:
+            self.nvim.command(f'Gdiffsplit {base_sha}:{path}')
+            return
+
+        # Without fugitive, diff against a scratch copy of the base version.
+        base_contents = offline_pr_review.get_file_at_revision(base_sha, path)
+        if base_contents is None:
+            self.nvim.err_write(f"{path} does not exist in the base commit {base_sha}.\n")
+            return
+        self.nvim.command('diffthis')
+        self.nvim.command('vertical new')
+        self.nvim.current.buffer[:] = base_contents.split('\n')
+        self.nvim.command('setlocal buftype=nofile bufhidden=wipe nomodifiable')
+        self.nvim.command('diffthis')
+
+    def fetch_changed_files(self) -> bool:
+        """
+        Fetch the PR's changed files into `self.changed_files`, reporting any
+        errors. Returns whether the fetch succeeded.
+
+        Big PRs are downloaded a page at a time, with the progress echoed
+        (unless `g:reviewer_verbosity` is `quiet`).
+        """
+        quiet = self.verbosity() == 'quiet'
+
+        def progress(count: int):
+            if not quiet:
+                self.nvim.command(f'redraw | echo "Fetched {count} changed files..."')
+
+        try:
+            self.changed_files = self.without_ignored(self.review.fetch_changed_files(progress))
+        except requests.RequestException as e:
+            self.nvim.err_write(f"Could not fetch the changed files of the PR: {e}\n")
+            return False
+        return True
+
+    @pynvim.command('ReviewFiles', sync=True)
+    def review_files(self):
+        """
+        Open a panel listing the files changed by the PR, along with the
+        number of draft and already published comments on each.
+
+        In the panel, `<CR>` opens the file under the cursor, `d` opens its
+        diff against the PR base, and `v` toggles whether it has been viewed.
+
+        The draft comments and notes on each file are listed under it, grouped
+        in threads by the lines they are on, in folds that are closed until
+        opened. Which folds are open is remembered until the review ends.
+        """
+        if not self.is_review_active():
+            self.nvim.err_write("No review is currently active.\n")
+            return
+        try:
+            overview = self.review.fetch_overview(self.nvim.vars.get('reviewer_graphql', 1))
+        except requests.RequestException as e:
+            self.nvim.err_write(f"Could not fetch the changed files of the PR: {e}\n")
+            return
+        # Files loaded over GraphQL have no patches; those are only needed
+        # to match lines with the diff, which fetches them separately.
+        self.changed_files = self.without_ignored(overview.changed_files)
+        self.files_panel_remote_counts = overview.remote_comment_counts
+        if overview.viewed_paths is not None and self.nvim.vars.get('reviewer_sync_viewed_files'):
+            for path in overview.viewed_paths:
+                if not self.review.is_file_viewed(path):
+                    self.review.viewed_files.append(path)
+            self.save()
+
+        self.nvim.command('topleft vertical 50new')
+        self.nvim.command('setlocal buftype=nofile bufhidden=wipe noswapfile nobuflisted nowrap')
+        self.nvim.command('setlocal filetype=reviewer-files')
+        # The fold level of each line is set by `render_files_panel`: files
+        # are at the top level, with their threads and comments nested under.
+        self.nvim.command(
+            "setlocal foldmethod=expr foldexpr=get(b:reviewer_fold_levels,v:lnum-1,0) foldlevel=0 foldminlines=0"
+        )
+        # The folds of a previous panel were recorded when it was closed.
+        self.files_panel_folds = []
+        self.render_files_panel(self.nvim.current.buffer)
+        self.nvim.command('autocmd BufLeave <buffer> call ReviewFilesPanelRememberFolds()')
+        self.nvim.command("nnoremap <buffer> <silent> <CR> :call ReviewFilesPanelAction('open')<CR>")
+        self.nvim.command("nnoremap <buffer> <silent> d :call ReviewFilesPanelAction('diff')<CR>")
+        self.nvim.command("nnoremap <buffer> <silent> v :call ReviewFilesPanelAction('viewed')<CR>")
+
+    def render_files_panel(self, buffer: pynvim.api.Buffer):
+        """
+        Fill the files panel, which must be the current window, keeping the
+        folds the user opened open.
+        """
+        if self.files_panel_folds:
+            self.remember_files_panel_folds()
+        draft_counts = self.review.comment_counts()
+        held_counts: Dict[str, int] = {}
+        for comment in self.review.held_comments():
+            held_counts[comment.path] = held_counts.get(comment.path, 0) + 1
+        note_counts = self.review.note_counts()
+        viewed_count = len([f for f in self.changed_files if self.review.is_file_viewed(f.path)])
+        scope = f' ({self.review.commit_range})' if self.review.commit_range and not self.review.local_only else ''
+        ignored = f' ({self.ignored_file_count} ignored)' if self.ignored_file_count else ''
+        lines = [
+            f'{self.review.title}{scope}: {len(self.changed_files)} changed files{ignored}, {viewed_count} viewed',
+            '',
+        ]
+        self.files_panel_paths = [None, None]
+        self.files_panel_lines = [None, None]
+        self.files_panel_folds = [None, None]
+        levels = [0, 0]
+        threads = self.comment_threads()
+        for changed_file in self.changed_files:
+            counts = []
+            if draft_counts.get(changed_file.path):
+                counts.append(f'{draft_counts[changed_file.path]} draft')
+            if held_counts.get(changed_file.path):
+                counts.append(f'{held_counts[changed_file.path]} held')
+            if self.files_panel_remote_counts.get(changed_file.path):
+                counts.append(f'{self.files_panel_remote_counts[changed_file.path]} remote')
+            if note_counts.get(changed_file.path):
+                counts.append(f'{note_counts[changed_file.path]} notes')
+            summary = f'  ({", ".join(counts)})' if counts else ''
+            viewed = '✓' if self.review.is_file_viewed(changed_file.path) else ' '
+            lines.append(f'{viewed} {changed_file.status_letter} {changed_file.path}{summary}')
+            self.files_panel_paths.append(changed_file.path)
+            self.files_panel_lines.append(None)
+            file_threads = threads.get(changed_file.path, [])
+            self.files_panel_folds.append(changed_file.path if file_threads else None)
+            levels.append(0)
+            for location, thread in file_threads:
+                lines.append(f'  {location}:')
+                self.files_panel_paths.append(changed_file.path)
+                self.files_panel_lines.append(self.files_panel_jump_line(thread[0][1]))
+                self.files_panel_folds.append(f'{changed_file.path}:{location}')
+                levels.append(1)
+                for kind, comment in thread:
+                    first_line = comment.body.strip().split('\n')[0][:60]
+                    lines.append(f'    [{kind}] {first_line}')
+                    self.files_panel_paths.append(changed_file.path)
+                    self.files_panel_lines.append(self.files_panel_jump_line(comment))
+                    self.files_panel_folds.append(None)
+                    levels.append(2)
+
+        # Private notes get a section of their own, so that reminders left
+        # for later are easy to find again.
+        if self.review.notes:
+            lines += ['', f'Private notes ({len(self.review.notes)}):']
+            self.files_panel_paths += [None, None]
+            self.files_panel_lines += [None, None]
+            self.files_panel_folds += [None, 'notes']
+            levels += [0, 0]
+            changed_paths = {changed_file.path for changed_file in self.changed_files}
+            for note in sorted(self.review.notes, key=lambda note: (note.path, note.line)):
+                first_line = note.body.strip().split('\n')[0][:60]
+                lines.append(f'  {note.path}:{note.line}  {first_line}')
+                self.files_panel_paths.append(note.path if note.path in changed_paths else None)
+                self.files_panel_lines.append(self.files_panel_jump_line(note))
+                self.files_panel_folds.append(None)
+                levels.append(1)
+
+        buffer.vars['reviewer_fold_levels'] = levels
+        buffer.options['modifiable'] = True
+        buffer[:] = lines
+        buffer.options['modifiable'] = False
+        self.restore_files_panel_folds()
+
+    def comment_threads(self) -> Dict[str, List[Tuple[str, List[Tuple[str, offline_pr_review.Comment]]]]]:
+        """
+        Group the draft comments and notes of each file by the lines they are
+        on, in the order of those lines. Each thread is listed under its
+        location (e.g. `Lines 10-12 (LEFT)`), and each of its comments under
+        its kind: `draft`, `held` or `note`.
+        """
+        grouped: Dict[Tuple[str, str, int, int], List[Tuple[str, offline_pr_review.Comment]]] = {}
+        entries = [('draft' if comment.ready else 'held', comment) for comment in self.review.comments]
+        entries += [('note', note) for note in self.review.notes]
+        for kind, comment in entries:
+            key = (comment.path, comment.side, comment.start_line or comment.line, comment.line)
+            grouped.setdefault(key, []).append((kind, comment))
+        threads: Dict[str, List[Tuple[str, List[Tuple[str, offline_pr_review.Comment]]]]] = {}
+        for (path, side, start_line, line) in sorted(grouped, key=lambda key: (key[0], key[2], key[3], key[1])):
+            location = f'Line {line}' if start_line == line else f'Lines {start_line}-{line}'
+            if side == 'LEFT':
+                location += ' (LEFT)'
+            threads.setdefault(path, []).append((location, grouped[(path, side, start_line, line)]))
+        return threads
+
+    def files_panel_jump_line(self, comment: offline_pr_review.Comment) -> Optional[int]:
+        """
+        Return the line of the file to jump to when opening a comment from
+        the files panel, or None when the opened buffer doesn't show the
+        commented side (or is a patch).
+        """
+        if comment.side != 'RIGHT' or self.review.patch_file:
+            return None
+        return comment.start_line or comment.line
+
+    def remember_files_panel_folds(self):
+        """
+        Record which folds of the files panel, the current window, are open.
+        Folds hidden inside a closed fold keep their previous state.
+        """
+        for index, key in enumerate(self.files_panel_folds):
+            if key is None:
+                continue
+            # The folds start on the line after the one they belong to.
+            first_child = index + 2
+            closed_at = self.nvim.call('foldclosed', first_child)
+            if closed_at == -1:
+                self.files_panel_open_folds.add(key)
+            elif closed_at == first_child:
+                self.files_panel_open_folds.discard(key)
+
+    @pynvim.function('ReviewFilesPanelRememberFolds', sync=True)
+    def review_files_panel_remember_folds(self, args):
+        if self.is_review_active():
+            self.remember_files_panel_folds()
+
+    def restore_files_panel_folds(self):
+        """
+        Close every fold of the files panel, the current window, except the
+        ones the user left open.
+        """
+        self.nvim.command('normal! zM')
+        for index, key in enumerate(self.files_panel_folds):
+            if key is None or key not in self.files_panel_open_folds:
+                continue
+            first_child = index + 2
+            # Don't open a thread whose file is still folded.
+            closed_at = self.nvim.call('foldclosed', first_child)
+            if closed_at == first_child:
+                self.nvim.command(f'{first_child}foldopen')
+
+    @pynvim.function('ReviewFilesPanelAction', sync=True)
+    def review_files_panel_action(self, args):
+        """
+        Act on the file under the cursor in the changed-files panel.
+
+        `open` and `diff` open the file (or its diff) in the previous window,
+        and `viewed` toggles its viewed flag.
+        """
+        action = args[0]
+        line_number = self.nvim.current.window.cursor[0]
+        if line_number > len(self.files_panel_paths) or self.files_panel_paths[line_number - 1] is None:
+            return
+        path = self.files_panel_paths[line_number - 1]
+        if action == 'viewed':
+            self.toggle_file_viewed(path)
+            self.render_files_panel(self.nvim.current.buffer)
+            return
+
+        jump_line = self.files_panel_lines[line_number - 1]
+        changed_file = next(f for f in self.changed_files if f.path == path)
+        self.nvim.command('wincmd p')
+        self.open_changed_file(changed_file)
+        if jump_line is not None and changed_file.status != 'removed':
+            self.nvim.current.window.cursor = (min(jump_line, len(self.nvim.current.buffer)), 0)
+        if action == 'diff' and changed_file.status != 'removed':
+            self.review_diff_file()
+
+    def open_changed_file(self, changed_file: offline_pr_review.ChangedFile):
+        """
+        Open a file changed by the PR in the current window.
+        """
+        if self.review.remote_only:
+            self.open_remote_file(changed_file.path, 'LEFT' if changed_file.status == 'removed' else 'RIGHT')
+            return
+        if self.review.patch_file:
+            self.nvim.command(f'edit {self.nvim.call("fnameescape", self.review.patch_file)}')
+            first_hunk_line = next((
+                hunk.lines[0].source_line
+                for patch in self.patches for file_diff in patch.files if file_diff.path == changed_file.path
+                for hunk in file_diff.hunks if hunk.lines
+            ), None)
+            if first_hunk_line is not None:
+                self.nvim.current.window.cursor = (first_hunk_line, 0)
+            return
+        if changed_file.status == 'removed':
+            # Deleted files only exist in the base revision.
+            if self.nvim.call('exists', ':Gedit') != 2:
+                self.nvim.err_write(f"{changed_file.path} was deleted; opening its base version requires vim-fugitive.\n")
+                return
+            base_sha = self.review.base_sha()
+            if base_sha is None:
+                self.nvim.err_write("Could not determine the base commit of the PR.\n")
+                return
+            self.nvim.command(f'Gedit {base_sha}:{changed_file.path}')
+            return
+        self.nvim.command(f'edit {self.nvim.call("fnameescape", self.worktree_filename(changed_file.path))}')
+
+    def open_remote_file(self, path: str, side: offline_pr_review.Side) -> bool:
+        """
+        Open the version of `path` on the given side of the PR, fetched from
+        GitHub into a read-only buffer. Returns whether the file was opened.
+        """
+        buffer_name = f'reviewer://{side}/{path}'
+        if self.nvim.call('bufloaded', buffer_name):
+            self.nvim.command(f'buffer {self.nvim.call("fnameescape", buffer_name)}')
+            return True
+        try:
+            contents = self.review.fetch_file_contents(path, side)
+        except requests.RequestException as e:
+            self.nvim.err_write(f"Could not fetch {path} from GitHub: {e}\n")
+            return False
+        if contents is None:
+            self.nvim.err_write(f"{path} does not exist on the {side} side of the PR.\n")
+            return False
+
+        self.nvim.command('enew')
+        self.nvim.command('setlocal buftype=nofile bufhidden=hide noswapfile')
+        self.nvim.current.buffer.name = buffer_name
+        self.nvim.current.buffer[:] = contents.rstrip('\n').split('\n')
+        self.nvim.command('setlocal nomodifiable readonly')
+        self.nvim.command('filetype detect')
+        self.update_signs()
+        return True
+
+    def jump_to_changed_file(self, step: int, include_viewed: bool, count: int = 1):
+        """
+        Open the `count`th changed file in the direction of `step` from the
+        current one, skipping files already marked as viewed unless
+        `include_viewed`.
+        """
+        if not self.is_review_active():
+            self.nvim.err_write("No review is currently active.\n")
+            return
+        if not self.changed_files and not self.fetch_changed_files():
+            return
+
+        paths = [f.path for f in self.changed_files]
+        current_path = self.current_buffer_path()
+        if current_path in paths:
+            index = paths.index(current_path) + step
+        else:
+            index = 0 if step > 0 else len(paths) - 1
+        while 0 <= index < len(paths):
+            changed_file = self.changed_files[index]
+            if include_viewed or not self.review.is_file_viewed(changed_file.path):
+                count -= 1
+                if count <= 0:
+                    self.open_changed_file(changed_file)
+                    return
+            index += step
+        self.nvim.err_write("No more changed files to review in that direction.\n")
+
+    @pynvim.command('ReviewNextFile', count=1, bang=True, sync=True)
+    def review_next_file(self, count, bang):
+        """
+        Open the next file changed by the PR that hasn't been viewed yet, or
+        with a count (`:3ReviewNextFile`), the third one. With a bang, viewed
+        files are not skipped.
+        """
+        self.jump_to_changed_file(1, bang, count)
+
+    @pynvim.command('ReviewPrevFile', count=1, bang=True, sync=True)
+    def review_prev_file(self, count, bang):
+        """
+        Open the previous file changed by the PR that hasn't been viewed yet,
+        or with a count, the one that many files back. With a bang, viewed
+        files are not skipped.
+        """
+        self.jump_to_changed_file(-1, bang, count)
+
+    def toggle_file_viewed(self, path: str):
+        """
+        Toggle the viewed flag of `path`, mirroring it to GitHub if
+        `g:reviewer_sync_viewed_files` is set.
+        """
+        if self.refuse_if_read_only():
+            return
+        viewed = self.review.toggle_file_viewed(path)
+        self.info(f"Marked {path} as {'viewed' if viewed else 'not viewed'}.\n")
+        for buffer in self.nvim.buffers:
+            if buffer.handle in self.signed_buffers:
+                self.update_buffer_status(buffer)
+        if self.nvim.vars.get('reviewer_sync_viewed_files'):
+            try:
+                self.review.sync_file_viewed(path)
+            except requests.RequestException as e:
+                self.nvim.err_write(f"Could not sync the viewed state of {path} to GitHub: {e}\n")
+
+    @pynvim.command('ToggleFileViewed', sync=True)
+    def toggle_current_file_viewed(self):
+        """
+        Toggle the viewed flag of the file in the current buffer.
+        """
+        if not self.is_review_active():
+            self.nvim.err_write("No review is currently active.\n")
+            return
+        path = self.current_buffer_path()
+        if path is None:
+            self.nvim.err_write("Current buffer is not a valid path in the git repository.\n")
+            return
+        self.toggle_file_viewed(path)
+
+    def existing_changed_files(self) -> List[offline_pr_review.ChangedFile]:
+        """
+        Return the PR's changed files that exist in the working tree, i.e.
+        all of them except deleted files.
+        """
+        return [f for f in self.changed_files if f.status != 'removed']
+
+    @pynvim.command('ReviewFilesToArgs', sync=True)
+    def review_files_to_args(self):
+        """
+        Replace the argument list with the files changed by the PR, so they
+        can be iterated with `:next` or `:argdo`.
+        """
+        if not self.is_review_active():
+            self.nvim.err_write("No review is currently active.\n")
+            return
+        if not self.fetch_changed_files():
+            return
+        paths = [
+            self.nvim.call('fnameescape', self.worktree_filename(f.path))
+            for f in self.existing_changed_files()
+        ]
+        self.nvim.command('%argdelete')
+        if paths:
+            self.nvim.command(f'argadd {" ".join(paths)}')
+        self.info(f"Added {len(paths)} changed files to the argument list.\n")
+
+    @pynvim.command('ReviewFilesToQuickfix', sync=True)
+    def review_files_to_quickfix(self):
+        """
+        Fill the quickfix list with the files changed by the PR, so they can
+        be iterated with `:cnext`.
+        """
+        if not self.is_review_active():
+            self.nvim.err_write("No review is currently active.\n")
+            return
+        if not self.fetch_changed_files():
+            return
+        file_dictionaries = [
+            {
+                'filename': self.worktree_filename(f.path),
+                'lnum': 1,
+                'text': f'{f.status} (+{f.additions} -{f.deletions})',
+            }
+            for f in self.existing_changed_files()
+        ]
+        self.nvim.call("setqflist", [], ' ', {
+            'title': f'{self.review.title} changed files',
+            'items': file_dictionaries,
+        })
+
+    @pynvim.command('ExportReview', nargs='*', complete='customlist,ReviewerCompleteExport', sync=True)
+    def export_review(self, args):
+        """
+        Export the active review as Markdown (or, for patch reviews, as an
+        inline reply to the patches), either to the file given as argument or
+        into a new buffer.
+
+        Comments are grouped by file, unless another order is given with
+        `--order=<order>` (see `:QuickfixAllComments`).
+
+        For patch reviews, `:ExportReview email <directory> [reviewed-by]`
+        writes one reply email per patch into `directory`, ready for
+        `git send-email`. With `reviewed-by`, every patch gets a reply with a
+        `Reviewed-by:` trailer.
+
+        `:ExportReview audit <path>` writes a machine-readable record of the
+        review for archiving: CSV if `path` ends with `.csv`, JSON Lines
+        otherwise (see `offline_pr_review.AUDIT_COLUMNS`).
+
+        `:ExportReview digest [--author=<login>] [path]` compiles the comments
+        with a teaching label across this repository's reviews (see
+        `export_digest`); it doesn't need an active review.
+
+        With `--sign`, files exported to a path are also signed with the
+        user's git signing key, next to them.
+        """
+        sign = '--sign' in args
+        args = [arg for arg in args if arg != '--sign']
+        if args and args[0] == 'digest':
+            self.export_digest(args[1:], sign)
+            return
+        if not self.is_review_active():
+            self.nvim.err_write("No review is currently active.\n")
+            return
+        order, args = self.comment_order(args)
+        if order is None:
+            return
+        if sign and (not args or args[0] == 'email'):
+            self.nvim.err_write("Only reviews exported to a file can be signed.\n")
+            return
+        if args and args[0] == 'email':
+            self.export_reply_emails(args[1:])
+            return
+        if args and args[0] == 'audit':
+            self.export_audit(args[1:], sign)
+            return
+        if self.review.patch_file:
+            markdown = self.review.to_email_reply()
+        else:
+            markdown = self.review.to_markdown(order)
+        if args:
+            path = os.path.expanduser(args[0])
+            with open(path, 'w', encoding='utf-8') as f:
+                f.write(markdown)
+            self.info(f"Exported review to {path}{self.sign_export(path) if sign else ''}.\n")
+            return
+        self.nvim.command('new')
+        self.nvim.command('setlocal buftype=nofile bufhidden=hide noswapfile filetype=markdown')
+        self.nvim.current.buffer[:] = markdown.rstrip('\n').split('\n')
+
+    def export_digest(self, args: List[str], sign: bool = False):
+        """
+        Export a learning digest: the comments labelled with one of
+        `g:reviewer_teaching_labels` (`pattern`, `idiom` and `tooling` by
+        default) across the reviews saved in this repository, grouped by
+        label, to `path` or into a new buffer. `--author=<login>` limits it
+        to the reviews of that user's PRs.
+        """
+        author = next((arg[len('--author='):] for arg in args if arg.startswith('--author=')), None)
+        paths = [arg for arg in args if not arg.startswith('--author=')]
+        if len(paths) > 1 or (sign and not paths):
+            self.nvim.err_write("Usage: ExportReview digest [--author=<login>] [--sign] [path]\n")
+            return
+        if not self.nvim.call('FugitiveGitDir'):
+            self.nvim.err_write("Not in a git repository.\n")
+            return
+        teaching_labels = self.nvim.vars.get('reviewer_teaching_labels', offline_pr_review.DEFAULT_TEACHING_LABELS)
+        reviews = offline_pr_review.list_digest_reviews(author)
+        digest = offline_pr_review.learning_digest(reviews, teaching_labels)
+        if digest is None:
+            self.nvim.err_write(f"No comments are labelled {', '.join(teaching_labels)} in these reviews.\n")
+            return
+        if paths:
+            path = os.path.expanduser(paths[0])
+            with open(path, 'w', encoding='utf-8') as f:
+                f.write(digest)
+            self.info(f"Exported the learning digest to {path}{self.sign_export(path) if sign else ''}.\n")
+            return
+        self.nvim.command('new')
+        self.nvim.command('setlocal buftype=nofile bufhidden=hide noswapfile filetype=markdown')
+        self.nvim.current.buffer[:] = digest.rstrip('\n').split('\n')
+
+    def sign_export(self, path: str) -> str:
+        """
+        Sign an exported file, returning how to mention the signature in the
+        export's message, or reporting why it couldn't be signed.
+        """
+        try:
+            return f", signed in {offline_pr_review.sign_file(path)}"
+        except ValueError as e:
+            self.nvim.err_write(f"{e}\n")
+            return " (unsigned)"
+
+    def export_audit(self, args: List[str], sign: bool = False):
+        if len(args) != 1:
+            self.nvim.err_write("Usage: ExportReview audit <path>\n")
+            return
+        try:
+            reviewer = offline_pr_review.get_git_identity()
+        except subprocess.CalledProcessError:
+            self.nvim.err_write("Set git's user.name and user.email to record who wrote the review.\n")
+            return
+        path = os.path.expanduser(args[0])
+        records = self.review.audit_records(reviewer)
+        try:
+            offline_pr_review.write_audit_export(path, records)
+        except OSError as e:
+            self.nvim.err_write(f"Could not write the audit export to {path}: {e.strerror or e}\n")
+            return
+        signed = self.sign_export(path) if sign else ''
+        self.info(f"Exported {len(records)} audit records to {path}{signed}.\n")
+
+    def export_reply_emails(self, args: List[str]):
+        if not self.review.patch_file:
+            self.nvim.err_write("Only reviews of patch series can be exported as emails.\n")
+            return
+        if not args or args[1:] not in ([], ['reviewed-by']):
+            self.nvim.err_write("Usage: ExportReview email <directory> [reviewed-by]\n")
+            return
+        directory = os.path.expanduser(args[0])
+        count = self.write_reply_emails(directory, reviewed_by=args[1:] == ['reviewed-by'])
+        if count is not None:
+            self.info(
+                f"Wrote {count} reply emails to {directory}. Send them with `git send-email {directory}`.\n"
+            )
+
+    def write_reply_emails(
+        self, directory: str, reviewed_by: bool = False, include_held: bool = True, unsent_only: bool = False
+    ) -> Optional[int]:
+        """
+        Write the reply emails of the patch review to `directory`, returning
+        how many there are, or None if the user's git identity isn't set.
+        """
+        try:
+            sender = offline_pr_review.get_git_identity()
+        except subprocess.CalledProcessError:
+            self.nvim.err_write("Set git's user.name and user.email to export reply emails.\n")
+            return None
+        os.makedirs(directory, exist_ok=True)
+        emails = self.review.to_reply_emails(
+            sender, reviewed_by=reviewed_by, include_held=include_held, unsent_only=unsent_only
+        )
+        for file_name, email in emails:
+            with open(os.path.join(directory, file_name), 'w', encoding='utf-8') as f:
+                f.write(email)
+        return len(emails)
+
+    def send_patch_replies(self, include_held: bool = False):
+        """
+        Publish a patch review of a sourcehut project the way sourcehut
+        reviews happen: as replies on the mailing list. The replies are
+        written next to the review file and sent with `git send-email` in a
+        terminal, which asks before sending each one. Like other reviews,
+        held comments are left out unless `include_held` is set, and comments
+        already sent aren't sent again; they are marked as published once
+        `git send-email` succeeds.
+        """
+        sent = [comment.id for comment in self.review.publishable_comments(include_held)]
+        if not sent:
+            self.nvim.err_write("The review has no comments left to send.\n")
+            return
+        directory = os.path.join(
+            os.path.dirname(self.review.file_path()), 'outgoing', os.path.basename(self.review.patch_file)
+        )
+        for stale in os.listdir(directory) if os.path.isdir(directory) else []:
+            os.remove(os.path.join(directory, stale))
+        count = self.write_reply_emails(directory, include_held=include_held, unsent_only=True)
+        if count is None:
+            return
+        if count == 0:
+            self.nvim.err_write("None of the comments left to send could be placed in the patches.\n")
+            return
+        self.sent_patch_replies = (self.review.file_path(), sent, include_held)
+        self.nvim.command('botright new')
+        self.nvim.exec_lua(SEND_EMAIL_LUA, directory)
+        self.nvim.command('startinsert')
+
+    @pynvim.function('ReviewerPatchRepliesSent', sync=True)
+    def patch_replies_sent(self, args):
+        """
+        Mark the comments `send_patch_replies` handed to `git send-email` as
+        published, once it succeeded.
+        """
+        if self.sent_patch_replies is None:
+            return
+        file_path, sent, include_held = self.sent_patch_replies
+        self.sent_patch_replies = None
+        if not self.is_review_active() or self.review.file_path() != file_path:
+            return
+        try:
+            self.review.mark_published(include_held, sent)
+        except ValueError as e:
+            self.nvim.err_write(f"Could not save the review: {e}\n")
+            return
+        self.info(f"Sent {len(sent)} comments to the mailing list.\n")
+
+    def comment_file_lines(self, path: str, side: offline_pr_review.Side) -> List[str]:
+        """
+        Return the lines of the file a comment on `path` and `side` refers to,
+        or an empty list if it can't be read.
+        """
+        if side == 'LEFT' or self.review.remote_only:
+            if self.review.remote_only:
+                contents = self.review.fetch_file_contents(path, side)
+            else:
+                contents = offline_pr_review.get_file_at_revision(self.review.base_sha(), path)
+            return contents.split('\n') if contents is not None else []
+        try:
+            with open(self.worktree_filename(path), encoding='utf-8', errors='replace') as f:
+                return f.read().split('\n')
+        except OSError:
+            return []
+
+    def comment_filename(self, path: str, side: offline_pr_review.Side) -> str:
+        """
+        Return the name of the buffer showing the file a comment on `path`
+        and `side` refers to.
+        """
+        if self.review.remote_only:
+            return f'reviewer://{side}/{path}'
+        if side == 'LEFT':
+            return self.nvim.call('FugitiveFind', f'{self.review.base_sha()}:{path}')
+        return self.worktree_filename(path)
+
+    def comment_quickfix_entries(self, comments: List[offline_pr_review.Comment]) -> List[dict]:
+        """
+        Return quickfix entries for `comments`, spanning each comment's line
+        range and quoting the first commented line of code.
+        """
+        file_lines = {}
+        filenames = {}
+        entries = []
+        for c in comments:
+            key = (c.path, c.side)
+            if key not in file_lines:
+                file_lines[key] = self.comment_file_lines(c.path, c.side)
+                filenames[key] = self.comment_filename(c.path, c.side)
+            start_line = c.start_line or c.line
+            code = c.quote(file_lines[key])
+            line_range = f'L{start_line}-{c.line}' if start_line != c.line else f'L{c.line}'
+            index = self.review.comment_number(c)
+            entries.append({
+                'filename': filenames[key],
+                'lnum': start_line,
+                'end_lnum': c.line,
+                'col': c.start_column or 1,
+                'end_col': c.end_column + 1 if c.end_column is not None else 0,
+                'text': f'#{index} {line_range}{" (outdated)" if c.outdated else ""}{"" if c.ready else " (held)"}'
+                        f' `{code}`: {c.body[:MAX_QF_BODY_LENGTH]}',
+                'user_data': {'comment_id': c.id},
+            })
+        return entries
+
+    def comment_order(self, args: List[str]) -> Tuple[Optional[str], List[str]]:
+        """
+        Extract the `--order=<order>` option from `args`, defaulting to
+        `g:reviewer_comment_order` (or `file`). Returns the order, or None
+        after reporting an error if it is invalid, and the remaining args.
+        """
+        order = self.nvim.vars.get('reviewer_comment_order', 'file')
+        remaining = []
+        for arg in args:
+            if arg.startswith('--order='):
+                order = arg[len('--order='):]
+            else:
+                remaining.append(arg)
+        if order not in offline_pr_review.COMMENT_ORDERS:
+            self.nvim.err_write(f"Invalid comment order {order!r}; use one of {', '.join(offline_pr_review.COMMENT_ORDERS)}.\n")
+            return None, remaining
+        return order, remaining
+
+    @pynvim.command('QuickfixAllComments', nargs='?', complete='customlist,ReviewerCompleteCommentOrder')
+    def quickfix_all_comments(self, args):
+        """
+        Load all comments into the quickfix list, by file and line, or in the
+        order given with `--order=`: `severity`, `created` or `published`.
+        """
+        order, _ = self.comment_order(args)
+        if order is None:
+            return
+        self.nvim.call("setqflist", [], ' ', {
+            'title': f'{self.review.title} comments',
+            'items': self.comment_quickfix_entries(self.review.sorted_comments(order)),
+        })
+
+    @pynvim.function('ReviewerCompleteExport', sync=True)
+    def complete_export(self, args):
+        """
+        Complete `:ExportReview`: the `--order=` options, `audit`, `digest`,
+        `--sign`, `email` for patch reviews, and file names.
+        """
+        arg_lead, command_line = args[0], args[1]
+        options = [f'--order={order}' for order in offline_pr_review.COMMENT_ORDERS] + ['audit', 'digest', '--sign']
+        if self.review_active and self.review.patch_file:
+            options.append('reviewed-by' if 'email' in command_line.split() else 'email')
+        matches = [option for option in options if option.startswith(arg_lead)]
+        return matches + self.nvim.call('getcompletion', arg_lead, 'file')
+
+    @pynvim.function('ReviewerCompleteCommentOrder', sync=True)
+    def complete_comment_order(self, args):
+        options = [f'--order={order}' for order in offline_pr_review.COMMENT_ORDERS]
+        return [option for option in options if option.startswith(args[0])]
+
+    @pynvim.command('GotoComment', nargs=1, bang=True, complete='customlist,ReviewerCompleteComment', sync=True)
+    def goto_comment(self, args, bang):
+        """
+        Open the file of the comment with the given number (as shown in the
+        quickfix list) or ID at the comment's first line. With a bang, also
+        show the comment's body.
+        """
+        if not self.is_review_active():
+            self.nvim.err_write("No review is currently active.\n")
+            return
+        comment = self.find_comment(args[0])
+        if comment is None:
+            self.nvim.err_write(f"No comment {args[0]} in this review.\n")
+            return
+
+        start_line = comment.start_line or comment.line
+        if self.review.patch_file:
+            source_line = self.review.comment_source_line(self.patches, comment)
+            self.nvim.command(f'edit {self.nvim.call("fnameescape", self.review.patch_file)}')
+            if source_line is not None:
+                self.nvim.current.window.cursor = (source_line, 0)
+        elif self.review.remote_only:
+            if not self.open_remote_file(comment.path, comment.side):
+                return
+            self.nvim.current.window.cursor = (start_line, 0)
+        else:
+            filename = self.comment_filename(comment.path, comment.side)
+            self.nvim.command(f'edit {self.nvim.call("fnameescape", filename)}')
+            self.nvim.current.window.cursor = (start_line, 0)
+        if bang:
+            self.nvim.out_write(f'#{self.review.comment_number(comment)} by {comment.describe_origin()}:\n{comment.body}\n')
+
+    @pynvim.function('ReviewerCompleteComment', sync=True)
+    def complete_comment(self, args):
+        """
+        Complete the numbers of the comments of the active review, then their
+        IDs.
+        """
+        if not self.review_active:
+            return []
+        numbers = [str(number) for number in range(1, len(self.review.comments) + 1)]
+        ids = [comment.id for comment in self.review.comments]
+        return [candidate for candidate in numbers + ids if candidate.startswith(args[0])]
+
+    def find_comment(self, reference: str) -> Optional[offline_pr_review.Comment]:
+        """
+        Return the comment of the active review with the given number (`3` or
+        `#3`) or ID, if any.
+        """
+        number = reference.lstrip('#')
+        if number.isdigit() and 1 <= int(number) <= len(self.review.comments):
+            return self.review.comments[int(number) - 1]
+        return self.review.find_comment(reference)
+
+    @pynvim.command('SearchComments', nargs=1, sync=True)
+    def search_comments(self, args):
+        """
+        Search the bodies of the draft comments, and of the comments already
+        posted on the PR, for a (Python) regular expression, e.g.
+        `:SearchComments /retry/`. Matches are loaded into the quickfix list.
+        """
+        if not self.is_review_active():
+            self.nvim.err_write("No review is currently active.\n")
+            return
+        pattern = args[0]
+        if len(pattern) > 1 and pattern.startswith('/') and pattern.endswith('/'):
+            pattern = pattern[1:-1]
+        try:
+            regex = re.compile(pattern)
+        except re.error as e:
+            self.nvim.err_write(f"Invalid pattern: {e}\n")
+            return
+
+        entries = self.comment_quickfix_entries([c for c in self.review.comments if regex.search(c.body)])
+        if not self.review.local_only:
+            try:
+                remote_comments = self.review.fetch_remote_comments()
+            except requests.RequestException as e:
+                self.nvim.err_write(f"Could not fetch the comments posted on the PR: {e}\n")
+                remote_comments = []
+            filenames = {}
+            for remote_comment in map(offline_pr_review.Comment.from_remote_json, remote_comments):
+                if not regex.search(remote_comment.body):
+                    continue
+                key = (remote_comment.path, remote_comment.side)
+                if key not in filenames:
+                    filenames[key] = self.comment_filename(*key)
+                entries.append({
+                    'filename': filenames[key],
+                    'lnum': remote_comment.start_line or remote_comment.line,
+                    'end_lnum': remote_comment.line,
+                    'col': 1,
+                    'text': f"{remote_comment.describe_origin()}: {remote_comment.body[:MAX_QF_BODY_LENGTH]}",
+                })
+
+        self.nvim.call("setqflist", [], ' ', {
+            'title': f'Comments matching /{pattern}/',
+            'items': entries,
+        })
+        self.info(f"{len(entries)} comments match /{pattern}/.\n")
+
+    @pynvim.command('LoclistFileComments', sync=True)
+    def loclist_file_comments(self):
+        """
+        Fill the current window's location list with the comments on the file
+        (and diff side) it shows.
+        """
+        if not self.is_review_active():
+            self.nvim.err_write("No review is currently active.\n")
+            return
+        location = self.current_buffer_location()
+        if location is None:
+            self.nvim.err_write("Current buffer is not a valid path in the git repository.\n")
+            return
+        path, side = location
+        comments = sorted(
+            (c for c in self.review.comments if c.path == path and c.side == side),
+            key=lambda c: c.start_line or c.line,
+        )
+        entries = self.comment_quickfix_entries(comments)
+        for entry in entries:
+            # Point at the buffer in this window, whichever plugin named it.
+            entry['bufnr'] = self.nvim.current.buffer.handle
+            del entry['filename']
+        self.nvim.call("setloclist", 0, [], ' ', {
+            'title': f'Comments on {path}',
+            'items': entries,
+        })
@@ -0,0 +1,18 @@
+" Comment buffers of vim-reviewer: a metadata header, a `---` line, then the
+" comment body in Markdown.
+if exists('b:current_syntax')
+  finish
+endif
+
+runtime! syntax/markdown.vim
+unlet! b:current_syntax
+
+syntax match reviewerCommentHeaderKey /^\%(Path\|Lines\|Severity\|Labels\|Ready\|Written\):/ contained
+syntax match reviewerCommentHeaderLine /^\%(Path\|Lines\|Severity\|Labels\|Ready\|Written\):.*$/ contains=reviewerCommentHeaderKey
+syntax match reviewerCommentSeparator /^---$/
+
+highlight default link reviewerCommentHeaderKey Identifier
+highlight default link reviewerCommentHeaderLine Comment
+highlight default link reviewerCommentSeparator Delimiter
+
+let b:current_syntax = 'reviewer-comment'